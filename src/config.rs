@@ -0,0 +1,553 @@
+//! # Config
+//! Settings stored in TOML, read from three files where each one overrides the ones before:
+//! the system file `/etc/goldfishconfig` (or the GOLDFISH_CONFIG_SYSTEM environment variable),
+//! the user file `~/.goldfishconfig`, and the repository file `.goldfish/config`. A setting is
+//! named by its dotted key: `diff.context` is `context` in the `[diff]` table, and
+//! `remote.origin.url` is `url` in the `[remote.origin]` table:
+//!
+//! ```toml
+//! [user]
+//! name = "Goldie"
+//! email = "goldie@tank.org"
+//!
+//! [diff]
+//! context = 5
+//!
+//! [remote.origin]
+//! url = "fish@tank.org:repos/goldfish"
+//! fetch = ["branches/*:remotes/origin/*"]
+//!
+//! [branch.main]
+//! remote = "origin"
+//! merge = "main"
+//! ```
+//!
+//! A remote names the url of another repository, and its fetch refspecs tell which of its
+//! branches are kept as which remote-tracking branches. The `[branch.<name>]` section records
+//! the upstream of a branch: the remote (and branch of that remote) it pulls from and pushes to
+use crate::error::GoldfishError;
+use crate::filesystem;
+use crate::model;
+use std::collections::BTreeMap;
+use toml::value::{Table, Value};
+
+const REMOTE_SECTION: &str = "remote";
+const BRANCH_SECTION: &str = "branch";
+
+const SYSTEM_CONFIG_PATH: &str = "/etc/goldfishconfig";
+const GLOBAL_CONFIG_FILE: &str = ".goldfishconfig";
+
+// identity recorded in new commits
+pub const USER_NAME: &str = "user.name";
+pub const USER_EMAIL: &str = "user.email";
+// number of unchanged lines shown around each change by diff
+pub const DIFF_CONTEXT: &str = "diff.context";
+pub const DEFAULT_DIFF_CONTEXT: usize = 3;
+// `auto` (colour when printing to a terminal), `always` or `never`
+pub const COLOR_UI: &str = "color.ui";
+pub const DEFAULT_COLOR_UI: &str = "auto";
+// length of the `<<<` and `>>>` lines around a conflict, the `===` line is twice as long
+pub const MERGE_MARKER_SIZE: &str = "merge.markerSize";
+pub const DEFAULT_MERGE_MARKER_SIZE: usize = 10;
+// check that every object read hashes to its id (slower, fsck always does)
+pub const VERIFY_OBJECTS: &str = "core.verifyObjects";
+// number of days an unreachable object is kept by gc, so objects of an ongoing command are not deleted
+pub const GC_GRACE_PERIOD: &str = "gc.gracePeriod";
+pub const DEFAULT_GC_GRACE_PERIOD: usize = 14;
+
+pub fn get_system_config_path() -> String {
+    std::env::var("GOLDFISH_CONFIG_SYSTEM").unwrap_or_else(|_| String::from(SYSTEM_CONFIG_PATH))
+}
+
+pub fn get_global_config_path() -> String {
+    let home = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE")).unwrap_or_default();
+    filesystem::join_path(vec![home.as_str(), GLOBAL_CONFIG_FILE])
+}
+
+fn split_key(key: &str) -> Result<Vec<&str>, GoldfishError> {
+    //! Split `{section}.{name}` or `{section}.{subsection}.{name}` into the path of tables
+    //! leading to the setting. The subsection may contain dots, like a branch named `v1.0`
+    let invalid_key = || GoldfishError::Config(format!("Invalid key `{}`, expect section.name", key));
+    let (section, rest) = key.split_once('.').ok_or_else(invalid_key)?;
+    let parts = match rest.rsplit_once('.') {
+        Some((subsection, name)) => vec![section, subsection, name],
+        None => vec![section, rest],
+    };
+    if parts.iter().any(|part| part.is_empty()) {
+        return Err(invalid_key());
+    }
+    Ok(parts)
+}
+
+pub fn parse_value(raw: &str) -> Value {
+    //! Read a value given on the command line: a boolean, an integer or else a string
+    match raw {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        _ => match raw.parse::<i64>() {
+            Ok(number) => Value::Integer(number),
+            Err(_) => Value::String(raw.to_string()),
+        },
+    }
+}
+
+pub fn format_value(value: &Value) -> String {
+    //! Strings as they are, other values in TOML syntax
+    match value {
+        Value::String(string) => string.to_owned(),
+        _ => value.to_string(),
+    }
+}
+
+/**
+ * A type that settings can be read as
+ */
+pub trait ConfigValue: Sized {
+    const TYPE_NAME: &'static str;
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+impl ConfigValue for String {
+    const TYPE_NAME: &'static str = "a string";
+    fn from_value(value: &Value) -> Option<String> {
+        match value {
+            Value::Table(_) | Value::Array(_) => None,
+            _ => Some(format_value(value)),
+        }
+    }
+}
+
+impl ConfigValue for bool {
+    const TYPE_NAME: &'static str = "true or false";
+    fn from_value(value: &Value) -> Option<bool> {
+        match value {
+            Value::Boolean(boolean) => Some(*boolean),
+            Value::String(string) => match string.as_str() {
+                "true" | "yes" | "on" => Some(true),
+                "false" | "no" | "off" => Some(false),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl ConfigValue for usize {
+    const TYPE_NAME: &'static str = "a non-negative integer";
+    fn from_value(value: &Value) -> Option<usize> {
+        match value {
+            Value::Integer(number) => usize::try_from(*number).ok(),
+            Value::String(string) => string.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * A `{source}:{destination}` mapping from the branches of a remote to remote-tracking branches,
+ * where each side may contain one `*` matching the rest of the branch name (see the default
+ * refspec of `origin` at the top of this file)
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Refspec {
+    source: String,
+    destination: String,
+}
+
+impl Refspec {
+    pub fn parse(raw: &str) -> Result<Refspec, GoldfishError> {
+        let (source, destination) = raw
+            .split_once(':')
+            .ok_or_else(|| GoldfishError::Config(format!("Invalid refspec `{}`, expect source:destination", raw)))?;
+        let globs = (source.matches('*').count(), destination.matches('*').count());
+        if !source.starts_with("branches/") || !destination.starts_with("remotes/") || !(globs == (0, 0) || globs == (1, 1)) {
+            return Err(GoldfishError::Config(format!(
+                "Invalid refspec `{}`, expect branches/[pattern]:remotes/[remote]/[pattern]",
+                raw
+            )));
+        }
+        Ok(Refspec { source: source.to_string(), destination: destination.to_string() })
+    }
+
+    fn apply(pattern: &str, replacement: &str, name: &str) -> Option<String> {
+        match pattern.split_once('*') {
+            Some((prefix, suffix)) => {
+                let matched = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+                Some(replacement.replacen('*', matched, 1))
+            }
+            None if pattern == name => Some(replacement.to_string()),
+            None => None,
+        }
+    }
+
+    pub fn map_branch(&self, branch: &str) -> Option<(String, String)> {
+        //! Return the (remote, branch) of the remote-tracking branch for a branch of the remote
+        let destination = Refspec::apply(&self.source, &self.destination, format!("branches/{}", branch).as_str())?;
+        let (remote, tracking_branch) = destination.strip_prefix("remotes/")?.split_once('/')?;
+        if !model::is_valid_remote_name(remote) || !model::is_valid_branch_name(tracking_branch) {
+            return None
+        }
+        Some((remote.to_string(), tracking_branch.to_string()))
+    }
+
+    pub fn unmap_branch(&self, remote: &str, tracking_branch: &str) -> Option<String> {
+        //! Return the branch of the remote that a remote-tracking branch is kept for
+        let source = Refspec::apply(&self.destination, &self.source, format!("remotes/{}/{}", remote, tracking_branch).as_str())?;
+        source.strip_prefix("branches/").map(|branch| branch.to_string())
+    }
+
+    pub fn get_destination_remote(&self) -> Option<&str> {
+        //! The remote whose remote-tracking branches this refspec writes
+        Some(self.destination.strip_prefix("remotes/")?.split_once('/')?.0)
+    }
+
+    fn serialize(&self) -> String {
+        format!("{}:{}", self.source, self.destination)
+    }
+}
+
+/**
+ * A named url of another repository
+ */
+#[derive(Debug, Clone)]
+pub struct Remote {
+    name: String,
+    url: String,
+    fetch: Vec<Refspec>,
+}
+
+impl Remote {
+    pub fn new(name: &str, url: &str) -> Remote {
+        //! A remote keeping every branch as `{name}/{branch}`
+        Remote {
+            name: name.to_string(),
+            url: url.to_string(),
+            fetch: vec![Refspec {
+                source: String::from("branches/*"),
+                destination: format!("remotes/{}/*", name),
+            }],
+        }
+    }
+
+    pub fn anonymous(url: &str) -> Remote {
+        //! An url given on the command line, which has no remote-tracking branches
+        Remote { name: String::new(), url: url.to_string(), fetch: vec![] }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn get_fetch_refspecs(&self) -> &Vec<Refspec> {
+        &self.fetch
+    }
+
+    pub fn set_url(&mut self, url: &str) {
+        self.url = url.to_string();
+    }
+
+    pub fn map_branch(&self, branch: &str) -> Option<(String, String)> {
+        //! The remote-tracking branch (remote, branch) a branch of this remote is kept as
+        self.fetch.iter().find_map(|refspec| refspec.map_branch(branch))
+    }
+}
+
+/**
+ * The content of a TOML config file
+ */
+#[derive(Debug)]
+pub struct Config {
+    path: String,
+    table: Table,
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config, GoldfishError> {
+        //! Read the config file at the path, which is empty if the file does not exist
+        if !filesystem::is_file(path) {
+            return Ok(Config { path: path.to_string(), table: Table::new() })
+        }
+        let content = filesystem::read_file(path).map_err(|e| GoldfishError::io(format!("Fail to read {}", path), e))?;
+        match content.parse::<Value>() {
+            Ok(Value::Table(table)) => Ok(Config { path: path.to_string(), table }),
+            Ok(_) => Err(GoldfishError::Config(format!("Invalid config file {}", path))),
+            Err(e) => Err(GoldfishError::Config(format!("Invalid config file {}: {}", path, e))),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), GoldfishError> {
+        let content = toml::to_string(&self.table).map_err(|e| GoldfishError::Config(format!("Fail to write config: {}", e)))?;
+        filesystem::write_file(content, &self.path).map_err(|e| GoldfishError::io(format!("Fail to save {}", self.path), e))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        let parts = split_key(key).ok()?;
+        let mut value = self.table.get(parts[0])?;
+        for part in &parts[1..] {
+            value = value.as_table()?.get(*part)?;
+        }
+        Some(value)
+    }
+
+    pub fn set(&mut self, key: &str, value: Value) -> Result<(), GoldfishError> {
+        let parts = split_key(key)?;
+        let mut table = &mut self.table;
+        for part in &parts[..parts.len() - 1] {
+            let entry = table.entry(part.to_string()).or_insert_with(|| Value::Table(Table::new()));
+            table = match entry {
+                Value::Table(inner) => inner,
+                _ => return Err(GoldfishError::Config(format!("Cannot set {}, {} is not a table", key, part))),
+            };
+        }
+        table.insert(parts[parts.len() - 1].to_string(), value);
+        Ok(())
+    }
+
+    pub fn unset(&mut self, key: &str) -> bool {
+        //! Remove a setting, and the tables it leaves empty. Return false if it was not set
+        fn remove(table: &mut Table, parts: &[&str]) -> bool {
+            if parts.len() == 1 {
+                return table.remove(parts[0]).is_some()
+            }
+            let removed = match table.get_mut(parts[0]) {
+                Some(Value::Table(inner)) => remove(inner, &parts[1..]),
+                _ => false,
+            };
+            if table.get(parts[0]).and_then(|value| value.as_table()).is_some_and(|inner| inner.is_empty()) {
+                table.remove(parts[0]);
+            }
+            removed
+        }
+        match split_key(key) {
+            Ok(parts) => remove(&mut self.table, &parts),
+            Err(_) => false,
+        }
+    }
+
+    pub fn list(&self) -> Vec<(String, Value)> {
+        //! Return every setting with its dotted key
+        fn flatten(prefix: &str, table: &Table, result: &mut Vec<(String, Value)>) {
+            for (name, value) in table {
+                let key = if prefix.is_empty() { name.to_owned() } else { format!("{}.{}", prefix, name) };
+                match value {
+                    Value::Table(inner) => flatten(key.as_str(), inner, result),
+                    _ => result.push((key, value.clone())),
+                }
+            }
+        }
+        let mut result = vec![];
+        flatten("", &self.table, &mut result);
+        result
+    }
+
+    fn get_section(&self, section: &str, name: &str) -> Option<&Table> {
+        //! The `[{section}.{name}]` table
+        self.table.get(section)?.as_table()?.get(name)?.as_table()
+    }
+
+    fn set_section(&mut self, section: &str, name: &str, values: Table) {
+        let sections = self.table.entry(section).or_insert_with(|| Value::Table(Table::new()));
+        if !sections.is_table() {
+            *sections = Value::Table(Table::new());
+        }
+        if let Value::Table(sections) = sections {
+            sections.insert(name.to_string(), Value::Table(values));
+        }
+    }
+
+    fn remove_section(&mut self, section: &str, name: &str) -> bool {
+        let removed = match self.table.get_mut(section) {
+            Some(Value::Table(sections)) => sections.remove(name).is_some(),
+            _ => false,
+        };
+        if self.table.get(section).and_then(|sections| sections.as_table()).is_some_and(|sections| sections.is_empty()) {
+            self.table.remove(section);
+        }
+        removed
+    }
+}
+
+// Interacting with remotes and upstreams
+impl Config {
+    pub fn get_remote(&self, name: &str) -> Option<Remote> {
+        let section = self.get_section(REMOTE_SECTION, name)?;
+        let url = section.get("url")?.as_str()?;
+        let fetch = match section.get("fetch") {
+            Some(Value::Array(refspecs)) => refspecs.iter()
+                .filter_map(|refspec| Refspec::parse(refspec.as_str()?).ok())
+                .collect(),
+            _ => vec![],
+        };
+        Some(Remote { name: name.to_string(), url: url.to_string(), fetch })
+    }
+
+    pub fn list_remotes(&self) -> Vec<Remote> {
+        //! Return every remote, sorted by name
+        let names: Vec<String> = match self.table.get(REMOTE_SECTION).and_then(|sections| sections.as_table()) {
+            Some(sections) => sections.keys().cloned().collect(),
+            None => vec![],
+        };
+        let mut remotes: Vec<Remote> = names.iter().filter_map(|name| self.get_remote(name)).collect();
+        remotes.sort_by(|a, b| a.name.cmp(&b.name));
+        remotes
+    }
+
+    pub fn set_remote(&mut self, remote: &Remote) {
+        let mut values = Table::new();
+        values.insert(String::from("url"), Value::String(remote.url.to_owned()));
+        values.insert(
+            String::from("fetch"),
+            Value::Array(remote.fetch.iter().map(|refspec| Value::String(refspec.serialize())).collect()),
+        );
+        self.set_section(REMOTE_SECTION, remote.name.as_str(), values);
+    }
+
+    pub fn remove_remote(&mut self, name: &str) -> bool {
+        //! Remove a remote and the upstreams using it. Return false if there is no such remote
+        if !self.remove_section(REMOTE_SECTION, name) {
+            return false
+        }
+        let branches: Vec<String> = match self.table.get(BRANCH_SECTION).and_then(|sections| sections.as_table()) {
+            Some(sections) => sections.keys().cloned().collect(),
+            None => vec![],
+        };
+        for branch in branches {
+            if self.get_upstream(branch.as_str()).is_some_and(|(remote, _)| remote == name) {
+                self.remove_section(BRANCH_SECTION, branch.as_str());
+            }
+        }
+        true
+    }
+
+    pub fn get_upstream(&self, branch: &str) -> Option<(String, String)> {
+        //! The (remote, branch of the remote) that a local branch pulls from and pushes to
+        let section = self.get_section(BRANCH_SECTION, branch)?;
+        let remote = section.get("remote")?.as_str()?;
+        let merge = section.get("merge")?.as_str()?;
+        Some((remote.to_string(), merge.to_string()))
+    }
+
+    pub fn set_upstream(&mut self, branch: &str, remote: &str, remote_branch: &str) {
+        let mut values = Table::new();
+        values.insert(String::from("remote"), Value::String(remote.to_string()));
+        values.insert(String::from("merge"), Value::String(remote_branch.to_string()));
+        self.set_section(BRANCH_SECTION, branch, values);
+    }
+}
+
+/**
+ * The settings of every config file, the repository file overriding the user file,
+ * which overrides the system file
+ */
+#[derive(Debug)]
+pub struct Settings {
+    // from lowest to highest priority
+    layers: Vec<Config>,
+}
+
+impl Settings {
+    pub fn load(repo_config_path: Option<&str>) -> Result<Settings, GoldfishError> {
+        //! Read the system and user config files, and the repository one if there is a repository
+        let mut paths = vec![get_system_config_path(), get_global_config_path()];
+        paths.extend(repo_config_path.map(|path| path.to_string()));
+        let mut layers = vec![];
+        for path in paths {
+            layers.push(Config::load(path.as_str())?);
+        }
+        Ok(Settings { layers })
+    }
+
+    pub fn get_value(&self, key: &str) -> Option<&Value> {
+        self.layers.iter().rev().find_map(|layer| layer.get(key))
+    }
+
+    pub fn get<T: ConfigValue>(&self, key: &str) -> Result<Option<T>, GoldfishError> {
+        //! Read a setting as the given type, which is an error if it is set to something else
+        match self.get_value(key) {
+            Some(value) => match T::from_value(value) {
+                Some(typed) => Ok(Some(typed)),
+                None => Err(GoldfishError::Config(format!("The setting {} must be {}, not {}", key, T::TYPE_NAME, value))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn list(&self) -> Vec<(String, Value)> {
+        //! Every setting in effect, sorted by key
+        let mut settings: BTreeMap<String, Value> = BTreeMap::new();
+        for layer in &self.layers {
+            settings.extend(layer.list());
+        }
+        settings.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{parse_value, Config, Remote, Settings, Table};
+    use crate::filesystem;
+
+    #[test]
+    fn test_1_settings_layers() {
+        //! Check that a later config file overrides an earlier one, and that settings are read as their type
+        let mut global = Config { path: String::new(), table: Table::new() };
+        global.set("user.name", parse_value("Goldie")).unwrap();
+        global.set("diff.context", parse_value("5")).unwrap();
+        let mut local = Config { path: String::new(), table: Table::new() };
+        local.set("diff.context", parse_value("1")).unwrap();
+        local.set("branch.v1.0.remote", parse_value("origin")).unwrap();
+        local.set("color.ui", parse_value("sometimes")).unwrap();
+        let settings = Settings { layers: vec![global, local] };
+        assert_eq!(Some("Goldie".to_string()), settings.get::<String>("user.name").unwrap());
+        assert_eq!(Some(1), settings.get::<usize>("diff.context").unwrap());
+        assert_eq!(Some("origin".to_string()), settings.get::<String>("branch.v1.0.remote").unwrap());
+        assert!(settings.get::<bool>("color.ui").is_err());
+        assert_eq!(None, settings.get::<usize>("merge.markerSize").unwrap());
+        assert_eq!(4, settings.list().len());
+    }
+
+    #[test]
+    fn test_2_remotes() {
+        //! Check that remotes and upstreams are added, changed and removed through the TOML file
+        let path = filesystem::pathbuf_to_string(std::env::temp_dir().join(format!("goldfish-test-{}-config", std::process::id())));
+        filesystem::remove(path.as_str()).ok();
+        let mut config = Config::load(path.as_str()).unwrap();
+        config.set_remote(&Remote::new("origin", "fish@tank.org:repos/goldfish"));
+        config.set_remote(&Remote::new("backup", "fish@pond.org:goldfish"));
+        config.set_upstream("main", "origin", "trunk");
+        config.save().unwrap();
+        let content = filesystem::read_file(path.as_str()).unwrap();
+        assert!(content.contains("[remote.origin]"), "{}", content);
+        assert!(content.contains("[branch.main]"), "{}", content);
+
+        let mut config = Config::load(path.as_str()).unwrap();
+        let names: Vec<String> = config.list_remotes().iter().map(|remote| remote.get_name().to_string()).collect();
+        assert_eq!(vec!["backup", "origin"], names);
+        let mut origin = config.get_remote("origin").unwrap();
+        assert_eq!("fish@tank.org:repos/goldfish", origin.get_url());
+        assert_eq!(Some((String::from("origin"), String::from("trunk"))), origin.map_branch("trunk"));
+        assert_eq!(Some((String::from("origin"), String::from("trunk"))), config.get_upstream("main"));
+        assert_eq!(None, config.get_upstream("topic"));
+        origin.set_url("fish@lake.org:goldfish");
+        config.set_remote(&origin);
+        config.save().unwrap();
+
+        let mut config = Config::load(path.as_str()).unwrap();
+        assert_eq!("fish@lake.org:goldfish", config.get_remote("origin").unwrap().get_url());
+        assert_eq!(1, config.get_remote("origin").unwrap().get_fetch_refspecs().len());
+        assert!(config.remove_remote("origin"));
+        assert!(!config.remove_remote("origin"));
+        config.save().unwrap();
+
+        // removing a remote also removes the upstreams that use it
+        let config = Config::load(path.as_str()).unwrap();
+        assert!(config.get_remote("origin").is_none());
+        assert_eq!(None, config.get_upstream("main"));
+        assert_eq!(1, config.list_remotes().len());
+        filesystem::remove(path.as_str()).unwrap();
+    }
+}
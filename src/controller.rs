@@ -1,1101 +1,1594 @@
 //! # Controller
-use crate::display::{print_error, print_output, print_output_string, print_output_vec_string, print_error_string};
+use crate::display::{print_output, print_output_bytes, print_output_string, print_output_vec_string, print_error_string};
+use crate::config;
+use crate::config::{Config, Remote, Settings};
+use crate::display;
+use crate::display::Color;
+use crate::error::GoldfishError;
 use crate::filesystem::*;
 use crate::filesystem;
+use crate::fsck;
+use crate::ignore::IgnoreRules;
 use crate::model;
 use crate::networking;
-use crate::model::{Blob, Commit, Repository, ChangeBin};
+use crate::pack;
+use crate::model::{Blob, Commit, Repository, ChangeBin, Signature, UnmergedPath};
 use crate::utilities;
-use std::collections::HashMap;
-use std::path::Path;
+use crate::utilities::MergeChunk;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime};
 
-pub fn init() {
+// (branch, commit_id) of each branch of a remote repository
+type RemoteBranches = Vec<(String, String)>;
+
+pub fn create_repository(working_path: &str) -> Result<Repository, GoldfishError> {
+    //! Create the .goldfish folder with its files and folders inside the working path
+    create_dir(join_path(vec![working_path, model::GOLDFISH_ROOT_DIR]).as_str())
+        .map_err(|e| GoldfishError::io("Something went wrong creating the .goldfish folder", e))?;
+    let repo = Repository::find(working_path).ok_or(GoldfishError::NotARepository)?;
+    for file in [model::HEAD, model::TRACKEDFILES] {
+        write_file("", join_path(vec![repo.get_repo_path(), file]).as_str())
+            .map_err(|e| GoldfishError::io(format!("Something went wrong creating the `{}` file", file), e))?;
+    }
+    // start on the default branch, which is created by the first commit
+    repo.write_symbolic_head(model::DEFAULT_BRANCH)?;
+    for folder in [
+        model::BLOBS_DIR,
+        model::TREES_DIR,
+        model::PACKS_DIR,
+        model::BRANCHES_DIR,
+        model::COMMITS_DIR,
+        model::STAGING_DIR,
+    ] {
+        create_dir(join_path(vec![repo.get_repo_path(), folder]).as_str())
+            .map_err(|e| GoldfishError::io(format!("Something went wrong creating the `{}` directory", folder), e))?;
+    }
+    Ok(repo)
+}
+
+fn get_current_dir() -> Result<String, GoldfishError> {
+    //! The current directory, as a string
+    let current_dir = std::env::current_dir().map_err(|e| GoldfishError::io("Fail to read the current directory", e))?;
+    Ok(pathbuf_to_string(current_dir))
+}
+
+pub fn init() -> Result<(), GoldfishError> {
     // Create a new .dvcs folder inside the current directory (if it doesn't already exist)
-    let current_directory = pathbuf_to_string(std::env::current_dir().unwrap());
-    match Repository::find(current_directory.as_str()) {
-        None => {
-            match create_dir(
-                join_path(vec![current_directory.as_str(), model::GOLDFISH_ROOT_DIR]).as_str(),
-            ) {
-                Ok(_) => {
-                    assert!(Repository::find(current_directory.as_str()).is_some());
-                    let repo = Repository::find(current_directory.as_str()).unwrap();
-                    for file in [model::HEAD, model::TRACKEDFILES] {
-                        match write_file("", join_path(vec![repo.get_repo_path(), file]).as_str()) {
-                            Ok(_) => {}
-                            Err(_) => return print_error_string(format!("Something went wrong creating the `{}` file", file)),
-                        }
-                    }
-                    for folder in [
-                        model::BLOBS_DIR,
-                        model::BRANCHES_DIR,
-                        model::COMMITS_DIR,
-                        model::STAGING_DIR,
-                    ] {
-                        match create_dir(join_path(vec![repo.get_repo_path(), folder]).as_str()) {
-                            Ok(_) => {}
-                            Err(_) => print_error_string(format!("Something went wrong creating the `{}` directory", folder)),
-                        }
-                    }
-                }
-                Err(_) => return print_error("Something went wrong creating the .goldfish folder"),
-            }
-            print_output("Successfully initialized new repository")
+    let current_directory = get_current_dir()?;
+    if Repository::find(current_directory.as_str()).is_some() {
+        return Err(GoldfishError::Invalid(String::from("Already a Goldfish folder")));
+    }
+    create_repository(current_directory.as_str())?;
+    print_output("Successfully initialized new repository");
+    Ok(())
+}
+
+pub fn clone(url: &str, folder_name: &str) -> Result<(), GoldfishError> {
+    //! Create a folder with the repo name, download every object and branch of the
+    //! repository at the url, and check out its HEAD into the folder
+    //! Example url: username@host:path/to/repository
+    if Repository::find(get_current_dir()?.as_str()).is_some() {
+        return Err(GoldfishError::Invalid(String::from("Cannot clone, already a repository")));
+    }
+    // create the base folder for the new local repository
+    let working_path = join_path(vec![".", folder_name]);
+    if is_dir(working_path.as_str()) {
+        return Err(GoldfishError::Invalid(format!("The directory {} already exists", working_path)));
+    }
+    create_dir(working_path.as_str()).map_err(|e| {
+        GoldfishError::io(format!("Something went wrong creating the {} folder for the repository", working_path), e)
+    })?;
+
+    let err = match clone_into(url, working_path.as_str()) {
+        Ok(_) => {
+            print_output("Successfully cloned repository");
+            return Ok(());
         }
-        Some(_) => return print_error("Already a Goldfish folder"),
+        Err(e) => e.with_context(format!("Cannot clone {}", url)),
+    };
+    // cleanup
+    if remove(working_path.as_str()).is_err() {
+        print_error_string(format!("Something went wrong cleaning up the {} repository folder", working_path));
     }
+    Err(err)
 }
 
-pub fn clone(url: &str, folder_name: &str) {
-    //! Create a folder with the repo name, download the .dvcs folder from the specified url,
-    //! and load the full directory into the folder
-    //! Example url: username@host:path/to/.goldfish
-    match Repository::find(pathbuf_to_string(std::env::current_dir().unwrap()).as_str()) {
-        Some(_) => return print_error("Cannot clone, already a repository"),
-        None => {
-            // create the base folder for the new local repository
-            let working_path = join_path(vec![".", folder_name]);
-            if is_dir(working_path.as_str()) {
-                return print_output_string(format!("The directory {} already exists", working_path))
-            }
-            if let Err(_) = create_dir(working_path.as_str()) {
-                print_output_string(format!("Something went wrong creating the {} folder for the repository", working_path));
-            }
+fn clone_into(url: &str, working_path: &str) -> Result<(), GoldfishError> {
+    //! Create a repository in the working path holding everything from the repository at the url,
+    //! which is recorded as the `origin` remote
+    let repo = create_repository(working_path)?;
+    // a local path is recorded absolute, since it was given relative to another directory
+    let url = match networking::parse_url(url) {
+        networking::Url::Local(path) => canonicalize(path.as_str()).unwrap_or(path),
+        networking::Url::Ssh { .. } => url.to_string(),
+    };
+    let remote = Remote::new(model::DEFAULT_REMOTE, url.as_str());
+    let mut config = repo.load_config()?;
+    config.set_remote(&remote);
+    config.save()?;
+    let (head, _, _) = fetch_remote(&repo, &remote)?;
 
-            // download the .goldfish folder (repository data) from the given url
-            let download_succeeded = networking::rsync(url, working_path.as_str());
-            if download_succeeded {
-                print_output("--> Finished downloading repository data; now populating working tree");
-                match Repository::find(working_path.as_str()) {
-                    Some(repo) => {
-                        match repo.read_head() {
-                            Ok(head_id) => {
-                                match Commit::get(&repo, head_id.as_str()) {
-                                    Some(commit) => {
-                                        match commit.checkout() {
-                                            Ok(_) => return print_output("Successfully cloned repository"),
-                                            Err(err) => {
-                                                print_error("Error checking out the HEAD commit:");
-                                                print_error_string(err)
-                                            }
-                                        }
-                                    }
-                                    None => print_output("Something went wrong loading the HEAD commit")
-                                }
-                            }
-                            Err(_) => print_output("Something went wrong leading the HEAD commit")
-                        }
-                    }
-                    None => print_output("Something went wrong creating the repository")
-                }
-            } else {
-                print_error(format!("Cannot fetch repository data from the given url: {}", url).as_str());
+    // create and follow the branch the remote HEAD follows, or detach at the same commit
+    match model::parse_symbolic_head(head.as_str()) {
+        Some(branch) if model::is_valid_branch_name(branch.as_str()) => {
+            config.set_upstream(branch.as_str(), remote.get_name(), branch.as_str());
+            // the remote repository may have no commits yet
+            if let Ok(commit_id) = repo.read_remote_branch(model::DEFAULT_REMOTE, branch.as_str()) {
+                repo.write_branch(branch.as_str(), commit_id.as_str())?;
             }
+            repo.write_symbolic_head(branch.as_str())?;
+            config.save()?;
+        }
+        Some(_) => return Err(GoldfishError::Transport(format!("Invalid remote HEAD: {}", head))),
+        None if repo.has_object(model::COMMITS_DIR, head.as_str()) => repo.write_head(format!("{}\n", head))?,
+        None => {}
+    }
 
-            // cleanup
-            if let Err(_) = remove(working_path.as_str()) {
-                print_error_string(format!("Something went wrong cleaning up the {} repository folder", working_path));
+    print_output("--> Finished downloading repository data; now populating working tree");
+    let head_id = repo.get_current_commit_id()?;
+    if head_id.is_empty() {
+        return Ok(());
+    }
+    Commit::get(&repo, head_id.as_str())?.checkout(false)
+}
+
+fn find_remote(repo: &Repository, remote: Option<&str>) -> Result<(Remote, Option<String>), GoldfishError> {
+    //! Find the remote given on the command line: a remote name, or an url (of a remote, or not).
+    //! Without one, use the upstream of the current branch. Also return the branch of the
+    //! remote that is the upstream of the current branch, if any
+    let config = repo.load_config()?;
+    let upstream = repo.get_current_branch().and_then(|branch| config.get_upstream(branch.as_str()));
+    let remote = match remote {
+        Some(remote) => config.get_remote(remote)
+            .or_else(|| config.list_remotes().into_iter().find(|known| known.get_url() == remote))
+            .unwrap_or_else(|| Remote::anonymous(remote)),
+        None => match &upstream {
+            Some((name, _)) => config.get_remote(name)
+                .ok_or_else(|| GoldfishError::Config(format!("The upstream remote {} does not exist", name)))?,
+            None => return Err(GoldfishError::Invalid(String::from(
+                "The current branch has no upstream remote. Pass a remote name or url",
+            ))),
+        },
+    };
+    let upstream_branch = upstream.filter(|(name, _)| name == remote.get_name()).map(|(_, branch)| branch);
+    Ok((remote, upstream_branch))
+}
+
+fn fetch_remote(repo: &Repository, remote: &Remote) -> Result<(String, RemoteBranches, Vec<String>), GoldfishError> {
+    //! Download the commits of the remote that are missing, and point the remote-tracking
+    //! branches given by its fetch refspecs to the branches of the remote. Return the content
+    //! of the remote HEAD, the (branch, commit_id) of the remote and a report of the updates
+    let mut connection = networking::Connection::open(remote.get_url())?;
+    let count = connection.fetch(repo)?;
+    let mut report = vec![format!("Fetched {} object(s) from {}", count, remote.get_url())];
+    let remote_branches = connection.get_branches().clone();
+    let head = connection.get_head().to_string();
+    connection.close()?;
+
+    for (branch, commit_id) in &remote_branches {
+        let (tracking_remote, tracking_branch) = match remote.map_branch(branch) {
+            Some(tracking) => tracking,
+            None => continue,
+        };
+        let old_commit_id = repo.read_remote_branch(tracking_remote.as_str(), tracking_branch.as_str()).ok();
+        if old_commit_id.as_ref() == Some(commit_id) {
+            continue;
+        }
+        repo.write_remote_branch(tracking_remote.as_str(), tracking_branch.as_str(), commit_id)?;
+        let status = if old_commit_id.is_some() { "   [updated]   " } else { " * [new branch]" };
+        report.push(format!("{} {} -> {}/{}", status, branch, tracking_remote, tracking_branch));
+    }
+    // remote-tracking branches of branches deleted from the remote
+    for refspec in remote.get_fetch_refspecs() {
+        let tracking_remote = match refspec.get_destination_remote() {
+            Some(tracking_remote) => tracking_remote,
+            None => continue,
+        };
+        for tracking_branch in repo.list_remote_branches(tracking_remote)? {
+            let branch = match refspec.unmap_branch(tracking_remote, tracking_branch.as_str()) {
+                Some(branch) => branch,
+                None => continue,
+            };
+            if !remote_branches.iter().any(|(remote_branch, _)| *remote_branch == branch) {
+                repo.delete_remote_branch(tracking_remote, tracking_branch.as_str())?;
+                report.push(format!(" - [deleted]     (none) -> {}/{}", tracking_remote, tracking_branch));
             }
         }
     }
+    Ok((head, remote_branches, report))
 }
 
+pub fn fetch(remote: Option<&str>) -> Result<(), GoldfishError> {
+    //! Download the commits of a remote (by default the upstream of the current branch) into
+    //! its remote-tracking branches, without touching the local branches or the working directory
+    let repo = Repository::find_current()?;
+    let (_, _, report) = find_remote(&repo, remote)
+        .and_then(|(remote, _)| fetch_remote(&repo, &remote))
+        .map_err(|e| e.with_context("Failed to fetch"))?;
+    print_output_vec_string(report);
+    Ok(())
+}
 
+pub fn remote_add(name: &str, url: &str) -> Result<(), GoldfishError> {
+    //! Record a remote, whose branches are fetched as `{name}/{branch}`
+    let repo = Repository::find_current()?;
+    if !model::is_valid_remote_name(name) {
+        return Err(GoldfishError::Invalid(format!("Invalid remote name: {}", name)));
+    }
+    let mut config = repo.load_config()?;
+    if config.get_remote(name).is_some() {
+        return Err(GoldfishError::Invalid(format!("Remote {} already exists", name)));
+    }
+    config.set_remote(&Remote::new(name, url));
+    config.save()?;
+    print_output_string(format!("Added remote {} ({})", name, url));
+    Ok(())
+}
 
-pub fn commit() {
-    match Repository::find(pathbuf_to_string(std::env::current_dir().unwrap()).as_str()) {
-        Some(repo) => {
-            // Comparing staging with HEAD to check if there is any change
-            let staging_tracked_files;
-            match repo.get_staging_tracked_files() {
-                Ok(files) => staging_tracked_files = files,
-                Err(e) => return print_error(e.as_str()),
-            }
-            let head_tracked_files;
-            match repo.get_current_commit_id() {
-                Ok(commit_id) => {
-                    if commit_id.is_empty() {
-                        head_tracked_files = HashMap::new();
-                    } else {
-                        match Commit::get(&repo, commit_id.as_str()) {
-                            Some(commit) => match commit.load_tracked_files() {
-                                Some(files) => head_tracked_files = files,
-                                None => return print_error("Fail to load current commitx"),
-                            },
-                            None => return print_error("Fail to load current commity"),
-                        }
-                    }
-                }
-                Err(_) => head_tracked_files = HashMap::new(),
-            }
-            if utilities::compare_map(&staging_tracked_files, &head_tracked_files) {
-                print_output("Nothing to commit")
-            }
-            // list files in staging area
-            match list_files(repo.get_staging_path().as_str(), true, &vec![]) {
-                Ok(files) => {
-                    let mut file_list = vec![];
-                    // create blobs
-                    for file_path in files {
-                        match read_file(file_path.as_str()) {
-                            Ok(file_content) => {
-                                match Blob::create(&repo, file_content.as_str()) {
-                                    Ok(blob) => file_list.push((
-                                        diff_path(repo.get_staging_path().as_str(), file_path.as_str())
-                                            .unwrap(),
-                                        blob.get_id().to_string(),
-                                    )),
-                                    Err(err) => return print_error_string(format!("Something went wrong creating blob objects for the commit:\n{}", err)),
-                                }
-                            }
-                            Err(_) => return print_error("This file path should be valid"),
-                        }
-                    }
-                    // clean staging
-                    remove(repo.get_staging_path().as_str()).unwrap();
-                    // create commit
-                    match repo.get_current_commit_id() {
-                        Ok(current_commit_id) => {
-                            let tracked_files;
-                            match repo.get_staging_tracked_files() {
-                                Ok(files) => tracked_files = files,
-                                Err(e) => return print_error(e.as_str()),
-                            }
-                            match Commit::create(&repo, current_commit_id, vec![], tracked_files) {
-                                Ok(commit) => print_output(
-                                    format!("Created commit: {}", commit.get_id()).as_str(),
-                                ),
-                                Err(err) => print_error(
-                                    format!(
-                                        "Something went wrong writing the commit file:\n{}",
-                                        err
-                                    )
-                                    .as_str(),
-                                ),
-                            }
-                        }
-                        Err(err) => return print_error(
-                                        format!(
-                                            "Something went wrong reading the current commit id:\n{}",
-                                            err
-                                        )
-                                        .as_str(),
-                                    ),
-                    }
-                }
-                Err(_) => return print_error(
-                                "No file found in staging area. Please `add` files before committing",
-                            ),
-            }
+pub fn remote_remove(name: &str) -> Result<(), GoldfishError> {
+    //! Forget a remote, its remote-tracking branches and the upstreams using it
+    let repo = Repository::find_current()?;
+    let mut config = repo.load_config()?;
+    if !config.remove_remote(name) {
+        return Err(GoldfishError::Invalid(format!("No such remote: {}", name)));
+    }
+    config.save()?;
+    repo.delete_remote_branches(name)?;
+    print_output_string(format!("Removed remote {}", name));
+    Ok(())
+}
+
+pub fn remote_list() -> Result<(), GoldfishError> {
+    //! Print every remote and its url
+    let config = Repository::find_current()?.load_config()?;
+    print_output_vec_string(
+        config.list_remotes().iter().map(|remote| format!("{}\t{}", remote.get_name(), remote.get_url())).collect(),
+    );
+    Ok(())
+}
+
+pub fn remote_set_url(name: &str, url: &str) -> Result<(), GoldfishError> {
+    let repo = Repository::find_current()?;
+    let mut config = repo.load_config()?;
+    let mut remote = config.get_remote(name).ok_or_else(|| GoldfishError::Invalid(format!("No such remote: {}", name)))?;
+    remote.set_url(url);
+    config.set_remote(&remote);
+    config.save()?;
+    print_output_string(format!("Remote {} now points to {}", name, url));
+    Ok(())
+}
+
+
+fn get_author(repo: &Repository) -> Result<Signature, GoldfishError> {
+    //! Identify the author of new commits from the `GOLDFISH_AUTHOR_NAME` and
+    //! `GOLDFISH_AUTHOR_EMAIL` environment variables, or else the `user.name` and
+    //! `user.email` settings, falling back to the current user
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| String::from("unknown"));
+    let name = match std::env::var("GOLDFISH_AUTHOR_NAME") {
+        Ok(name) => name,
+        Err(_) => repo.get_setting::<String>(config::USER_NAME)?.unwrap_or_else(|| user.to_owned()),
+    };
+    let email = match std::env::var("GOLDFISH_AUTHOR_EMAIL") {
+        Ok(email) => email,
+        Err(_) => repo.get_setting::<String>(config::USER_EMAIL)?.unwrap_or_else(|| format!("{}@localhost", user)),
+    };
+    Ok(Signature::now(name.as_str(), email.as_str()))
+}
+
+fn use_color(repo: &Repository) -> Result<bool, GoldfishError> {
+    //! Whether to colour the output, following the `color.ui` setting
+    let color_ui = repo.get_setting::<String>(config::COLOR_UI)?.unwrap_or_else(|| String::from(config::DEFAULT_COLOR_UI));
+    match color_ui.as_str() {
+        "always" | "true" => Ok(true),
+        "never" | "false" => Ok(false),
+        "auto" => Ok(display::is_terminal()),
+        _ => Err(GoldfishError::Config(format!("The setting {} must be auto, always or never, not {}", config::COLOR_UI, color_ui))),
+    }
+}
+
+fn resolve_revision(repo: &Repository, revision: &str) -> Result<String, GoldfishError> {
+    //! The commit id of a branch, remote-tracking branch or commit id
+    model::resolve_reference(repo, revision).ok_or_else(|| GoldfishError::UnknownRevision(revision.to_string()))
+}
+
+fn load_head_tracked_files(repo: &Repository) -> Result<HashMap<String, String>, GoldfishError> {
+    //! The {file_path: blob_id} list of the current commit, empty if there is no commit yet
+    match repo.get_current_commit_id() {
+        Ok(commit_id) if !commit_id.is_empty() => Commit::get(repo, commit_id.as_str())?.load_tracked_files(),
+        _ => Ok(HashMap::new()),
+    }
+}
+
+pub fn commit(message: &str) -> Result<(), GoldfishError> {
+    let repo = Repository::find_current()?;
+    // a merge that stopped on conflicts is concluded by this commit
+    let merge_head = repo.read_merge_head();
+    // Comparing staging with HEAD to check if there is any change
+    let staging_tracked_files = repo.get_staging_tracked_files()?;
+    let head_tracked_files = load_head_tracked_files(&repo)?;
+    if staging_tracked_files == head_tracked_files && merge_head.is_none() {
+        print_output("Nothing to commit");
+        return Ok(());
+    }
+    if merge_head.is_some() {
+        let unmerged_paths = repo.get_unmerged_paths()?;
+        if !unmerged_paths.is_empty() {
+            let paths: Vec<&str> = unmerged_paths.iter().map(|unmerged_path| unmerged_path.get_path()).collect();
+            return Err(GoldfishError::Conflict(format!(
+                "Cannot commit, unmerged paths: {}. Fix the conflicts and add the files first",
+                paths.join(", ")
+            )));
+        }
+        let marked_files = get_files_with_conflict_markers(&repo)?;
+        if !marked_files.is_empty() {
+            return Err(GoldfishError::Conflict(format!(
+                "Cannot commit, conflict markers remain in: {}",
+                marked_files.join(", ")
+            )));
         }
-        None => return print_error("Not a Goldfish folder"),
     }
+    let message = match &merge_head {
+        Some(merge_head) if message.is_empty() => format!("Merge {}", merge_head),
+        _ => message.to_string(),
+    };
+    let commit_id = create_commit(&repo, message.as_str(), merge_head.into_iter().collect())?;
+    print_output_string(format!("Created commit: {}", commit_id));
+    Ok(())
 }
 
-pub fn status() {
-    match Repository::find(pathbuf_to_string(std::env::current_dir().unwrap()).as_str()) {
-        Some(repo) => {
-            let mut change = false;
-            // Comparing staging with HEAD
-            let staging_tracked_files;
-            match repo.get_staging_tracked_files() {
-                Ok(files) => staging_tracked_files = files,
-                Err(e) => return print_error(e.as_str()),
-            }
-            let head_tracked_files;
-            match repo.get_current_commit_id() {
-                Ok(commit_id) => {
-                    if commit_id.is_empty() {
-                        head_tracked_files = HashMap::new();
-                    } else {
-                        match Commit::get(&repo, commit_id.as_str()) {
-                            Some(commit) => match commit.load_tracked_files() {
-                                Some(files) => head_tracked_files = files,
-                                None => return print_error("Fail to load current commit"),
-                            },
-                            None => return print_error("Fail to load current commit"),
-                        }
-                    }
-                }
-                Err(_) => head_tracked_files = HashMap::new(),
+fn create_commit(repo: &Repository, message: &str, secondary_parent_ids: Vec<String>) -> Result<String, GoldfishError> {
+    //! Commit the staging area on top of HEAD, with the given commits as extra parents
+    //! for a merge commit, and return the id of the new commit
+    // list files in staging area (there is no staging folder if no file was added)
+    let staging_path = repo.get_staging_path();
+    let files = if is_dir(staging_path.as_str()) {
+        list_files(staging_path.as_str(), true, &vec![])
+            .map_err(|e| GoldfishError::io("Fail to list the files in the staging area", e))?
+    } else {
+        vec![]
+    };
+    let tracked_files = repo.get_staging_tracked_files()?;
+    let author = get_author(repo)?;
+    // create blobs
+    for file_path in files {
+        let file_content = read_bytes(file_path.as_str())
+            .map_err(|e| GoldfishError::io(format!("Fail to read the staged copy {}", file_path), e))?;
+        let blob = Blob::create(repo, &file_content)
+            .map_err(|e| GoldfishError::io("Something went wrong creating blob objects for the commit", e))?;
+        let rel_path = diff_path(staging_path.as_str(), file_path.as_str()).unwrap_or(file_path);
+        if tracked_files.get(&rel_path).is_some_and(|blob_id| blob_id != blob.get_id()) {
+            return Err(GoldfishError::Conflict(format!(
+                "The staged copy of {} does not match the list of tracked files. Please add it again",
+                rel_path
+            )));
+        }
+    }
+    // clean staging
+    if is_dir(staging_path.as_str()) {
+        remove(staging_path.as_str()).map_err(|e| GoldfishError::io("Something went wrong cleaning the staging area", e))?;
+    }
+    // create commit
+    let current_commit_id = repo.get_current_commit_id()?;
+    let commit = Commit::create(repo, current_commit_id, secondary_parent_ids, tracked_files, author, message)?;
+    repo.clear_merge_head()?;
+    repo.save_unmerged_paths(&[])?;
+    Ok(commit.get_id())
+}
+
+fn get_working_files(
+    repo: &Repository,
+    staging_tracked_files: &HashMap<String, String>,
+) -> Result<(HashMap<String, String>, Vec<String>), GoldfishError> {
+    //! Hash the files of the working directory that are tracked or not ignored by a `.goldfishignore`,
+    //! and list the ignored paths that are not tracked (directories end with `/`)
+    let working_path = repo.get_working_path();
+    let mut rules = IgnoreRules::load(working_path, working_path);
+    let (files, ignored) = rules.list_unignored_files(working_path, &vec![repo.get_repo_path()])
+        .map_err(|e| GoldfishError::io("Fail to list the files of the working directory", e))?;
+    let hash_file = |file_path: &str| match read_bytes(file_path) {
+        Ok(content) => utilities::hash(&content),
+        Err(_e) => "".to_string(),
+    };
+    let mut wd_files = HashMap::new();
+    for file_path in files {
+        wd_files.insert(get_relative_path_from_base(working_path, file_path.as_str()), hash_file(file_path.as_str()));
+    }
+    // ignore files only apply to untracked files
+    for tracked_path in staging_tracked_files.keys() {
+        let file_path = join_path(vec![working_path, tracked_path]);
+        if !wd_files.contains_key(tracked_path) && is_file(file_path.as_str()) {
+            wd_files.insert(tracked_path.to_owned(), hash_file(file_path.as_str()));
+        }
+    }
+    let mut ignored_paths: Vec<String> = ignored
+        .iter()
+        .map(|ignored_path| {
+            let relative_path = get_relative_path_from_base(working_path, ignored_path.as_str());
+            if is_dir(ignored_path.as_str()) { format!("{}/", relative_path) } else { relative_path }
+        })
+        .filter(|ignored_path| !staging_tracked_files.contains_key(ignored_path))
+        .collect();
+    ignored_paths.sort();
+    Ok((wd_files, ignored_paths))
+}
+
+pub fn status(show_ignored: bool) -> Result<(), GoldfishError> {
+    let repo = Repository::find_current()?;
+    let mut change = false;
+    // Comparing staging with HEAD
+    let staging_tracked_files = repo.get_staging_tracked_files()?;
+    let head_tracked_files = load_head_tracked_files(&repo)?;
+    if !utilities::compare_map(&staging_tracked_files, &head_tracked_files) {
+        change = true;
+        print_output("Changes to be commit:");
+        for (file_path, _hash) in &staging_tracked_files {
+            if !head_tracked_files.contains_key(file_path) {
+                print_output(format!("\tAdded:   \t{}", file_path).as_str());
             }
-            if !utilities::compare_map(&staging_tracked_files, &head_tracked_files) {
-                change = true;
-                print_output("Changes to be commit:");
-                for (file_path, _hash) in &staging_tracked_files {
-                    if !head_tracked_files.contains_key(file_path) {
-                        print_output(format!("\tAdded:   \t{}", file_path).as_str());
-                    }
-                }
-                for (file_path, _hash) in &head_tracked_files {
-                    if !staging_tracked_files.contains_key(file_path) {
-                        print_output(format!("\tDeleted: \t{}", file_path).as_str());
-                    }
-                }
-                for (file_path, hash) in &staging_tracked_files {
-                    if head_tracked_files.contains_key(file_path)
-                        && !hash.eq(&head_tracked_files[file_path])
-                    {
-                        print_output(format!("\tModified:\t{}", file_path).as_str());
-                    }
-                }
+        }
+        for (file_path, _hash) in &head_tracked_files {
+            if !staging_tracked_files.contains_key(file_path) {
+                print_output(format!("\tDeleted: \t{}", file_path).as_str());
             }
-            // Comparing current WD with staging
-            let mut wd_files = HashMap::new();
-            for file_path in
-                list_files(repo.get_working_path(), true, &vec![repo.get_repo_path()]).unwrap()
+        }
+        for (file_path, hash) in &staging_tracked_files {
+            if head_tracked_files.contains_key(file_path)
+                && !hash.eq(&head_tracked_files[file_path])
             {
-                let hash;
-                match read_file(&file_path) {
-                    Ok(content) => hash = utilities::hash(content.as_str()),
-                    Err(_e) => hash = "".to_string(),
-                }
-                wd_files.insert(
-                    get_relative_path_from_base(repo.get_working_path(), file_path.as_str()),
-                    hash,
-                );
-            }
-            if !utilities::compare_map(&wd_files, &staging_tracked_files) {
-                change = true;
-                print_output("Changes not staged for commit:");
-                for (file_path, _hash) in &wd_files {
-                    if !staging_tracked_files.contains_key(file_path) {
-                        print_output(format!("\tAdded:   \t{}", file_path).as_str());
-                    }
-                }
-                for (file_path, _hash) in &staging_tracked_files {
-                    if !wd_files.contains_key(file_path) {
-                        print_output(format!("\tDeleted: \t{}", file_path).as_str());
-                    }
-                }
-                for (file_path, hash) in &wd_files {
-                    if staging_tracked_files.contains_key(file_path)
-                        && !hash.eq(&staging_tracked_files[file_path])
-                    {
-                        print_output(format!("\tModified:\t{}", file_path).as_str());
-                    }
-                }
-            }
-            if !change {
-                print_output("Nothing to commit, working directory clean");
+                print_output(format!("\tModified:\t{}", file_path).as_str());
             }
         }
-        None => return print_error("Not a Goldfish folder"),
     }
-}
-
-fn check_status() -> Option<bool> {
-    match Repository::find(pathbuf_to_string(std::env::current_dir().unwrap()).as_str()) {
-        Some(repo) => {
-            // Comparing staging with HEAD
-            let staging_tracked_files;
-            match repo.get_staging_tracked_files() {
-                Ok(files) => staging_tracked_files = files,
-                Err(e) => {
-                    print_error(e.as_str());
-                    return None;
-                }
+    // Comparing current WD with staging
+    let (wd_files, ignored_paths) = get_working_files(&repo, &staging_tracked_files)?;
+    if !utilities::compare_map(&wd_files, &staging_tracked_files) {
+        change = true;
+        print_output("Changes not staged for commit:");
+        for (file_path, _hash) in &wd_files {
+            if !staging_tracked_files.contains_key(file_path) {
+                print_output(format!("\tAdded:   \t{}", file_path).as_str());
             }
-            let head_tracked_files;
-            match repo.get_current_commit_id() {
-                Ok(commit_id) => {
-                    if commit_id.is_empty() {
-                        head_tracked_files = HashMap::new();
-                    } else {
-                        match Commit::get(&repo, commit_id.as_str()) {
-                            Some(commit) => match commit.load_tracked_files() {
-                                Some(files) => head_tracked_files = files,
-                                None => {
-                                    print_error("Fail to load current commit");
-                                    return None;
-                                }
-                            },
-                            None => {
-                                print_error("Fail to load current commit");
-                                return None;
-                            }
-                        }
-                    }
-                }
-                Err(_) => head_tracked_files = HashMap::new(),
+        }
+        for (file_path, _hash) in &staging_tracked_files {
+            if !wd_files.contains_key(file_path) {
+                print_output(format!("\tDeleted: \t{}", file_path).as_str());
             }
-            // Comparing current WD with staging
-            let mut wd_files = HashMap::new();
-            for file_path in
-                list_files(repo.get_working_path(), true, &vec![repo.get_repo_path()]).unwrap()
+        }
+        for (file_path, hash) in &wd_files {
+            if staging_tracked_files.contains_key(file_path)
+                && !hash.eq(&staging_tracked_files[file_path])
             {
-                let hash;
-                match read_file(&file_path) {
-                    Ok(content) => hash = utilities::hash(content.as_str()),
-                    Err(_e) => hash = "".to_string(),
-                }
-                wd_files.insert(
-                    get_relative_path_from_base(repo.get_working_path(), file_path.as_str()),
-                    hash,
-                );
+                print_output(format!("\tModified:\t{}", file_path).as_str());
             }
-            return Some(!utilities::compare_map(&staging_tracked_files, &head_tracked_files)
-                    || !utilities::compare_map(&wd_files, &staging_tracked_files));
         }
-        None => {
-            print_error("Not a Goldfish folder");
-            return None;
+    }
+    // Conflicts left by a merge
+    let unmerged_paths = repo.get_unmerged_paths()?;
+    if !unmerged_paths.is_empty() {
+        change = true;
+        print_output("Unmerged paths:");
+        for unmerged_path in &unmerged_paths {
+            print_output_string(format!("\t{}:\t{}", unmerged_path.get_kind(), unmerged_path.get_path()));
         }
     }
+    if show_ignored && !ignored_paths.is_empty() {
+        print_output("Ignored files:");
+        for ignored_path in &ignored_paths {
+            print_output_string(format!("\t{}", ignored_path));
+        }
+    }
+    if let Some(merge_head) = repo.read_merge_head() {
+        print_output_string(format!("Merging {}: fix the conflicts and run `merge --continue`, or `merge --abort`", merge_head));
+    }
+    if !change {
+        print_output("Nothing to commit, working directory clean");
+    }
+    Ok(())
 }
 
-pub fn heads() {
-    //! Print out the current HEAD and the branch name of that HEAD, taken from the .dvcs folder
-    match Repository::find(pathbuf_to_string(std::env::current_dir().unwrap()).as_str()) {
-        Some(repo) => match repo.read_head() {
-            Ok(head) => {
-                if head == "" {
-                    print_output("Empty repository")
-                } else {
-                    print_output(format!("At commit {}", head).as_str())
-                }
-            }
-            Err(e) => return print_error(e.as_str()),
-        },
-        None => return print_error("Not a Goldfish folder"),
-    }
+fn check_status(repo: &Repository) -> Result<bool, GoldfishError> {
+    //! Whether there are staged changes, or changes not added yet
+    // Comparing staging with HEAD
+    let staging_tracked_files = repo.get_staging_tracked_files()?;
+    let head_tracked_files = load_head_tracked_files(repo)?;
+    // Comparing current WD with staging
+    let (wd_files, _ignored_paths) = get_working_files(repo, &staging_tracked_files)?;
+    Ok(!utilities::compare_map(&staging_tracked_files, &head_tracked_files)
+        || !utilities::compare_map(&wd_files, &staging_tracked_files))
 }
 
-pub fn diff(commit_id1: &str, commit_id2: &str) {
-    fn get_diff_files(tracked_file_list1: &HashMap<String, String>,
-                        tracked_file_list2: &HashMap<String, String>) -> Vec<String> {
-        let mut result: Vec<String> = vec![];
-        for (file_path1, _) in tracked_file_list1 {
-            let mut is_file_in: bool = false;
-            for (file_path2, _) in tracked_file_list2 {
-                if file_path1 == file_path2 {
-                    is_file_in = true;
-                    break;
-                }
+pub fn heads() -> Result<(), GoldfishError> {
+    //! Print out the current HEAD and the branch name of that HEAD, taken from the .dvcs folder
+    let repo = Repository::find_current()?;
+    let head = repo.get_current_commit_id().map_err(|e| e.with_context("Fail to load HEAD file"))?;
+    match repo.get_current_branch() {
+        Some(branch_name) => {
+            if head.is_empty() {
+                print_output_string(format!("On branch {}, no commits yet", branch_name))
+            } else {
+                print_output_string(format!("On branch {} at commit {}", branch_name, head))
             }
-            if !is_file_in {
-                result.push(file_path1.to_string());
+        }
+        None => {
+            if head.is_empty() {
+                print_output("Empty repository")
+            } else {
+                print_output_string(format!("HEAD detached at commit {}", head));
+                print_output("Warning: you are in 'detached HEAD' state. New commits will not belong to any branch;");
+                print_output("use `branch [name]` to keep them on a new branch");
             }
         }
-        result
     }
+    Ok(())
+}
+
+pub fn diff(commit_id1: &str, commit_id2: &str, context: Option<usize>) -> Result<(), GoldfishError> {
+    //! Print the changes between two commits as a unified diff, with `context` (by default
+    //! the `diff.context` setting) unchanged lines around each change
     let mut result: Vec<String> = vec![];
-    // Takes in two commit hashes and use the `display` module to print out the changes
-    // between the two files
-    match Repository::find(pathbuf_to_string(std::env::current_dir().unwrap()).as_str()) {
-        Some(repo) => {
-            // get the Commit associated with the given commit_id
-            match Commit::get(&repo, commit_id1) {
-                Some(commit1) => {
-                    match Commit::get(&repo, commit_id2) {
-                        Some(commit2) => {
-                            // load all the files of that commit
-                            match commit1.load_tracked_files() {
-                                Some(tracked_file_list1) => {
-                                    match commit2.load_tracked_files() {
-                                        Some(tracked_file_list2) => {
-                                            let added_files = get_diff_files(&tracked_file_list2, &tracked_file_list1);
-                                            let removed_files = get_diff_files(&tracked_file_list1, &tracked_file_list2);
-                                            if !added_files.is_empty() {
-                                                result.push(format!("Added files from commit {}:", commit_id1));
-                                                result.extend(added_files);
-                                            }
-                                            if !removed_files.is_empty() {
-                                                result.push(format!("Removed files from commit {}:", commit_id1));
-                                                result.extend(removed_files);
-                                            }
-                                            for (file_path1, blob_id1) in &tracked_file_list1 {
-                                                let mut is_file_in: bool = false;
-                                                let mut blob_id2: String = "".to_string();
-                                                for (file_path2, temp_blob_id) in &tracked_file_list2 {
-                                                    if file_path1 == file_path2 {
-                                                        is_file_in = true;
-                                                        blob_id2 = temp_blob_id.to_string();
-                                                        break;
-                                                    }
-                                                }
-                                                if is_file_in {
-                                                    match Blob::get(&repo, blob_id1.as_str()) {
-                                                        Some(blob1) => {
-                                                            match Blob::get(&repo, blob_id2.as_str()) {
-                                                                Some(blob2) => {
-                                                                    match blob1.get_blob_content() {
-                                                                        Ok(content1) => {
-                                                                            match blob2.get_blob_content() {
-                                                                                Ok(content2) => {
-                                                                                    let mut has_diff: bool = false;
-                                                                                    let mut temp_result: Vec<String> = vec![];
-                                                                                    let file_vec1: Vec<String> = content1.lines().collect::<Vec<&str>>()
-                                                                                                                        .iter().map(|s| s.to_string()).collect();
-                                                                                    let file_vec2: Vec<String> = content2.lines().collect::<Vec<&str>>()
-                                                                                                                        .iter().map(|s| s.to_string()).collect();
-                                                                                    let diff_content = utilities::diff(file_vec1, file_vec2);
-                                                                                    for (_, content) in diff_content.iter().enumerate() {
-                                                                                        if content.0 == "+" || content.0 == "-" {
-                                                                                            has_diff = true;
-                                                                                            temp_result.push(format!("{} {}", content.0, content.1));
-                                                                                        } else {
-                                                                                            temp_result.push(format!("{}", content.1));
-                                                                                        }
-                                                                                    }
-                                                                                    if has_diff {
-                                                                                        result.push(format!("Differences in file {}:", file_path1));
-                                                                                        result.extend(temp_result);
-                                                                                    }
-                                                                                }
-                                                                                Err(_) => return print_error("File not found"),
-                                                                            }
-                                                                        }
-                                                                        Err(_) => return print_error("File not found"),
-                                                                    }
-                                                                }
-                                                                None => return print_error(
-                                                                    "Something went wrong reading file",
-                                                                ),
-                                                            }
-                                                        }
-                                                        None => return print_error(
-                                                            "Something went wrong reading file",
-                                                        ),
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        None => return print_error("Corrupted second commit file"),
-                                    }
-                                }
-                                None => return print_error("Corrupted first commit file"),
-                            }
-                        }
-                        None => return print_error("Invalid second commit id"),
-                    }
+    let repo = Repository::find_current()?;
+    let context = match context {
+        Some(context) => context,
+        None => repo.get_setting::<usize>(config::DIFF_CONTEXT)?.unwrap_or(config::DEFAULT_DIFF_CONTEXT),
+    };
+    let color = use_color(&repo)?;
+    let paint = |line: String, color_of_line: Color| if color { display::paint(line.as_str(), color_of_line) } else { line };
+    let commit_id1 = resolve_revision(&repo, commit_id1)?;
+    let commit_id2 = resolve_revision(&repo, commit_id2)?;
+    // get the Commit associated with the given commit_id
+    let commit1 = Commit::get(&repo, commit_id1.as_str())?;
+    let commit2 = Commit::get(&repo, commit_id2.as_str())?;
+    let changes = commit_diff(&commit1, &commit2, &repo)?;
+    let mut file_paths: Vec<&String> = changes.keys().collect();
+    file_paths.sort();
+    for file_path in file_paths {
+        let change = &changes[file_path];
+        if !change.has_changes() {
+            continue;
+        }
+        let old_name = match change.get_tag() {
+            "+" => String::from("/dev/null"),
+            _ => format!("a/{}", file_path),
+        };
+        let new_name = match change.get_tag() {
+            "-" => String::from("/dev/null"),
+            _ => format!("b/{}", file_path),
+        };
+        if change.is_binary() {
+            result.push(format!("Binary files {} and {} differ", old_name, new_name));
+            continue;
+        }
+        result.push(paint(match change.get_old_blob_id() {
+            Some(blob_id) => format!("--- {}\t{}", old_name, blob_id),
+            None => format!("--- {}", old_name),
+        }, Color::Bold));
+        result.push(paint(match change.get_new_blob_id() {
+            Some(blob_id) => format!("+++ {}\t{}", new_name, blob_id),
+            None => format!("+++ {}", new_name),
+        }, Color::Bold));
+        for line in utilities::unified_hunks(change.get_line_list(), context) {
+            let color_of_line = match line.chars().next() {
+                Some('@') => Color::Cyan,
+                Some('+') => Color::Green,
+                Some('-') => Color::Red,
+                _ => {
+                    result.push(line);
+                    continue;
                 }
-                None => return print_error("Invalid first commit id"),
-            }
-            if result.is_empty() {
-                print_output("Two commits are identical");
-            } else {
-                print_output_vec_string(result);
-            }
+            };
+            result.push(paint(line, color_of_line));
         }
-        None => return print_error("Cannot find the repository"),
     }
+    if result.is_empty() {
+        print_output("Two commits are identical");
+    } else {
+        print_output_vec_string(result);
+    }
+    Ok(())
 }
 
-pub fn cat(commit_id: &str, file: &str) {
+pub fn cat(commit_id: &str, file: &str) -> Result<(), GoldfishError> {
     //! Reads a file in the given commit (revision)
-    match Repository::find(pathbuf_to_string(std::env::current_dir().unwrap()).as_str()) {
-        Some(repo) => {  // found repo
-            match Commit::get(&repo, commit_id) {
-                Some(commit) => {  // found commit
-                    match commit.load_tracked_files() {
-                        Some(files_lookup) => {  // found committed file list
-                            for (committed_file, blob_id) in &files_lookup {
-                                if compare_paths(committed_file, file) {
-                                    match Blob::get(&repo, blob_id) {
-                                        Some(blob) => {
-                                            match blob.get_blob_content() {
-                                                Ok(content) => {
-                                                    print_output(format!("File data for {}:\n{}", file, content).as_str());
-                                                }
-                                                Err(_) => return print_error("Blob object is corrupted")
-                                            }
-                                        }
-                                        None => return print_error("Blob object is corrupted")
-                                    }
-                                }
-                            }
-                        }
-                        None => return print_error("Commit object is corrupted")
-                    }
-                }
-                None => return print_error(format!("Invalid commit id: {}", commit_id).as_str())
-            }
+    let repo = Repository::find_current()?;
+    let commit_id = resolve_revision(&repo, commit_id)?;
+    let files_lookup = Commit::get(&repo, commit_id.as_str())?.load_tracked_files()?;
+    for (committed_file, blob_id) in &files_lookup {
+        if compare_paths(committed_file, file) {
+            let content = Blob::get(&repo, blob_id)?.get_blob_content()
+                .map_err(|e| GoldfishError::io(format!("Fail to read blob {}", blob_id), e))?;
+            print_output(format!("File data for {}:", file).as_str());
+            print_output_bytes(&content);
         }
-        None => return print_error("Not a Goldfish folder")
     }
+    Ok(())
 }
 
-pub fn log() {
+pub fn log() -> Result<(), GoldfishError> {
     //! Print the ancestors of the current commit
-    fn print_ancestor(repo: &Repository, commit_id: &str) -> bool {
-        //! Print the current commit, then recursively print the ancestor of this commit
-        //! result
-        if commit_id == "" {
-            return false;
+    let repo = Repository::find_current()?;
+    let head_commit_id = repo.get_current_commit_id()
+        .map_err(|e| e.with_context("Something went wrong reading the current commit id"))?;
+    let color = use_color(&repo)?;
+    print_output("History:");
+    if head_commit_id.is_empty() {
+        print_output("Empty, no commit found");
+        return Ok(());
+    }
+    // print the current commit, then its direct parents one by one
+    let mut commit_id = head_commit_id;
+    while !commit_id.is_empty() {
+        let commit = Commit::get(&repo, commit_id.as_str())?;
+        let description = commit.pretty_print();
+        match description.split_once('\n') {
+            // highlight the `Commit: {id}` line
+            Some((first_line, rest)) if color => print_output_string(
+                format!("---\n{}\n{}", display::paint(first_line, Color::Yellow), rest),
+            ),
+            _ => print_output(format!("---\n{}", description).as_str()),
         }
+        commit_id = commit.get_direct_parent_id().to_string();
+    }
+    Ok(())
+}
 
-        match Commit::get(repo, commit_id) {
-            Some(commit) => {
-                print_output(format!("---\n{}", commit.pretty_print()).as_str());
-                print_ancestor(repo, commit.get_direct_parent_id());
-            }
-            None => {
-                print_error(format!("Invalid commit id: {}", commit_id).as_str());
-            },
-        }
-        true
+pub fn checkout(commit_id: &str, force: bool) -> Result<(), GoldfishError> {
+    //! Edit the commit (branch) name in the HEAD file, and update the working directory to the files of
+    //! the commit. Unless `force` is given, refuse to overwrite local modifications
+    let repo = Repository::find_current()?;
+    let id = resolve_revision(&repo, commit_id)?;
+    // get the Commit associated with the given commit_id
+    Commit::get(&repo, id.as_str())?.checkout(force)?;
+    // follow the branch if one was given, otherwise detach HEAD at the commit
+    if commit_id == model::HEAD {
+        print_output_string(format!("Checked out commit {}", id))
+    } else if model::is_valid_branch_name(commit_id) && repo.branch_exists(commit_id) {
+        repo.write_symbolic_head(commit_id)?;
+        print_output_string(format!("Checked out branch {} (commit {})", commit_id, id))
+    } else {
+        repo.write_head(format!("{}\n", id))?;
+        print_output_string(format!("Checked out commit {} (detached HEAD)", id))
     }
+    Ok(())
+}
 
-    match Repository::find(pathbuf_to_string(std::env::current_dir().unwrap()).as_str()) {
-        Some(repo) => match repo.get_current_commit_id() {
-            Ok(head_commit_id) => {
-                print_output("History:");
-                if !print_ancestor(&repo, head_commit_id.as_str()) {
-                    print_output("Empty, no commit found")
-                };
-            }
-            Err(err) => return print_error(
-                format!(
-                    "Something went wrong reading the current commit id:\n{}",
-                    err
-                )
-                .as_str(),
-            ),
-        },
-        None => print_error("Not a Goldfish folder"),
+fn describe_size_change(size: u64, previous_size: u64) -> String {
+    //! A size compared to the size before, e.g. `120 bytes, 80 bytes less than before`. A pack
+    //! can be larger than the objects it replaced, since it has headers and little to compress
+    match size.cmp(&previous_size) {
+        Ordering::Less => format!("{} bytes, {} bytes less than before", size, previous_size - size),
+        Ordering::Equal => format!("{} bytes, as before", size),
+        Ordering::Greater => format!("{} bytes, {} bytes more than before", size, size - previous_size),
     }
 }
 
-pub fn checkout(commit_id: &str) {
-    //! Edit the commit (branch) name in the HEAD file, and load the full directory of the commit
-    // TODO: catch all errors
-    match Repository::find(pathbuf_to_string(std::env::current_dir().unwrap()).as_str()) {
-        Some(repo) => {
-            let mut id = commit_id.to_owned();
-            if commit_id == "HEAD" {
-                match repo.read_head() {
-                    Ok(head_commit_id) => {
-                        id = head_commit_id;
-                    }
-                    Err(_) => return print_error("Could not find the HEAD commit")
-                }
+pub fn repack() -> Result<(), GoldfishError> {
+    //! Move all loose objects and existing packs into a single pack file
+    let repo = Repository::find_current()?;
+    match pack::repack(&repo, None).map_err(|e| e.with_context("Something went wrong packing objects"))? {
+        Some(summary) => print_output_string(format!(
+            "Packed {} objects ({} stored as deltas) into {}: {}",
+            summary.object_count, summary.delta_count, summary.pack_name, describe_size_change(summary.pack_size, summary.replaced_size)
+        )),
+        None => print_output("Nothing to pack"),
+    }
+    Ok(())
+}
+
+pub fn fsck() -> Result<(), GoldfishError> {
+    //! Check that every object hashes to its id and that nothing reachable from a ref is missing,
+    //! and print the problems found. Fails if an object is missing or corrupt
+    let repo = Repository::find_current()?;
+    let report = fsck::check(&repo)?;
+    print_output_vec_string(report.missing.iter().chain(&report.corrupt).chain(&report.dangling).cloned().collect());
+    print_output_string(format!(
+        "Checked {} objects: {} missing, {} corrupt, {} dangling",
+        report.object_count, report.missing.len(), report.corrupt.len(), report.dangling.len()
+    ));
+    if !report.is_ok() {
+        return Err(GoldfishError::CorruptObject(String::from("The repository has missing or corrupt objects")));
+    }
+    Ok(())
+}
+
+pub fn gc(dry_run: bool) -> Result<(), GoldfishError> {
+    //! Delete the objects that nothing reachable from a ref, the tracked files or a conflict uses,
+    //! once they are older than the `gc.gracePeriod` setting (in days), and pack the other objects.
+    //! With `dry_run`, only list the objects that would be deleted
+    let repo = Repository::find_current()?;
+    let grace_period = repo.get_setting::<usize>(config::GC_GRACE_PERIOD)?.unwrap_or(config::DEFAULT_GC_GRACE_PERIOD);
+    // walking from damaged objects would miss what they point to, so nothing is deleted
+    let report = fsck::check(&repo)?;
+    if !report.is_ok() {
+        return Err(GoldfishError::CorruptObject(String::from(
+            "The repository has missing or corrupt objects, run `fsck` to list them. Nothing was removed",
+        )));
+    }
+    let cutoff = SystemTime::now() - Duration::from_secs(grace_period as u64 * 24 * 60 * 60);
+    let mut pruned: Vec<(String, String)> = vec![];
+    let mut pruned_size = 0;
+    let mut recent: Vec<(String, String, SystemTime)> = vec![];
+    let mut keep: HashSet<(String, String)> = HashSet::new();
+    for object_dir in [model::COMMITS_DIR, model::TREES_DIR, model::BLOBS_DIR] {
+        let ids = repo.list_objects(object_dir).map_err(|e| GoldfishError::io(format!("Fail to list the {}", object_dir), e))?;
+        for id in ids {
+            let key = (object_dir.to_string(), id);
+            if report.reachable.contains(&key) {
+                keep.insert(key);
+                continue;
             }
-            // get the Commit associated with the given commit_id
-            match Commit::get(&repo, id.as_str()) {
-                Some(commit) => {
-                    match commit.checkout() {
-                        Ok(_) => {}
-                        Err(err) => return print_error(err.as_str())
-                    }
-                }
-                None => return print_error("Invalid commit_id"),
+            let (size, modified) = repo.get_object_stats(object_dir, key.1.as_str())
+                .map_err(|e| GoldfishError::io(format!("Fail to read object {}", key.1), e))?;
+            if modified <= cutoff {
+                pruned_size += size;
+                pruned.push(key);
+            } else {
+                recent.push((key.0, key.1, modified));
             }
-            print_output_string(format!("Checked out commit {}", id))
         }
-        None => return print_error("Not a Goldfish folder"),
     }
+    let kept_message = if recent.is_empty() {
+        String::new()
+    } else {
+        format!(" ({} unreachable objects newer than {} days kept)", recent.len(), grace_period)
+    };
+
+    if dry_run {
+        for (object_dir, id) in &pruned {
+            print_output_string(format!("Would remove {} {}", model::get_object_kind(object_dir), id));
+        }
+        print_output_string(format!("Would remove {} unreachable objects, reclaiming {} bytes{}", pruned.len(), pruned_size, kept_message));
+        return Ok(());
+    }
+    if pruned.is_empty() {
+        print_output_string(format!("Nothing to remove{}", kept_message));
+        return Ok(());
+    }
+    // recent unreachable objects are left out of the pack and written back as loose objects with
+    // their time, so that their grace period does not start again
+    let mut recent_objects = vec![];
+    for (object_dir, id, modified) in recent {
+        let content = repo.read_object(object_dir.as_str(), id.as_str())?;
+        recent_objects.push((object_dir, id, modified, content));
+    }
+    let summary = pack::repack(&repo, Some(&keep)).and_then(|summary| {
+        for (object_dir, id, modified, content) in &recent_objects {
+            repo.write_object(object_dir, id, content)?;
+            set_modified_time(repo.get_object_path(object_dir, id).as_str(), *modified)?;
+        }
+        Ok(summary)
+    });
+    let summary = summary.map_err(|e| e.with_context("Something went wrong packing objects"))?;
+    let (pack_size, replaced_size) = summary.map_or((0, pruned_size), |summary| (summary.pack_size, summary.replaced_size));
+    print_output_string(format!(
+        "Removed {} unreachable objects and packed the other {}: the objects now take {}{}",
+        pruned.len(), keep.len(), describe_size_change(pack_size, replaced_size), kept_message
+    ));
+    Ok(())
+}
+
+pub fn branch(branch_name: &str) -> Result<(), GoldfishError> {
+    //! Create a new branch pointing at the current commit
+    let repo = Repository::find_current()?;
+    if !model::is_valid_branch_name(branch_name) {
+        return Err(GoldfishError::Invalid(format!("Invalid branch name: {}", branch_name)));
+    }
+    if repo.branch_exists(branch_name) {
+        return Err(GoldfishError::Invalid(format!("Branch {} already exists", branch_name)));
+    }
+    let commit_id = repo.get_current_commit_id()
+        .map_err(|e| e.with_context("Something went wrong reading the current commit id"))?;
+    if commit_id.is_empty() {
+        return Err(GoldfishError::Invalid(String::from("Cannot create a branch before the first commit")));
+    }
+    repo.write_branch(branch_name, commit_id.as_str())?;
+    print_output_string(format!("Created branch {} at commit {}", branch_name, commit_id));
+    Ok(())
+}
+
+pub fn list_branches() -> Result<(), GoldfishError> {
+    //! Print all branches and the commit each of them points to
+    let repo = Repository::find_current()?;
+    let branches = repo.list_branches()?;
+    if branches.is_empty() {
+        print_output("No branches");
+        return Ok(());
+    }
+    let current_branch = repo.get_current_branch();
+    for branch_name in branches {
+        let marker = if current_branch.as_ref() == Some(&branch_name) { "*" } else { " " };
+        let commit_id = repo.read_branch(branch_name.as_str())?;
+        print_output_string(format!("{} {} {}", marker, branch_name, commit_id));
+    }
+    Ok(())
+}
+
+pub fn delete_branch(branch_name: &str) -> Result<(), GoldfishError> {
+    let repo = Repository::find_current()?;
+    if !model::is_valid_branch_name(branch_name) || !repo.branch_exists(branch_name) {
+        return Err(GoldfishError::Invalid(format!("Branch {} not found", branch_name)));
+    }
+    if repo.get_current_branch().as_deref() == Some(branch_name) {
+        return Err(GoldfishError::Invalid(format!("Cannot delete branch {} while it is checked out", branch_name)));
+    }
+    repo.delete_branch(branch_name)?;
+    print_output_string(format!("Deleted branch {}", branch_name));
+    Ok(())
 }
 
 
 
 
 //returns a Hashmap mapping filename to ChangeBin
-pub fn commit_diff<'b>(a: &'b Commit, b: &'b Commit, repo: &Repository) -> Option<HashMap<String, ChangeBin>> {
-    fn get_diff_files(tracked_file_list1: &HashMap<String, String>,
-                        tracked_file_list2: &HashMap<String, String>) -> Vec<String> {
-        let mut result: Vec<String> = vec![];
-        for (file_path1, _) in tracked_file_list1 {
-            let mut is_file_in: bool = false;
-            for (file_path2, _) in tracked_file_list2 {
-                if file_path1 == file_path2 {
-                    is_file_in = true;
-                    break;
-                }
-            }
-            if !is_file_in {
-                result.push(file_path1.to_string());
-            }
+//files with the same blob in both commits get an empty line list
+pub fn commit_diff<'b>(a: &'b Commit, b: &'b Commit, repo: &Repository) -> Result<HashMap<String, ChangeBin>, GoldfishError> {
+    // read a blob as a list of lines, or as no lines and true for a binary file
+    fn load_lines(repo: &Repository, blob_id: &str) -> Result<(Vec<String>, bool), GoldfishError> {
+        let content = Blob::get(repo, blob_id)?.get_blob_content()?;
+        if utilities::is_binary(&content) {
+            return Ok((vec![], true));
         }
-        result
+        Ok((utilities::split_lines(&content), false))
     }
 
-
     let mut result : HashMap<String, ChangeBin> = HashMap::new();
-    // Takes in two commit hashes and use the `display` module to print out the changes
-    // between the two files
-
-    // load all the files of the commits
-    match a.load_tracked_files() {
-        Some(tracked_file_list1) => {
-            match b.load_tracked_files() {
-                Some(tracked_file_list2) => {
-                    let added_files = get_diff_files(&tracked_file_list2, &tracked_file_list1);
-                    let removed_files = get_diff_files(&tracked_file_list1, &tracked_file_list2);
-
-
-                    for file in added_files {
-                        result.insert(file, ChangeBin::create(
-                            String::from("+"),
-                            vec![],
-                        ));
-                    }
-
-
-                    for file in removed_files{
-                        result.insert(file, ChangeBin::create(
-                            String::from("-"),
-                            vec![],
-                        ));
-                    }
-
-
-
-                    for (file_path1, blob_id1) in &tracked_file_list1 {
-                        let mut is_file_in: bool = false;
-                        let mut blob_id2: String = "".to_string();
-                        for (file_path2, temp_blob_id) in &tracked_file_list2 {
-                            if file_path1 == file_path2 {
-                                is_file_in = true;
-                                blob_id2 = temp_blob_id.to_string();
-                                break;
-                            }
-                        }
-                        if is_file_in {
-                            match Blob::get(&repo, blob_id1.as_str()) {
-                                Some(blob1) => {
-                                    match Blob::get(&repo, blob_id2.as_str()) {
-                                        Some(blob2) => {
-                                            match blob1.get_blob_content() {
-                                                Ok(content1) => {
-                                                    match blob2.get_blob_content() {
-                                                        Ok(content2) => {
-                                                            let file_vec1: Vec<String> = content1.lines().collect::<Vec<&str>>()
-                                                                                            .iter().map(|s| s.to_string()).collect();
-                                                            let file_vec2: Vec<String> = content2.lines().collect::<Vec<&str>>()
-                                                                                            .iter().map(|s| s.to_string()).collect();
-                                                            let diff_content = utilities::diff(file_vec1, file_vec2);
-                                                            result.insert(file_path1.to_string(), ChangeBin::create(
-                                                                String::from("="),
-                                                                diff_content,
-                                                            ));
-                                                        }
-                                                        Err(_) => (),
-                                                    }
-                                                }
-                                                Err(_) => (),
-                                            }
-                                        }
-                                        None => (),
-                                    }
-
-                                }
-                                None => (),
-                            }
-                        }
-                    }
-                }
-                None => (),
+    let tracked_file_list1 = a.load_tracked_files()?;
+    let tracked_file_list2 = b.load_tracked_files()?;
+
+    for (file_path1, blob_id1) in &tracked_file_list1 {
+        match tracked_file_list2.get(file_path1) {
+            Some(blob_id2) if blob_id1 == blob_id2 => {
+                result.insert(file_path1.to_string(), ChangeBin::create(
+                    String::from("="),
+                    vec![],
+                    Some(blob_id1.to_string()),
+                    Some(blob_id2.to_string()),
+                    false,
+                ));
+            }
+            Some(blob_id2) => {
+                let (file_vec1, binary1) = load_lines(repo, blob_id1)?;
+                let (file_vec2, binary2) = load_lines(repo, blob_id2)?;
+                let binary = binary1 || binary2;
+                let diff_content = if binary { vec![] } else { utilities::diff(file_vec1, file_vec2) };
+                result.insert(file_path1.to_string(), ChangeBin::create(
+                    String::from("="),
+                    diff_content,
+                    Some(blob_id1.to_string()),
+                    Some(blob_id2.to_string()),
+                    binary,
+                ));
+            }
+            None => {
+                let (file_vec1, binary) = load_lines(repo, blob_id1)?;
+                result.insert(file_path1.to_string(), ChangeBin::create(
+                    String::from("-"),
+                    file_vec1.into_iter().map(|line| (String::from("-"), line)).collect(),
+                    Some(blob_id1.to_string()),
+                    None,
+                    binary,
+                ));
             }
         }
-        None => (),
     }
 
+    for (file_path2, blob_id2) in &tracked_file_list2 {
+        if !tracked_file_list1.contains_key(file_path2) {
+            let (file_vec2, binary) = load_lines(repo, blob_id2)?;
+            result.insert(file_path2.to_string(), ChangeBin::create(
+                String::from("+"),
+                file_vec2.into_iter().map(|line| (String::from("+"), line)).collect(),
+                None,
+                Some(blob_id2.to_string()),
+                binary,
+            ));
+        }
+    }
 
+    Ok(result)
+}
 
+// Helper functions for merge
+fn get_blob_content_as_vec(repo: &Repository, blob_id: &str) -> Result<Vec<String>, GoldfishError> {
+    //! The lines of a text blob, each with its own line ending, so that joining them gives back its bytes
+    let content = Blob::get(repo, blob_id)?.get_blob_content()
+        .map_err(|e| GoldfishError::io(format!("Fail to read blob {}", blob_id), e))?;
+    Ok(utilities::split_lines(&content))
+}
 
+// Characters of the lines delimiting the two versions of a conflicting region
+const CONFLICT_MARKER_OURS: &str = "<";
+const CONFLICT_MARKER_SEPARATOR: &str = "=";
+const CONFLICT_MARKER_THEIRS: &str = ">";
 
-
-
-
-    Some(result)
+fn get_conflict_marker_size(repo: &Repository) -> Result<usize, GoldfishError> {
+    //! Length of the conflict markers, from the `merge.markerSize` setting
+    Ok(repo.get_setting::<usize>(config::MERGE_MARKER_SIZE)?
+        .filter(|size| *size > 0)
+        .unwrap_or(config::DEFAULT_MERGE_MARKER_SIZE))
 }
 
-// Helper functions for merge
-fn get_blob_content_as_vec(repo: &Repository, blob_id: &str) -> Vec<String> {
-    match Blob::get(&repo, blob_id) {
-        Some(blob) => {
-            match blob.get_blob_content() {
-                Ok(content) => {
-                    return content.lines()
-                                .collect::<Vec<&str>>()
-                                .iter()
-                                .map(|s| s.to_string())
-                                .collect();
-                },
-                Err(_) => ()
-            }
-        },
-        None => ()
+fn get_files_with_conflict_markers(repo: &Repository) -> Result<Vec<String>, GoldfishError> {
+    //! List the files in staging that still contain conflict markers
+    let staging_path = repo.get_staging_path();
+    if !is_dir(staging_path.as_str()) {
+        return Ok(vec![]);
     }
-    return vec!();
+    let marker_size = get_conflict_marker_size(repo)?;
+    let ours_marker = format!("{} ", CONFLICT_MARKER_OURS.repeat(marker_size));
+    let theirs_marker = format!("{} ", CONFLICT_MARKER_THEIRS.repeat(marker_size));
+    let mut result: Vec<String> = list_files(staging_path.as_str(), true, &vec![])
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|file_path| match read_bytes(file_path.as_str()) {
+            Ok(content) => String::from_utf8_lossy(&content).lines().any(|line| {
+                line.starts_with(ours_marker.as_str()) || line.starts_with(theirs_marker.as_str())
+            }),
+            Err(_) => false,
+        })
+        .filter_map(|file_path| diff_path(staging_path.as_str(), file_path.as_str()))
+        .collect();
+    result.sort();
+    Ok(result)
+}
+
+fn add_line(result: &mut String, line: &str) {
+    result.push_str(line);
+    result.push('\n');
 }
 
-fn add_line(result: &mut String, line: &String) {
-    if result.len() != 0 {
-        result.push_str("\n");
+fn add_lines(result: &mut String, lines: &[String]) {
+    //! Add lines that keep their own line endings
+    for line in lines {
+        result.push_str(line);
     }
-    result.push_str(line.as_str());
 }
 
 fn create_conflict(
     result: &mut String,
-    blob1_content: &Vec<String>,
-    blob2_content: &Vec<String>,
+    blob1_content: &[String],
+    blob2_content: &[String],
     blob1_id: &str,
-    blob2_id: &str
+    blob2_id: &str,
+    marker_size: usize,
 ) {
-    if blob1_content.len() == 0 && blob2_content.len() == 0 {
-        return;
-    }
-    if blob1_content.len() == 0 {
-        for line in blob2_content {
-            add_line(result, line);
+    // a side whose last line has no newline gets one, so that the marker starts its own line
+    let add_side = |result: &mut String, lines: &[String]| {
+        add_lines(result, lines);
+        if lines.last().is_some_and(|line| !line.ends_with('\n')) {
+            result.push('\n');
         }
-    }
-    if blob2_content.len() == 0 {
-        for line in blob1_content {
-            add_line(result, line);
-        }
-    }
-    // conflict!
-    add_line(result, &format!("<<<<<<<<<< {}", blob1_id));
-    for line in blob1_content {
-        add_line(result, line);
-    }
-    add_line(result, &format!("===================="));
-    for line in blob2_content {
-        add_line(result, line);
-    }
-    add_line(result, &format!(">>>>>>>>>> {}", blob2_id));
+    };
+    add_line(result, &format!("{} {}", CONFLICT_MARKER_OURS.repeat(marker_size), blob1_id));
+    add_side(result, blob1_content);
+    add_line(result, &CONFLICT_MARKER_SEPARATOR.repeat(2 * marker_size));
+    add_side(result, blob2_content);
+    add_line(result, &format!("{} {}", CONFLICT_MARKER_THEIRS.repeat(marker_size), blob2_id));
 }
 
-fn merge_files(repo: &Repository, blob1_id: &str, blob2_id: &str, rev1_id: &str, rev2_id: &str) -> String {
-    let blob1 = get_blob_content_as_vec(repo, blob1_id);
-    let blob2 = get_blob_content_as_vec(repo, blob2_id);
-    let diff_content = utilities::diff(blob1, blob2);
-    let mut blob1_content: Vec<String> = vec!();
-    let mut blob2_content: Vec<String> = vec!();
+// Three-way merge of two versions of a text file against the version in the merge base
+// (None if the file did not exist there), returning the merged content and whether
+// it contains conflict markers. The versions must be UTF-8, and their line endings are kept
+fn merge_files(repo: &Repository, base_blob_id: Option<&str>, blob1_id: &str, blob2_id: &str, rev1_id: &str, rev2_id: &str) -> Result<(String, bool), GoldfishError> {
+    let base = match base_blob_id {
+        Some(blob_id) => get_blob_content_as_vec(repo, blob_id)?,
+        None => vec![],
+    };
+    let blob1 = get_blob_content_as_vec(repo, blob1_id)?;
+    let blob2 = get_blob_content_as_vec(repo, blob2_id)?;
+    let marker_size = get_conflict_marker_size(repo)?;
     let mut result: String = String::from("");
-    for (typ, content) in diff_content {
-        if typ == "-" {
-            blob1_content.push(content);
-        } else if typ == "+" {
-            blob2_content.push(content);
-        } else {
-            create_conflict(&mut result, &blob1_content, &blob2_content, rev1_id, rev2_id);
-            add_line(&mut result, &content);
-            blob1_content.clear();
-            blob2_content.clear();
+    let mut conflicted = false;
+    for chunk in utilities::merge3(&base, &blob1, &blob2) {
+        match chunk {
+            MergeChunk::Clean(lines) => add_lines(&mut result, &lines),
+            MergeChunk::Conflict { ours, theirs, .. } => {
+                create_conflict(&mut result, &ours, &theirs, rev1_id, rev2_id, marker_size);
+                conflicted = true;
+            }
         }
     }
-    create_conflict(&mut result, &blob1_content, &blob2_content, rev1_id, rev2_id);
-    return result;
+    Ok((result, conflicted))
 }
-// End of helper functions for merge
 
-pub fn merge(commit: &str) {
-    if check_status() == Some(true){
-    match Repository::find(pathbuf_to_string(std::env::current_dir().unwrap()).as_str()) {
-        Some(repo) => {
-            match Commit::get(&repo, repo.get_current_commit_id().unwrap().as_str()){
-                Some(current) => {
-                    match Commit::get(&repo, commit){
-                        Some(update) => {
-
-                            if current.get_lowest_common_parent_with(&update).unwrap().get_id() == update.get_id(){
-                                match update.checkout() {
-                                    Ok(_) => return (),
-                                    Err(_) => return print_error("Something went wrong when checking out"),
-                                }
-                            }
-
-                            match update.load_tracked_files(){
-                                Some(tracked_file_list2) => {
-                                    match current.load_tracked_files(){
-                                        Some(tracked_file_list1) => {
-                                            match commit_diff(&current, &update, &repo){
-                                                Some(map) => {
-                                                    for(file, diff_list) in map.iter(){
-                                                        match diff_list.get_tag() {
-                                                            "-" => { }
-                                                            "+" => {
-                                                                for(file_path2, blob_id2) in &tracked_file_list2{
-                                                                    if file == file_path2{
-                                                                        let content = Blob::get(&repo, blob_id2).unwrap().get_blob_content().unwrap();
-                                                                        match filesystem::write_file(
-                                                                            content.as_str(),
-                                                                            filesystem::join_path(
-                                                                                vec![repo.get_working_path(), file.as_str()]
-                                                                            ).as_str()
-                                                                        ) {
-                                                                            Ok(_) => return (),
-                                                                            Err(_) => return print_error("Something went wrong writing files"),
-                                                                        }
-                                                                    }
-                                                                }
-                                                            }
-                                                            "=" => {
-                                                                for(file_path1, blob_id1) in &tracked_file_list1{
-                                                                    for(file_path2, blob_id2) in &tracked_file_list2{
-                                                                        if file == file_path1 && file == file_path2{
-                                                                            match filesystem::write_file(
-                                                                                    merge_files(&repo, blob_id1.as_str(), blob_id2.as_str(), current.get_id().as_str(), update.get_id().as_str()).as_str(),
-                                                                                    filesystem::join_path(
-                                                                                        vec![repo.get_working_path(), file]
-                                                                                    ).as_str()
-                                                                            ) {
-                                                                                Ok(_) => return (),
-                                                                                Err(_) => return print_error("Something went wrong writing files"),
-                                                                            }
-                                                                        }
-                                                                    }
-                                                                }
-                                                            }
-                                                            _ => return print_error("Problem with file merge tag")
-                                                        }
-                                                    }
-                                                }
-                                                None => {}
-                                            }
-                                        }
-                                        None => {}
-                                    }
-                                }
-                                None => {}
-                            }
-                         }
-                        None => {}
-                    }
-                }
-                None => {}
-            }
+// Bring one file of the working directory to its merged state, given its blob id in
+// the merge base, the current commit and the merged commit (None where it does not
+// exist). Returns true if the file is left with a conflict
+fn merge_path(repo: &Repository, file_path: &str, base: Option<&String>, ours: Option<&String>, theirs: Option<&String>, rev1_id: &str, rev2_id: &str) -> Result<bool, GoldfishError> {
+    let working_file = filesystem::join_path(vec![repo.get_working_path(), file_path]);
+    let write_error = |e| GoldfishError::io(format!("Something went wrong writing {}", file_path), e);
+    let write_blob = |blob_id: &str| -> Result<(), GoldfishError> {
+        let content = Blob::get(repo, blob_id)?.get_blob_content()
+            .map_err(|e| GoldfishError::io(format!("Something went wrong reading the merged version of {}", file_path), e))?;
+        filesystem::write_file(content, working_file.as_str()).map_err(write_error)
+    };
+
+    // add the merged version of the file to staging, or untrack it if it was deleted
+    let stage = |deleted: bool| -> Result<(), GoldfishError> {
+        if deleted {
+            remove(filesystem::join_path(vec![repo.get_staging_path().as_str(), file_path]).as_str()).ok();
+            let mut tracked_files = repo.get_staging_tracked_files()?;
+            tracked_files.remove(file_path);
+            repo.save_staging_tracked_files(tracked_files)
+        } else {
+            copy_and_mark_fike_tracked(repo, working_file.as_str(), file_path)
         }
-        None => {}
+    };
+
+    // only one side changed the file (or both made the same change)
+    if ours == theirs || theirs == base {
+        return Ok(false);
     }
-    }else{
-        return print_error("Can't merge, files in staging or WD")
+    if ours == base {
+        match theirs {
+            Some(blob_id) => write_blob(blob_id)?,
+            None => remove(working_file.as_str())
+                .map_err(|e| GoldfishError::io(format!("Something went wrong removing {}", file_path), e))?,
+        }
+        stage(theirs.is_none())?;
+        return Ok(false);
     }
 
-
-
+    // both sides changed the file
+    match (ours, theirs) {
+        (Some(blob_id1), Some(blob_id2)) => {
+            // content that is not UTF-8 cannot be merged line by line without changing its bytes
+            let is_binary = |blob_id: &str| {
+                Blob::get(repo, blob_id)
+                    .ok()
+                    .and_then(|blob| blob.get_blob_content().ok())
+                    .is_some_and(|content| utilities::is_binary(&content) || std::str::from_utf8(&content).is_err())
+            };
+            if is_binary(blob_id1) || is_binary(blob_id2) || base.is_some_and(|blob_id| is_binary(blob_id)) {
+                print_output_string(format!("CONFLICT (binary): {} changed on both sides, keeping the current version", file_path));
+                return Ok(true);
+            }
+            let (content, conflicted) = merge_files(repo, base.map(|id| id.as_str()), blob_id1, blob_id2, rev1_id, rev2_id)?;
+            filesystem::write_file(content, working_file.as_str()).map_err(write_error)?;
+            if conflicted {
+                print_output_string(format!("CONFLICT (content): Merge conflict in {}", file_path));
+            } else {
+                print_output_string(format!("Auto-merged {}", file_path));
+                stage(false)?;
+            }
+            Ok(conflicted)
+        }
+        (Some(_), None) => {
+            print_output_string(format!("CONFLICT (modify/delete): {} deleted in {} and modified in {}", file_path, rev2_id, rev1_id));
+            Ok(true)
+        }
+        (None, Some(blob_id2)) => {
+            write_blob(blob_id2)?;
+            print_output_string(format!("CONFLICT (modify/delete): {} deleted in {} and modified in {}", file_path, rev1_id, rev2_id));
+            Ok(true)
+        }
+        (None, None) => Ok(false),
+    }
 }
+// End of helper functions for merge
 
+pub fn merge(commit: &str) -> Result<(), GoldfishError> {
+    //! Merge the given revision into HEAD: fast-forward if HEAD is one of its ancestors,
+    //! otherwise merge every file against the common ancestor of both commits and create a
+    //! merge commit. If there are conflicts, MERGE_HEAD records the merged commit so that the
+    //! next commit gets both parents
+    let repo = Repository::find_current()?;
+    if let Some(merge_head) = repo.read_merge_head() {
+        return Err(GoldfishError::Conflict(format!(
+            "A merge of {} is in progress. Resolve the conflicts and commit first",
+            merge_head
+        )));
+    }
+    if check_status(&repo)? {
+        return Err(GoldfishError::Conflict(String::from("Can't merge, files in staging or WD")));
+    }
+    // refuse to start if the conflict markers cannot be written
+    get_conflict_marker_size(&repo)?;
+    let revision = commit;
+    let current = match repo.get_current_commit_id() {
+        Ok(id) if !id.is_empty() => Commit::get(&repo, id.as_str())?,
+        _ => return Err(GoldfishError::Invalid(String::from("Nothing to merge into, the current branch has no commits yet"))),
+    };
+    let commit = resolve_revision(&repo, commit)?;
+    let update = Commit::get(&repo, commit.as_str())?;
+    let base = current.get_lowest_common_parent_with(&update)
+        .ok_or_else(|| GoldfishError::Invalid(String::from("The two commits have no common ancestor")))?;
 
+    if base.get_id() == update.get_id() {
+        print_output("Already up to date");
+        return Ok(());
+    }
+    if base.get_id() == current.get_id() {
+        update.checkout(false)?;
+        repo.update_head(update.get_id().as_str())?;
+        print_output_string(format!("Fast-forward to {}", update.get_id()));
+        return Ok(());
+    }
 
+    let (base_files, current_files, update_files) = (
+        base.load_tracked_files()?,
+        current.load_tracked_files()?,
+        update.load_tracked_files()?,
+    );
+    let mut file_paths: Vec<&String> = base_files.keys()
+        .chain(current_files.keys())
+        .chain(update_files.keys())
+        .collect();
+    file_paths.sort();
+    file_paths.dedup();
 
+    let mut conflicts: Vec<UnmergedPath> = vec![];
+    for file_path in file_paths {
+        let conflicted = merge_path(
+            &repo,
+            file_path,
+            base_files.get(file_path),
+            current_files.get(file_path),
+            update_files.get(file_path),
+            current.get_id().as_str(),
+            update.get_id().as_str(),
+        )?;
+        if conflicted {
+            conflicts.push(UnmergedPath::new(
+                file_path,
+                base_files.get(file_path),
+                current_files.get(file_path),
+                update_files.get(file_path),
+            ));
+        }
+    }
+    if !conflicts.is_empty() {
+        repo.write_merge_head(update.get_id().as_str())?;
+        repo.save_unmerged_paths(&conflicts)?;
+        return Err(GoldfishError::Conflict(format!(
+            "Automatic merge failed in {} file(s); fix the conflicts, add the files and run `merge --continue`",
+            conflicts.len()
+        )));
+    }
+    let commit_id = create_commit(&repo, format!("Merge {}", revision).as_str(), vec![update.get_id()])?;
+    print_output_string(format!("Created merge commit: {}", commit_id));
+    Ok(())
+}
 
-
-pub fn push(url: &str) {
-    match Repository::find(pathbuf_to_string(std::env::current_dir().unwrap()).as_str()) {
-        Some(repo) => {
-            networking::rsync(repo.get_repo_path(), url);
+pub fn merge_abort() -> Result<(), GoldfishError> {
+    //! Give up on a merge that stopped on conflicts and restore the files of HEAD
+    let repo = Repository::find_current()?;
+    if repo.read_merge_head().is_none() {
+        return Err(GoldfishError::Invalid(String::from("There is no merge to abort")));
+    }
+    let current = Commit::get(&repo, repo.get_current_commit_id()?.as_str())?;
+    let head_files = current.load_tracked_files()?;
+    // remove the files the merge brought in, the others are restored by the checkout
+    let staging_files = repo.get_staging_tracked_files()?;
+    let unmerged_paths = repo.get_unmerged_paths()?;
+    let merged_paths = staging_files.keys().map(|path| path.as_str())
+        .chain(unmerged_paths.iter().map(|unmerged_path| unmerged_path.get_path()));
+    for file_path in merged_paths {
+        if !head_files.contains_key(file_path) {
+            remove(filesystem::join_path(vec![repo.get_working_path(), file_path]).as_str()).ok();
         }
-        None => print_error("Not a repository")
     }
+    if is_dir(repo.get_staging_path().as_str()) {
+        remove(repo.get_staging_path().as_str())
+            .map_err(|e| GoldfishError::io("Something went wrong cleaning the staging area", e))?;
+    }
+    current.checkout(true)?;
+    repo.clear_merge_head()?;
+    repo.save_unmerged_paths(&[])?;
+    print_output_string(format!("Merge aborted, back to {}", current.get_id()));
+    Ok(())
+}
 
+pub fn merge_continue() -> Result<(), GoldfishError> {
+    //! Create the merge commit once every conflict of the merge is resolved and added
+    let repo = Repository::find_current()?;
+    if repo.read_merge_head().is_none() {
+        return Err(GoldfishError::Invalid(String::from("There is no merge in progress")));
+    }
+    commit("")
 }
 
-pub fn pull(url: &str) {
-    // Use `networking` to make a pull request to a different
-    // dvcs server
-    if check_status().is_none() {
-        return print_error("Cannot pull. Working directory isn't clean or existed.");
-    }
-    let og_dir: String = std::env::current_dir().unwrap().to_str().unwrap().to_string();
-    if try_clone(url).is_some() {
-        match std::env::set_current_dir(&og_dir) {
-            Ok(_) => {},
-            Err(_) => return print_error("Something went wrong. Please try again"),
-        }
-        match remove(".goldfish_temp") {
-            Ok(_) => {},
-            Err(_) => {},
-        }
-        networking::rsync(url, ".goldfish");
-        match Repository::find(".goldfish") {
-            Some(repo) => {
-                match repo.read_head() {
-                    Ok(head_id) => {
-                        match Commit::get(&repo, head_id.as_str()) {
-                            Some(commit) => {
-                                match commit.checkout() {
-                                    Ok(_) => return print_output("Pull successfully!"),
-                                    Err(_) => {}
-                                }
-                            }
-                            None => {}
-                        }
-                    }
-                    Err(_) => {}
-                }
+pub fn push(remote: Option<&str>, force: bool) -> Result<(), GoldfishError> {
+    //! Upload the objects a remote (by default the upstream of the current branch) is missing,
+    //! and move its branch named like the current branch to the current commit. Unless forced,
+    //! the remote branch is only moved forward: its commit must be an ancestor of the current commit
+    let repo = Repository::find_current()?;
+    let branch = repo.get_current_branch()
+        .ok_or_else(|| GoldfishError::Invalid(String::from("HEAD is detached. Check out a branch to push it")))?;
+    let commit_id = repo.get_current_commit_id()?;
+    if commit_id.is_empty() {
+        return Err(GoldfishError::Invalid(String::from("Nothing to push, the current branch has no commits yet")));
+    }
+    let (remote, upstream_branch) = find_remote(&repo, remote).map_err(|e| e.with_context("Cannot push"))?;
+    let remote_branch = upstream_branch.unwrap_or(branch.to_owned());
+    // None if the remote branch is already at the current commit
+    let pushed = networking::Connection::open(remote.get_url()).and_then(|mut connection| {
+        let remote_id = connection.get_branches().iter()
+            .find(|(name, _)| *name == remote_branch)
+            .map(|(_, remote_id)| remote_id.to_owned());
+        let count = match remote_id {
+            Some(remote_id) if remote_id == commit_id => None,
+            Some(remote_id) if !force && !repo.is_ancestor(remote_id.as_str(), commit_id.as_str()) => {
+                connection.close()?;
+                return Err(GoldfishError::Conflict(format!(
+                    "Rejected {} -> {} (non-fast-forward): the remote branch has commits that the current branch does not have",
+                    branch, remote_branch
+                )));
             }
-            None => {}
-        }
-    } else {
-        match std::env::set_current_dir(&og_dir) {
-            Ok(_) => {},
-            Err(_) => return print_error("Something went wrong. Please try again"),
-        }
-        match remove(".goldfish_temp") {
-            Ok(_) => {},
-            Err(_) => {},
-        }
-        return print_error("Failed to pull the content from specified URL/address");
+            _ => match connection.push(&repo, &[(remote_branch.clone(), commit_id.clone())], force) {
+                // the session is still open after the remote refused the update
+                Err(e @ GoldfishError::Conflict(_)) => {
+                    connection.close()?;
+                    return Err(e);
+                }
+                result => Some(result?),
+            },
+        };
+        connection.close()?;
+        Ok(count)
+    });
+    let pushed = pushed.map_err(|e| match e {
+        GoldfishError::Conflict(message) if message.contains("(non-fast-forward)") => GoldfishError::Conflict(format!(
+            "{}\nhint: Pull them first (`pull`), then push again\nhint: or use `push --force` to overwrite the remote branch and lose those commits",
+            message
+        )),
+        e => e,
+    });
+    let count = pushed.map_err(|e| e.with_context(format!("Cannot push to {}", remote.get_url())))?;
+    // the remote-tracking branch now knows where the remote branch is
+    if let Some((tracking_remote, tracking_branch)) = remote.map_branch(remote_branch.as_str()) {
+        repo.write_remote_branch(tracking_remote.as_str(), tracking_branch.as_str(), commit_id.as_str())?;
+    }
+    match count {
+        Some(count) => print_output_string(format!("Pushed {} object(s); {} -> {}", count, remote_branch, commit_id)),
+        None => print_output("Everything up to date"),
     }
+    Ok(())
 }
 
-fn try_clone(url: &str) -> Option<bool> {
-    match create_dir(".goldfish_temp") {
-        Ok(_) => {},
-        Err(_) => return None,
-    }
-    match std::env::set_current_dir(format!("{}{}", std::env::current_dir().unwrap().display(), "/.goldfish_temp")) {
-        Ok(_) => {},
-        Err(_) => return None,
-    }
-    let download_succeeded = networking::rsync(url, ".goldfish");
-    if download_succeeded {
-        match Repository::find(".goldfish") {
-            Some(repo) => {
-                match repo.read_head() {
-                    Ok(head_id) => {
-                        match Commit::get(&repo, head_id.as_str()) {
-                            Some(commit) => {
-                                match commit.checkout() {
-                                    Ok(_) => return Some(true),
-                                    Err(_) => return None,
-                                }
-                            }
-                            None => return None
-                        }
-                    }
-                    Err(_) => return None
-                }
-            }
-            None => None
-        }
-    } else {
-        return None;
+pub fn pull(remote: Option<&str>) -> Result<(), GoldfishError> {
+    //! Fetch from a remote (by default the upstream of the current branch), then merge its
+    //! branch of the same name (or the upstream branch) into the current branch
+    let repo = Repository::find_current()?;
+    if check_status(&repo)? {
+        return Err(GoldfishError::Conflict(String::from("Cannot pull. Working directory isn't clean")));
+    }
+    let branch = repo.get_current_branch()
+        .ok_or_else(|| GoldfishError::Invalid(String::from("HEAD is detached. Check out a branch to pull into it")))?;
+    let (remote, upstream_branch) = find_remote(&repo, remote).map_err(|e| e.with_context("Failed to pull"))?;
+    let (_, remote_branches, report) = fetch_remote(&repo, &remote)
+        .map_err(|e| e.with_context(format!("Failed to pull from {}", remote.get_url())))?;
+    print_output_vec_string(report);
+    let remote_branch = upstream_branch.unwrap_or(branch);
+    let commit_id = match remote_branches.iter().find(|(name, _)| *name == remote_branch) {
+        Some((_, commit_id)) => commit_id.to_owned(),
+        None => return Err(GoldfishError::UnknownRevision(format!("{} (the remote repository has no such branch to merge)", remote_branch))),
+    };
+    // merge the remote-tracking branch if there is one, so the merge commit names it
+    let revision = match remote.map_branch(remote_branch.as_str()) {
+        Some((tracking_remote, tracking_branch)) => format!("{}/{}", tracking_remote, tracking_branch),
+        None => commit_id.to_owned(),
+    };
+
+    // a branch without commits yet simply starts at the remote commit
+    if repo.get_current_commit_id().is_ok_and(|id| id.is_empty()) {
+        Commit::get(&repo, commit_id.as_str())?.checkout(false)?;
+        repo.update_head(commit_id.as_str())?;
+        print_output_string(format!("Fast-forward to {}", commit_id));
+        return Ok(());
     }
+    merge(revision.as_str())
 }
 
-fn copy_and_mark_fike_tracked(repo: &Repository, abs_path: &str, rel_path_to_wd: &str) {
+pub fn serve(path: &str) -> Result<(), GoldfishError> {
+    //! Answer a remote goldfish on stdin and stdout for the repository at the path.
+    //! Stdout belongs to the protocol, so errors only go to stderr and the exit status
+    networking::serve(path)
+}
+
+fn copy_and_mark_fike_tracked(repo: &Repository, abs_path: &str, rel_path_to_wd: &str) -> Result<(), GoldfishError> {
+    // adding a conflicted file marks it as resolved
+    repo.resolve_unmerged_path(rel_path_to_wd)?;
     // if file hasn't changed, don't add it
-    match repo.get_file_content_hash(rel_path_to_wd) {
-        Some(hash) => {
-            let file_content = read_file(abs_path).unwrap();
-            let file_content_hash = utilities::hash(file_content.as_str());
-            if file_content_hash == hash {
-                return;
-            }
+    if let Some(hash) = repo.get_file_content_hash(rel_path_to_wd) {
+        let file_content = read_bytes(abs_path).map_err(|e| GoldfishError::io(format!("Fail to read {}", abs_path), e))?;
+        if utilities::hash(&file_content) == hash {
+            return Ok(());
         }
-        None => (),
     }
     // copy file to staging
-    match copy(
-        abs_path,
-        Path::new(repo.get_staging_path().as_str())
-            .join(&rel_path_to_wd)
-            .to_str()
-            .unwrap(),
-    ) {
-        Ok(_v) => (),
-        Err(_e) => return print_error(format!("Fail to add {}", abs_path).as_str()),
-    }
+    copy(abs_path, join_path(vec![repo.get_staging_path().as_str(), rel_path_to_wd]).as_str())
+        .map_err(|e| GoldfishError::io(format!("Fail to add {}", abs_path), e))?;
     // add/update file in tracked list
-    match repo.track_file(rel_path_to_wd) {
-        Some(e) => return print_error(e.as_str()),
-        None => (),
-    }
+    repo.track_file(abs_path)
 }
 
-pub fn add_track_file(path: &str) {
+pub fn add_track_file(path: &str) -> Result<(), GoldfishError> {
+    let repo = Repository::find_current()?;
     // sanity check
-    match Repository::find(pathbuf_to_string(std::env::current_dir().unwrap()).as_str()) {
-        Some(repo) => {
-            if !is_file(path) && !is_dir(path) {
-                match repo.read_head() {
-                    Ok(head_id) => {
-                        match Commit::get(&repo, head_id.as_str()) {
-                            Some(commit) => {
-                                match commit.load_tracked_files() {
-                                    Some(files_lookup) => {
-                                        for (committed_file, _blob_id) in &files_lookup {
-                                            if compare_paths(committed_file, path) {
-                                                // delete file from track file
-                                            }
-                                        }
-                                    }
-                                    None => return print_error("Something went wrong loading the HEAD commit")
-                                }
-                            }
-                            None => return print_output("Something went wrong loading the HEAD commit")
-                        }
-                    }
-                    Err(_) => return print_output("Something went wrong leading the HEAD commit")
-                }
-                return print_error(format!("{} did not match any file or folder", path).as_str());
-            } else {
-                let abs_path = pathbuf_to_string(get_absolute_path(path));
-                let rel_path_to_wd =
-                    get_relative_path_from_base(repo.get_working_path(), abs_path.as_str());
-                if !is_dir(path) {
-                    copy_and_mark_fike_tracked(&repo, abs_path.as_str(), rel_path_to_wd.as_str());
-                } else {
-                    match list_files(abs_path.as_str(), true, &vec![repo.get_repo_path()]) {
-                        Ok(files) => {
-                            for file_path in files {
-                                let abs_path = pathbuf_to_string(get_absolute_path(file_path.as_str()));
-                                let rel_path_to_wd =
-                                    get_relative_path_from_base(repo.get_working_path(), abs_path.as_str());
-                                copy_and_mark_fike_tracked(
-                                    &repo,
-                                    abs_path.as_str(),
-                                    rel_path_to_wd.as_str(),
-                                );
-                            }
-                        }
-                        Err(_e) => (),
-                    }
-                }
-            }
+    if !is_file(path) && !is_dir(path) {
+        return Err(GoldfishError::Invalid(format!("{} did not match any file or folder", path)));
+    }
+    let abs_path = pathbuf_to_string(get_absolute_path(path));
+    let rel_path_to_wd =
+        get_relative_path_from_base(repo.get_working_path(), abs_path.as_str());
+    if !is_dir(path) {
+        return copy_and_mark_fike_tracked(&repo, abs_path.as_str(), rel_path_to_wd.as_str());
+    }
+    let mut rules = IgnoreRules::load(repo.get_working_path(), abs_path.as_str());
+    let (mut files, _ignored) = rules.list_unignored_files(abs_path.as_str(), &vec![repo.get_repo_path()])
+        .map_err(|e| GoldfishError::io(format!("Fail to list the files in {}", path), e))?;
+    // tracked files are added even if they are ignored
+    let rel_dir = format!("{}/", rel_path_to_wd);
+    let listed: HashSet<String> = files
+        .iter()
+        .map(|file_path| get_relative_path_from_base(repo.get_working_path(), file_path.as_str()))
+        .collect();
+    for tracked_path in repo.get_staging_tracked_files()?.keys() {
+        let file_path = join_path(vec![repo.get_working_path(), tracked_path]);
+        if (rel_path_to_wd.is_empty() || tracked_path.starts_with(rel_dir.as_str()))
+            && !listed.contains(tracked_path)
+            && is_file(file_path.as_str())
+        {
+            files.push(file_path);
         }
-        None => print_error("Not a Goldfish folder"),
     }
+    for file_path in files {
+        let abs_path = pathbuf_to_string(get_absolute_path(file_path.as_str()));
+        let rel_path_to_wd =
+            get_relative_path_from_base(repo.get_working_path(), abs_path.as_str());
+        copy_and_mark_fike_tracked(
+            &repo,
+            abs_path.as_str(),
+            rel_path_to_wd.as_str(),
+        )?;
+    }
+    Ok(())
 }
 
-pub fn delete_track_file(path: &str) {
+pub fn delete_track_file(path: &str) -> Result<(), GoldfishError> {
     // sanity check
     if !is_file(path) && !is_dir(path) {
-        return print_error(format!("{} did not match any file or folder", path).as_str());
-    }
-    match Repository::find(pathbuf_to_string(std::env::current_dir().unwrap()).as_str()) {
-        Some(repo) => {
-            let abs_path = pathbuf_to_string(get_absolute_path(path));
-            let rel_path_to_wd =
-                get_relative_path_from_base(repo.get_working_path(), abs_path.as_str());
-            match remove(
-                Path::new(repo.get_staging_path().as_str())
-                    .join(&rel_path_to_wd.as_str())
-                    .to_str()
-                    .unwrap(),
-            ) {
-                Ok(_v) => (),
-                Err(_e) => (),
-            }
-            if !is_dir(path) {
-                match repo.untrack_file(rel_path_to_wd.as_str()) {
-                    Some(e) => return print_error(e.as_str()),
-                    None => (),
-                }
-            } else {
-                match list_files(abs_path.as_str(), true, &vec![repo.get_repo_path()]) {
-                    Ok(files) => {
-                        for file_path in files {
-                            let abs_path = pathbuf_to_string(get_absolute_path(file_path.as_str()));
-                            let rel_path_to_wd =
-                                get_relative_path_from_base(repo.get_working_path(), abs_path.as_str());
-                            match repo.untrack_file(rel_path_to_wd.as_str()) {
-                                Some(e) => print_error(e.as_str()),
-                                None => (),
-                            }
-                        }
-                    }
-                    Err(_e) => (),
-                }
-            }
-        }
-        None => return print_error("Not a Goldfish folder"),
+        return Err(GoldfishError::Invalid(format!("{} did not match any file or folder", path)));
+    }
+    let repo = Repository::find_current()?;
+    let abs_path = pathbuf_to_string(get_absolute_path(path));
+    let rel_path_to_wd =
+        get_relative_path_from_base(repo.get_working_path(), abs_path.as_str());
+    // the file may have no staged copy
+    remove(join_path(vec![repo.get_staging_path().as_str(), rel_path_to_wd.as_str()]).as_str()).ok();
+    if !is_dir(path) {
+        repo.untrack_file(rel_path_to_wd.as_str())?;
+        // removing a conflicted file resolves the conflict by deleting it
+        return repo.resolve_unmerged_path(rel_path_to_wd.as_str());
+    }
+    let files = list_files(abs_path.as_str(), true, &vec![repo.get_repo_path()])
+        .map_err(|e| GoldfishError::io(format!("Fail to list the files in {}", path), e))?;
+    for file_path in files {
+        let abs_path = pathbuf_to_string(get_absolute_path(file_path.as_str()));
+        let rel_path_to_wd =
+            get_relative_path_from_base(repo.get_working_path(), abs_path.as_str());
+        repo.untrack_file(rel_path_to_wd.as_str())?;
+        repo.resolve_unmerged_path(rel_path_to_wd.as_str())?;
+    }
+    Ok(())
+}
+
+fn load_config_file(scope: &str) -> Result<Config, GoldfishError> {
+    //! The config file of a scope: `--system`, `--global` (the user's) or `--local` (the repository's)
+    match scope {
+        "--system" => Config::load(config::get_system_config_path().as_str()),
+        "--global" => Config::load(config::get_global_config_path().as_str()),
+        _ => match Repository::find_current() {
+            Ok(repo) => repo.load_config(),
+            Err(GoldfishError::NotARepository) => Err(GoldfishError::Invalid(String::from(
+                "Not a repository. Use --global or --system to change the user or system settings",
+            ))),
+            Err(e) => Err(e),
+        },
+    }
+}
+
+fn load_settings() -> Result<Settings, GoldfishError> {
+    //! The settings in effect in the current directory
+    match Repository::find_current() {
+        Ok(repo) => repo.load_settings(),
+        Err(GoldfishError::NotARepository) => Settings::load(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn config_get(scope: Option<&str>, key: &str) -> Result<(), GoldfishError> {
+    //! Print a setting from the config file of the scope, or else the one in effect
+    let value = match scope {
+        Some(scope) => load_config_file(scope)?.get(key).cloned(),
+        None => load_settings()?.get_value(key).cloned(),
+    };
+    let value = value.ok_or_else(|| GoldfishError::Config(format!("{} is not set", key)))?;
+    print_output_string(config::format_value(&value));
+    Ok(())
+}
+
+pub fn config_set(scope: Option<&str>, key: &str, value: &str) -> Result<(), GoldfishError> {
+    //! Change a setting in the config file of the scope, by default the repository's
+    let mut config = load_config_file(scope.unwrap_or("--local"))?;
+    config.set(key, config::parse_value(value))?;
+    config.save()
+}
+
+pub fn config_unset(scope: Option<&str>, key: &str) -> Result<(), GoldfishError> {
+    //! Remove a setting from the config file of the scope, by default the repository's
+    let mut config = load_config_file(scope.unwrap_or("--local"))?;
+    if !config.unset(key) {
+        return Err(GoldfishError::Config(format!("{} is not set", key)));
+    }
+    config.save()
+}
+
+pub fn config_list(scope: Option<&str>) -> Result<(), GoldfishError> {
+    //! Print every setting of the config file of the scope, or else every setting in effect
+    let settings = match scope {
+        Some(scope) => load_config_file(scope)?.list(),
+        None => load_settings()?.list(),
+    };
+    print_output_vec_string(
+        settings.iter().map(|(key, value)| format!("{}={}", key, config::format_value(value))).collect(),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller;
+    use crate::filesystem;
+    use crate::model::tests::{commit_test_files, create_test_repository};
+    use crate::error::GoldfishError;
+    use crate::model::{Blob, Commit, Repository, BLOBS_DIR, COMMITS_DIR};
+    use crate::utilities;
+    use std::sync::{Mutex, MutexGuard};
+    use std::time::{Duration, SystemTime};
+
+    // commands work on the repository of the current directory, which every test shares
+    static CURRENT_DIR: Mutex<()> = Mutex::new(());
+
+    fn enter_repository(repo: &Repository) -> MutexGuard<'static, ()> {
+        //! Make the repository the current one until the returned guard is dropped
+        let guard = CURRENT_DIR.lock().unwrap_or_else(|e| e.into_inner());
+        std::env::set_current_dir(repo.get_working_path()).unwrap();
+        guard
+    }
+
+    #[test]
+    fn test_1_gc_binary_blob() {
+        //! Check that gc runs with a binary file tracked, and only prunes the old unreachable object
+        let repo = create_test_repository("gc_binary_blob");
+        commit_test_files(&repo, &[("bin", b"\x00\x01\xff\r\nabc"), ("text", b"hello\n")]);
+        let unreachable = Blob::create(&repo, b"nothing points to me\n").unwrap();
+        let old = SystemTime::now() - Duration::from_secs(30 * 24 * 60 * 60);
+        filesystem::set_modified_time(repo.get_object_path(BLOBS_DIR, unreachable.get_id()).as_str(), old).unwrap();
+        let binary_id = repo.get_staging_tracked_files().unwrap()["bin"].clone();
+
+        let _guard = enter_repository(&repo);
+        controller::gc(false).unwrap();
+        let repo = Repository::find(repo.get_working_path()).unwrap();
+        assert!(!repo.has_object(BLOBS_DIR, unreachable.get_id()));
+        assert!(repo.has_object(BLOBS_DIR, binary_id.as_str()));
+        assert_eq!(b"\x00\x01\xff\r\nabc".to_vec(), Blob::get(&repo, binary_id.as_str()).unwrap().get_blob_content().unwrap());
+        assert_eq!(4, crate::fsck::check(&repo).unwrap().object_count);
+    }
+    fn create_conflicting_branches(name: &str) -> (Repository, String, String) {
+        //! A repository on main, and a branch `other` that changed the same line of `a` and added
+        //! a file `new`. Returns the commit ids of main and other
+        let repo = create_test_repository(name);
+        let base_id = commit_test_files(&repo, &[("a", b"1\n"), ("c", b"same\n")]);
+        repo.write_branch("other", base_id.as_str()).unwrap();
+        let ours_id = commit_test_files(&repo, &[("a", b"2\n"), ("c", b"same\n")]);
+        repo.write_symbolic_head("other").unwrap();
+        let theirs_id = commit_test_files(&repo, &[("a", b"3\n"), ("c", b"same\n"), ("new", b"new\n")]);
+        repo.write_symbolic_head("main").unwrap();
+        Commit::get(&repo, ours_id.as_str()).unwrap().checkout(true).unwrap();
+        (repo, ours_id, theirs_id)
+    }
+
+    #[test]
+    fn test_2_merge_abort() {
+        //! Check that aborting a merge that stopped on conflicts restores the files of HEAD and
+        //! forgets the merge
+        let (repo, ours_id, _) = create_conflicting_branches("merge_abort");
+        let _guard = enter_repository(&repo);
+        assert!(matches!(controller::merge("other"), Err(GoldfishError::Conflict(_))));
+        assert!(repo.read_merge_head().is_some());
+        assert_eq!(1, repo.get_unmerged_paths().unwrap().len());
+        assert!(filesystem::is_file(filesystem::join_path(vec![repo.get_working_path(), "new"]).as_str()));
+
+        controller::merge_abort().unwrap();
+        assert_eq!(None, repo.read_merge_head());
+        assert!(repo.get_unmerged_paths().unwrap().is_empty());
+        assert_eq!(ours_id, repo.get_current_commit_id().unwrap());
+        let head_files = Commit::get(&repo, ours_id.as_str()).unwrap().load_tracked_files().unwrap();
+        assert_eq!(head_files, repo.get_staging_tracked_files().unwrap());
+        let a_path = filesystem::join_path(vec![repo.get_working_path(), "a"]);
+        assert_eq!(b"2\n".to_vec(), filesystem::read_bytes(a_path.as_str()).unwrap());
+        assert!(!filesystem::is_file(filesystem::join_path(vec![repo.get_working_path(), "new"]).as_str()));
+    }
+
+    #[test]
+    fn test_3_merge_continue() {
+        //! Check that continuing a merge once its conflicts are resolved creates a commit with
+        //! both parents
+        let (repo, ours_id, theirs_id) = create_conflicting_branches("merge_continue");
+        let _guard = enter_repository(&repo);
+        assert!(matches!(controller::merge("other"), Err(GoldfishError::Conflict(_))));
+        assert!(matches!(controller::merge_continue(), Err(GoldfishError::Conflict(_))));
+
+        let a_path = filesystem::join_path(vec![repo.get_working_path(), "a"]);
+        filesystem::write_file(b"resolved\n", a_path.as_str()).unwrap();
+        controller::add_track_file(a_path.as_str()).unwrap();
+        controller::merge_continue().unwrap();
+        assert_eq!(None, repo.read_merge_head());
+        assert!(repo.get_unmerged_paths().unwrap().is_empty());
+        let merge_commit = Commit::get(&repo, repo.get_current_commit_id().unwrap().as_str()).unwrap();
+        assert_eq!(vec![ours_id.as_str(), theirs_id.as_str()], merge_commit.get_parent_ids());
+        let merged_files = merge_commit.load_tracked_files().unwrap();
+        let mut file_paths: Vec<&str> = merged_files.keys().map(|file_path| file_path.as_str()).collect();
+        file_paths.sort();
+        assert_eq!(vec!["a", "c", "new"], file_paths);
+        assert_eq!(Blob::create(&repo, b"resolved\n").unwrap().get_id(), merged_files["a"]);
+    }
+    #[test]
+    fn test_4_gc_same_id() {
+        //! Check that gc tells objects apart by type: an unreachable commit is pruned even though
+        //! a reachable blob has the same id
+        let repo = create_test_repository("gc_same_id");
+        let commit_id = commit_test_files(&repo, &[("a", b"1")]);
+        // a blob holding the content of the commit hashes to the same id
+        let content = repo.read_object(COMMITS_DIR, commit_id.as_str()).unwrap();
+        repo.delete_branch("main").unwrap();
+        commit_test_files(&repo, &[("copy", content.as_slice())]);
+        assert!(repo.has_object(BLOBS_DIR, commit_id.as_str()));
+        let old = SystemTime::now() - Duration::from_secs(30 * 24 * 60 * 60);
+        filesystem::set_modified_time(repo.get_object_path(COMMITS_DIR, commit_id.as_str()).as_str(), old).unwrap();
+
+        let _guard = enter_repository(&repo);
+        controller::gc(false).unwrap();
+        let repo = Repository::find(repo.get_working_path()).unwrap();
+        assert!(!repo.has_object(COMMITS_DIR, commit_id.as_str()));
+        assert_eq!(content, Blob::get(&repo, commit_id.as_str()).unwrap().get_blob_content().unwrap());
+    }
+    #[test]
+    fn test_5_merge_line_endings() {
+        //! Check that merging keeps carriage returns and a missing final newline, and leaves
+        //! content that is not UTF-8 alone
+        let repo = create_test_repository("merge_line_endings");
+        let files = |crlf: &[u8], end: &[u8], latin1: &[u8]| -> Vec<(&'static str, Vec<u8>)> {
+            vec![("crlf", crlf.to_vec()), ("end", end.to_vec()), ("latin1", latin1.to_vec())]
+        };
+        let commit = |files: &Vec<(&'static str, Vec<u8>)>| {
+            let files: Vec<(&str, &[u8])> = files.iter().map(|(path, content)| (*path, content.as_slice())).collect();
+            commit_test_files(&repo, &files)
+        };
+        let base_id = commit(&files(b"1\r\n2\r\n3\r\n4\r\n", b"a\nb\nc\nd", b"caf\xe9\n1\n2\n3\n"));
+        repo.write_branch("other", base_id.as_str()).unwrap();
+        commit(&files(b"one\r\n2\r\n3\r\n4\r\n", b"A\nb\nc\nd", b"caf\xe9\none\n2\n3\n"));
+        repo.write_symbolic_head("other").unwrap();
+        commit(&files(b"1\r\n2\r\n3\r\nfour\r\n", b"a\nb\nc\nD", b"caf\xe9\n1\n2\nthree\n"));
+        repo.write_symbolic_head("main").unwrap();
+        let ours_id = repo.get_current_commit_id().unwrap();
+        Commit::get(&repo, ours_id.as_str()).unwrap().checkout(true).unwrap();
+
+        let _guard = enter_repository(&repo);
+        assert!(matches!(controller::merge("other"), Err(GoldfishError::Conflict(_))));
+        let read = |file_path: &str| filesystem::read_bytes(filesystem::join_path(vec![repo.get_working_path(), file_path]).as_str()).unwrap();
+        assert_eq!(b"one\r\n2\r\n3\r\nfour\r\n".to_vec(), read("crlf"));
+        assert_eq!(b"A\nb\nc\nD".to_vec(), read("end"));
+        // the current version is kept, as for a binary file
+        assert_eq!(b"caf\xe9\none\n2\n3\n".to_vec(), read("latin1"));
+        let unmerged_paths = repo.get_unmerged_paths().unwrap();
+        assert_eq!(vec!["latin1"], unmerged_paths.iter().map(|unmerged_path| unmerged_path.get_path()).collect::<Vec<&str>>());
+        let staged_files = repo.get_staging_tracked_files().unwrap();
+        assert_eq!(utilities::hash(b"one\r\n2\r\n3\r\nfour\r\n"), staged_files["crlf"]);
+        assert_eq!(utilities::hash(b"A\nb\nc\nD"), staged_files["end"]);
     }
 }
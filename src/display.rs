@@ -1,3 +1,5 @@
+use std::io::{self, IsTerminal, Write};
+
 /*
     Print output message (string literal) to terminal
     @param message: output message sent to user
@@ -14,6 +16,16 @@ pub fn print_output_string(message: String) {
     println!("{}", message);
 }
 
+/*
+    Print raw file content (which may not be text) to terminal, byte for byte
+    @param content: bytes sent to user
+*/
+pub fn print_output_bytes(content: &[u8]) {
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(content);
+    let _ = stdout.flush();
+}
+
 /*
     Print output message (vector of String struct) to terminal
     @param message: output message sent to user
@@ -25,19 +37,45 @@ pub fn print_output_vec_string(messages: Vec<String>) {
 }
 
 /*
-    Print error message (string literal) to terminal
+    Print error message (string struct) to stderr, so that it is not mixed with the output of the command
     @param message: error message sent to user
 */
-pub fn print_error(message: &str) {
-    println!("Error: {}", message);
+pub fn print_error_string(message: String) {
+    eprintln!("Error: {}", message);
 }
 
 /*
-    Print error message (string struct) to terminal
-    @param message: error message sent to user
+    Colours used to highlight output on a terminal
 */
-pub fn print_error_string(message: String) {
-    println!("Error: {}", message);
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Cyan,
+    Bold,
+}
+
+/*
+    Wrap a message in the ANSI escape codes of a colour
+    @param message: text to colour
+    @param color: colour of the text
+*/
+pub fn paint(message: &str, color: Color) -> String {
+    let code = match color {
+        Color::Red => "31",
+        Color::Green => "32",
+        Color::Yellow => "33",
+        Color::Cyan => "36",
+        Color::Bold => "1",
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, message)
+}
+
+/*
+    Check whether output goes to a terminal rather than to a file or a pipe
+*/
+pub fn is_terminal() -> bool {
+    io::stdout().is_terminal()
 }
 
 /*
@@ -59,18 +97,36 @@ pub fn print_welcome() {
 pub fn print_help() {
     println!("Here's the list of our supported commands:");
     println!("1. init: create an empty repository");
-    println!("2. clone [url]: copy an existing repository from an url");
+    println!("2. clone [url] [folder]: copy an existing repository (path, user@host:path or ssh://user@host[:port]/path) into a folder");
     println!("3. add [file]: add a specified file that you want to track");
     println!("4. remove [file]: remove a specified file from tracking list");
     println!("5. status: check the current status of current repository");
+    println!("   status --ignored: also list the untracked files skipped because of a .goldfishignore file");
     println!("6. heads: show the current heads");
-    println!("7. diff [rev1] [rev2]: check the changes between 2 revisions");
-    println!("8. cat [commit] [file]: inspect a file of a given revision");
-    println!("9. checkout [rev]: check out a specific revision");
-    println!("10. commit: commit changes and create a new revision");
-    println!("11. log: view the change log");
-    println!("12. merge [rev1] [rev2]: merge two revisions");
-    println!("13. pull [url]: pull changes from another repository");
-    println!("14. push [url]: push changes into another repository");
-    println!("15. quit: quit the program");
+    println!("7. branch [name]: create a branch at the current commit");
+    println!("   branch --list: list all branches");
+    println!("   branch -d [name]: delete a branch");
+    println!("8. diff [-U<n>] [rev1] [rev2]: show the changes between 2 revisions as a unified diff with n lines of context (default 3)");
+    println!("9. cat [rev] [file]: inspect a file of a given revision");
+    println!("10. checkout [--force] [rev]: check out a specific revision or branch, refusing to overwrite local changes unless forced");
+    println!("11. commit -m [message]: commit changes and create a new revision");
+    println!("12. log: view the change log");
+    println!("13. merge [rev]: merge a revision or branch into the current commit");
+    println!("    merge --continue: commit the merge once every conflict is resolved and added");
+    println!("    merge --abort: give up on a conflicted merge and restore the current commit");
+    println!("14. fetch [remote]: download the branches of a remote (name or url) as remote-tracking branches ([remote]/[branch])");
+    println!("    pull [remote]: fetch, then merge the branch of the remote into the current branch");
+    println!("15. push [--force] [remote]: push the current branch to a remote, refusing to overwrite its commits unless forced");
+    println!("    fetch, pull and push use the upstream remote of the current branch (set by clone) when no remote is given");
+    println!("    remote list: list the remotes and their urls");
+    println!("    remote add [name] [url] / remote remove [name] / remote set-url [name] [url]: manage remotes");
+    println!("16. repack: pack all objects into a single compressed pack file");
+    println!("    fsck: check that every object is intact and every object reachable from a branch exists");
+    println!("    gc [--dry-run]: delete the objects no branch uses that are older than gc.gracePeriod days (14 by default), and pack the others");
+    println!("    config [--system|--global|--local] get [key] / set [key] [value] / unset [key] / list: read and change settings");
+    println!("    (user.name, user.email, diff.context, color.ui = auto|always|never, merge.markerSize, core.verifyObjects, remote.[name].url, ...)");
+    println!("17. serve [path]: answer a remote goldfish on stdin/stdout (run by clone, pull and push over ssh)");
+    println!("18. quit: quit the program");
+    println!("Run with a command, goldfish exits with 0 on success, 1 invalid argument, 2 usage error, 3 not a repository,");
+    println!("4 unknown revision, 5 conflict, 6 transport error, 7 corrupt object, 8 config error, 9 I/O error");
 }
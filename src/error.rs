@@ -0,0 +1,131 @@
+//! # Errors
+//! Why a command failed. Each kind of failure is its own variant, so the command line can
+//! tell them apart, while the message says what exactly went wrong.
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/**
+ * An error of a Goldfish command
+ */
+#[derive(Debug)]
+pub enum GoldfishError {
+    // reading or writing a file failed, with what was being done (or empty if unknown)
+    Io(String, io::Error),
+    // an object that is missing, cannot be parsed, does not hash to its id or is unsafe to check out
+    CorruptObject(String),
+    // the current directory is not inside a repository
+    NotARepository,
+    // a revision that is neither a branch, a remote-tracking branch nor a commit id
+    UnknownRevision(String),
+    // local changes, unresolved conflicts or diverged branches in the way of the command
+    Conflict(String),
+    // a remote repository that cannot be reached, or that does not follow the protocol
+    Transport(String),
+    // a config file that cannot be parsed, or a setting with an invalid value
+    Config(String),
+    // an invalid argument, or a command that cannot be done in the current state of the repository
+    Invalid(String),
+    // an unknown command, or arguments that do not match the syntax of the command
+    Usage(String),
+}
+
+impl GoldfishError {
+    pub fn io(context: impl Into<String>, source: io::Error) -> GoldfishError {
+        //! Wrap an I/O error with what was being done
+        GoldfishError::Io(context.into(), source)
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        //! The exit status of a goldfish command run from the command line that failed with this
+        //! error. 0 is success; the README lists the codes
+        match self {
+            GoldfishError::Invalid(_) => 1,
+            GoldfishError::Usage(_) => 2,
+            GoldfishError::NotARepository => 3,
+            GoldfishError::UnknownRevision(_) => 4,
+            GoldfishError::Conflict(_) => 5,
+            GoldfishError::Transport(_) => 6,
+            GoldfishError::CorruptObject(_) => 7,
+            GoldfishError::Config(_) => 8,
+            GoldfishError::Io(_, _) => 9,
+        }
+    }
+
+    pub fn with_context(self, context: impl Into<String>) -> GoldfishError {
+        //! Prefix the message with what was being done, keeping the kind of failure
+        let context = context.into();
+        match self {
+            GoldfishError::Io(inner, source) if inner.is_empty() => GoldfishError::Io(context, source),
+            GoldfishError::Io(inner, source) => GoldfishError::Io(format!("{}: {}", context, inner), source),
+            GoldfishError::CorruptObject(message) => GoldfishError::CorruptObject(format!("{}: {}", context, message)),
+            GoldfishError::Conflict(message) => GoldfishError::Conflict(format!("{}: {}", context, message)),
+            GoldfishError::Transport(message) => GoldfishError::Transport(format!("{}: {}", context, message)),
+            GoldfishError::Config(message) => GoldfishError::Config(format!("{}: {}", context, message)),
+            GoldfishError::Invalid(message) => GoldfishError::Invalid(format!("{}: {}", context, message)),
+            GoldfishError::Usage(message) => GoldfishError::Usage(format!("{}: {}", context, message)),
+            // these already say all there is to say
+            GoldfishError::NotARepository | GoldfishError::UnknownRevision(_) => self,
+        }
+    }
+}
+
+impl fmt::Display for GoldfishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoldfishError::Io(context, source) if context.is_empty() => write!(f, "{}", source),
+            GoldfishError::Io(context, source) => write!(f, "{}: {}", context, source),
+            GoldfishError::NotARepository => write!(f, "Not a Goldfish repository"),
+            GoldfishError::UnknownRevision(revision) => write!(f, "Unknown revision: {}", revision),
+            GoldfishError::CorruptObject(message)
+            | GoldfishError::Conflict(message)
+            | GoldfishError::Transport(message)
+            | GoldfishError::Config(message)
+            | GoldfishError::Invalid(message)
+            | GoldfishError::Usage(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for GoldfishError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GoldfishError::Io(_, source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for GoldfishError {
+    fn from(source: io::Error) -> GoldfishError {
+        GoldfishError::Io(String::new(), source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::GoldfishError;
+    use std::collections::HashSet;
+    use std::io;
+    #[test]
+    fn test_1_exit_codes() {
+        //! Check that every kind of failure has its own non-zero exit code, which context does not change
+        let errors = [
+            GoldfishError::Invalid(String::from("a")),
+            GoldfishError::Usage(String::from("a")),
+            GoldfishError::NotARepository,
+            GoldfishError::UnknownRevision(String::from("a")),
+            GoldfishError::Conflict(String::from("a")),
+            GoldfishError::Transport(String::from("a")),
+            GoldfishError::CorruptObject(String::from("a")),
+            GoldfishError::Config(String::from("a")),
+            GoldfishError::io("a", io::Error::other("b")),
+        ];
+        let codes: HashSet<i32> = errors.iter().map(|e| e.exit_code()).collect();
+        assert_eq!(errors.len(), codes.len());
+        assert!(!codes.contains(&0));
+        let error = GoldfishError::Conflict(String::from("Rejected")).with_context("Cannot push");
+        assert_eq!(5, error.exit_code());
+        assert_eq!("Cannot push: Rejected", error.to_string());
+    }
+}
@@ -2,6 +2,7 @@ use pathdiff;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 pub fn pathbuf_to_string(path: PathBuf) -> String {
     //! Unsafe function (but mostly safe practically) to convert a PathBuf object into a String
@@ -61,8 +62,8 @@ pub fn list_files(path: &str, recursive: bool, exclude: &Vec<&str>) -> io::Resul
     Ok(result)
 }
 
-pub fn write_file(data: &str, path: &str) -> io::Result<()> {
-    //! Write data to the specified file
+pub fn write_file<T: AsRef<[u8]>>(data: T, path: &str) -> io::Result<()> {
+    //! Write data (text or raw bytes) to the specified file
     //! If the file does not exist, create the file as well as all intermediate parent folders
     let parent_folder = parent(path);
     match parent_folder {
@@ -84,6 +85,11 @@ pub fn read_file(path: &str) -> io::Result<String> {
     fs::read_to_string(path)
 }
 
+pub fn read_bytes(path: &str) -> io::Result<Vec<u8>> {
+    //! Read all the contents of a file as raw bytes, for files that may not be valid UTF-8
+    fs::read(path)
+}
+
 pub fn join_path(paths: Vec<&str>) -> String {
     //! Join the paths into a path string (in the format of the host OS)
     //! Currently panics if the conversion from OsString to String (after joining)
@@ -111,6 +117,21 @@ pub fn remove(path: &str) -> io::Result<()> {
     }
 }
 
+pub fn remove_empty_parents(path: &str, root: &str) {
+    //! Remove the parent directories of a removed path that are left empty, up to the root directory
+    let mut current = parent(path);
+    while let Some(dir) = current {
+        let dir_path = Path::new(dir.as_str());
+        if !dir_path.starts_with(root) || dir_path == Path::new(root) {
+            break;
+        }
+        if fs::read_dir(dir_path).map_or(true, |mut entries| entries.next().is_some()) || fs::remove_dir(dir_path).is_err() {
+            break;
+        }
+        current = parent(dir.as_str());
+    }
+}
+
 pub fn copy(source: &str, dest: &str) -> io::Result<()> {
     if is_dir(source) {
         for entry in fs::read_dir(source)? {
@@ -138,6 +159,21 @@ pub fn copy(source: &str, dest: &str) -> io::Result<()> {
     }
 }
 
+pub fn file_size(path: &str) -> io::Result<u64> {
+    //! Return the size of the given file in bytes
+    Ok(fs::metadata(path)?.len())
+}
+
+pub fn modified_time(path: &str) -> io::Result<SystemTime> {
+    //! Return when the given file was last modified
+    fs::metadata(path)?.modified()
+}
+
+pub fn set_modified_time(path: &str, time: SystemTime) -> io::Result<()> {
+    //! Change when the given file was last modified
+    fs::File::options().write(true).open(path)?.set_modified(time)
+}
+
 pub fn is_dir(path: &str) -> bool {
     //! Check if path is a directory
     Path::new(path).is_dir()
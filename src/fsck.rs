@@ -0,0 +1,210 @@
+//! # Fsck
+//! Checks every object of a repository: each one must hash to its id, and every commit,
+//! tree and blob reachable from a ref (HEAD, branches, remote-tracking branches and
+//! MERGE_HEAD) must exist. The blobs of the tracked files and of unresolved conflicts are
+//! reachable too. Objects that nothing points to are reported as dangling, which
+//! is not an error: deleting a branch or amending history leaves them behind.
+use crate::error::GoldfishError;
+use crate::model;
+use crate::model::{get_object_kind, Repository, BLOBS_DIR, COMMITS_DIR, TREES_DIR};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+const OBJECT_DIRS: [&str; 3] = [COMMITS_DIR, TREES_DIR, BLOBS_DIR];
+
+type ObjectKey = (String, String);
+
+/**
+ * What `check` found, one sorted message per problem
+ */
+#[derive(Debug, Default)]
+pub struct Report {
+    pub object_count: usize,
+    pub missing: BTreeSet<String>,
+    pub corrupt: BTreeSet<String>,
+    pub dangling: BTreeSet<String>,
+    // (object_dir, id) of every object reachable from a ref, the tracked files or a conflict
+    pub reachable: HashSet<ObjectKey>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        //! Missing and corrupt objects are errors, dangling objects are not
+        self.missing.is_empty() && self.corrupt.is_empty()
+    }
+}
+
+pub fn list_refs(repo: &Repository) -> Result<Vec<(String, String)>, GoldfishError> {
+    //! Every (name, commit id) that keeps commits alive: a detached HEAD, the branches,
+    //! the remote-tracking branches and MERGE_HEAD
+    let mut refs = vec![];
+    let head = repo.read_head()?;
+    if model::parse_symbolic_head(head.as_str()).is_none() && !head.trim().is_empty() {
+        refs.push((model::HEAD.to_string(), head.trim().to_string()));
+    }
+    for branch_name in repo.list_branches()? {
+        let commit_id = repo.read_branch(branch_name.as_str())?;
+        refs.push((format!("{}/{}", model::BRANCHES_DIR, branch_name), commit_id));
+    }
+    for remote in repo.list_remotes_with_branches()? {
+        for branch_name in repo.list_remote_branches(remote.as_str())? {
+            let commit_id = repo.read_remote_branch(remote.as_str(), branch_name.as_str())?;
+            refs.push((format!("{}/{}/{}", model::REMOTES_DIR, remote, branch_name), commit_id));
+        }
+    }
+    if let Some(merge_head) = repo.read_merge_head() {
+        refs.push((model::MERGE_HEAD.to_string(), merge_head));
+    }
+    Ok(refs)
+}
+
+fn parse_references(object_dir: &str, content: &[u8]) -> Option<Vec<ObjectKey>> {
+    //! The objects a commit or tree points to, or None if it cannot be parsed
+    // blobs point to nothing, and may hold any bytes
+    if object_dir == BLOBS_DIR {
+        return Some(vec![]);
+    }
+    let content = std::str::from_utf8(content).ok()?;
+    let mut references = vec![];
+    if object_dir == COMMITS_DIR {
+        for line in content.split('\n').skip(1).take_while(|line| !line.is_empty()) {
+            let items: Vec<&str> = line.split(' ').collect();
+            match items.as_slice() {
+                ["tree", tree_id] => references.push((TREES_DIR.to_string(), tree_id.to_string())),
+                ["parent", ""] => {}
+                ["parent", parent_id] => references.push((COMMITS_DIR.to_string(), parent_id.to_string())),
+                // commits made before trees were introduced list every file directly
+                ["tracked_file", _, blob_id] => references.push((BLOBS_DIR.to_string(), blob_id.to_string())),
+                _ => {}
+            }
+        }
+    } else if object_dir == TREES_DIR {
+        for line in content.split_terminator('\n').skip(1) {
+            let mut items = line.splitn(3, ' ');
+            let entry_dir = match items.next()? {
+                "blob" => BLOBS_DIR,
+                "tree" => TREES_DIR,
+                _ => return None,
+            };
+            references.push((entry_dir.to_string(), items.next()?.to_string()));
+            items.next()?;
+        }
+    }
+    Some(references)
+}
+
+pub fn check(repo: &Repository) -> Result<Report, GoldfishError> {
+    //! Read and hash every object, then walk the objects reachable from the refs
+    let mut report = Report::default();
+    let mut objects: HashMap<ObjectKey, Vec<ObjectKey>> = HashMap::new();
+    for object_dir in OBJECT_DIRS {
+        let ids = repo.list_objects(object_dir).map_err(|e| GoldfishError::io(format!("Fail to list the {}", object_dir), e))?;
+        for id in ids {
+            report.object_count += 1;
+            let kind = get_object_kind(object_dir);
+            let references = match repo.read_unverified_object(object_dir, id.as_str()) {
+                Ok(content) => {
+                    if let Err(e) = model::check_object_id(object_dir, id.as_str(), &content) {
+                        report.corrupt.insert(e.to_string());
+                    } else if let Err(e) = model::check_object_paths(object_dir, &content) {
+                        report.corrupt.insert(format!("{} in {} {}", e, kind, id));
+                    }
+                    parse_references(object_dir, &content).unwrap_or_else(|| {
+                        report.corrupt.insert(format!("Malformed {} {}", kind, id));
+                        vec![]
+                    })
+                }
+                Err(e) => {
+                    report.corrupt.insert(format!("Cannot read {} {}: {}", kind, id, e));
+                    vec![]
+                }
+            };
+            objects.insert((object_dir.to_string(), id), references);
+        }
+    }
+
+    let mut queue: VecDeque<&ObjectKey> = VecDeque::new();
+    let refs = list_refs(repo)?;
+    for (name, commit_id) in refs.iter() {
+        let key = (COMMITS_DIR.to_string(), commit_id.to_owned());
+        match objects.get_key_value(&key) {
+            Some((key, _)) => queue.push_back(key),
+            None => {
+                report.missing.insert(format!("Missing commit {}, pointed to by {}", commit_id, name));
+            }
+        }
+    }
+    // staged files and conflicts use blobs too, though they may not exist yet
+    let mut index_blob_ids: Vec<String> = repo.get_staging_tracked_files()?.into_values().collect();
+    for unmerged_path in repo.get_unmerged_paths()? {
+        index_blob_ids.extend(unmerged_path.get_blob_ids().into_iter().map(|id| id.to_string()));
+    }
+    for blob_id in index_blob_ids {
+        if let Some((key, _)) = objects.get_key_value(&(BLOBS_DIR.to_string(), blob_id)) {
+            queue.push_back(key);
+        }
+    }
+    let mut reachable: HashSet<&ObjectKey> = HashSet::new();
+    while let Some(key) = queue.pop_front() {
+        if !reachable.insert(key) {
+            continue;
+        }
+        for reference in &objects[key] {
+            match objects.get_key_value(reference) {
+                Some((reference, _)) => queue.push_back(reference),
+                None => {
+                    report.missing.insert(format!(
+                        "Missing {} {}, referenced by {} {}",
+                        get_object_kind(reference.0.as_str()), reference.1, get_object_kind(key.0.as_str()), key.1
+                    ));
+                }
+            }
+        }
+    }
+
+    let referenced: HashSet<&ObjectKey> = objects.values().flatten().collect();
+    for key in objects.keys() {
+        if !reachable.contains(key) && !referenced.contains(key) {
+            report.dangling.insert(format!("Dangling {} {}", get_object_kind(key.0.as_str()), key.1));
+        }
+    }
+    report.reachable = reachable.into_iter().cloned().collect();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fsck::{check, parse_references};
+    use crate::model::tests::{commit_test_files, create_test_repository};
+    use crate::model::{BLOBS_DIR, COMMITS_DIR, TREES_DIR};
+    #[test]
+    fn test_1_parse_references() {
+        //! Check that commits point to their tree and parents, and trees to their entries
+        let commit = b"commit\ntree AA\nparent BB\nparent CC\nauthor x <x@y> 1\n\ntree DD is only the message\n";
+        assert_eq!(
+            Some(vec![
+                (TREES_DIR.to_string(), "AA".to_string()),
+                (COMMITS_DIR.to_string(), "BB".to_string()),
+                (COMMITS_DIR.to_string(), "CC".to_string()),
+            ]),
+            parse_references(COMMITS_DIR, commit)
+        );
+        assert_eq!(Some(vec![]), parse_references(COMMITS_DIR, b"commit\nparent \n\nfirst\n"));
+        assert_eq!(
+            Some(vec![(BLOBS_DIR.to_string(), "EE".to_string()), (TREES_DIR.to_string(), "FF".to_string())]),
+            parse_references(TREES_DIR, b"tree\nblob EE a file\ntree FF src\n")
+        );
+        assert_eq!(None, parse_references(TREES_DIR, b"tree\nlink EE a\n"));
+    }
+
+    #[test]
+    fn test_2_binary_blob() {
+        //! Check that a blob that is not UTF-8 is neither malformed nor dangling
+        let repo = create_test_repository("fsck_binary_blob");
+        commit_test_files(&repo, &[("bin", b"\x00\x01\xff\r\nabc"), ("text", b"hello\n")]);
+        let report = check(&repo).unwrap();
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(4, report.object_count);
+        assert!(report.corrupt.is_empty() && report.dangling.is_empty(), "{:?}", report);
+        assert_eq!(Some(vec![]), parse_references(BLOBS_DIR, b"\xff\xfe"));
+    }
+}
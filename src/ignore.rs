@@ -0,0 +1,260 @@
+//! # Ignore files
+//! A `.goldfishignore` file lists patterns of paths that `status` and `add` leave alone,
+//! one per line, with the same rules as a `.gitignore`:
+//!
+//! ```text
+//! # build outputs (a comment)
+//! target/
+//! *.swp
+//! /notes.txt
+//! docs/**/*.pdf
+//! !docs/manual.pdf
+//! ```
+//!
+//! - `*` matches anything but `/`, `?` one character but `/`, `[abc]` or `[a-z]` one of the
+//!   listed characters, and `**` any number of directories
+//! - A pattern ending with `/` only matches directories (and so everything inside them)
+//! - A pattern containing a `/` elsewhere is anchored: it matches paths relative to the directory
+//!   of its ignore file. Other patterns match a file or directory name at any depth
+//! - A pattern starting with `!` re-includes what an earlier pattern ignored. A file inside an
+//!   ignored directory cannot be re-included, as the directory is not looked into
+//! - The last matching pattern wins, and the patterns of a `.goldfishignore` in a subdirectory
+//!   come after the ones of its parent directories
+use crate::filesystem;
+use regex::Regex;
+use std::fs;
+use std::io;
+
+pub const IGNORE_FILE: &str = ".goldfishignore";
+
+/**
+ * One line of an ignore file
+ */
+#[derive(Debug)]
+struct Pattern {
+    // directory of the ignore file relative to the working directory, with a trailing `/` (or empty)
+    base: String,
+    regex: Regex,
+    negated: bool,
+    directory_only: bool,
+}
+
+impl Pattern {
+    fn parse(line: &str, base: &str) -> Option<Pattern> {
+        //! Read a line of an ignore file. Blank lines and comments give no pattern
+        let mut line = line.trim_end_matches(['\r', '\n']);
+        // trailing spaces are ignored unless escaped
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let negated = line.starts_with('!');
+        if negated {
+            line = &line[1..];
+        }
+        let directory_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        if line.is_empty() {
+            return None;
+        }
+        let anchored = line.contains('/');
+        let glob = line.strip_prefix('/').unwrap_or(line);
+        let prefix = if anchored { "^" } else { "^(?:.*/)?" };
+        let regex = Regex::new(format!("{}{}$", prefix, glob_to_regex(glob)).as_str()).ok()?;
+        Some(Pattern { base: base.to_string(), regex, negated, directory_only })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.directory_only && !is_dir {
+            return false;
+        }
+        match path.strip_prefix(self.base.as_str()) {
+            Some(relative_path) => self.regex.is_match(relative_path),
+            None => false,
+        }
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    //! Translate a glob into the equivalent regular expression
+    let chars: Vec<char> = glob.chars().collect();
+    let mut regex = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let at_start = i == 0 || chars[i - 1] == '/';
+                if at_start && chars.get(i + 2) == Some(&'/') {
+                    // `**/`: any number of directories, including none
+                    regex.push_str("(?:.*/)?");
+                    i += 3;
+                } else {
+                    regex.push_str(".*");
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => match chars[i + 1..].iter().position(|c| *c == ']') {
+                Some(length) if length > 0 => {
+                    let class: String = chars[i + 1..i + 1 + length].iter().collect();
+                    let class = match class.strip_prefix('!') {
+                        Some(negated_class) => format!("^{}", negated_class),
+                        None => class,
+                    };
+                    regex.push_str(format!("[{}]", class.replace('\\', "\\\\").replace('[', "\\[")).as_str());
+                    i += length + 2;
+                    continue;
+                }
+                _ => regex.push_str("\\["),
+            },
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                regex.push_str(regex::escape(chars[i].to_string().as_str()).as_str());
+            }
+            c => regex.push_str(regex::escape(c.to_string().as_str()).as_str()),
+        }
+        i += 1;
+    }
+    regex
+}
+
+/**
+ * The patterns of the ignore files in effect in a directory of the working directory:
+ * the ones of that directory and of every parent directory up to the working directory
+ */
+#[derive(Debug)]
+pub struct IgnoreRules {
+    working_path: String,
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreRules {
+    pub fn load(working_path: &str, dir_path: &str) -> IgnoreRules {
+        //! Read the ignore files from the working directory down to the given directory
+        let mut rules = IgnoreRules { working_path: working_path.to_string(), patterns: vec![] };
+        let relative_dir = rules.get_relative_path(dir_path).unwrap_or_default();
+        rules.enter_dir(working_path);
+        let mut current_path = working_path.to_string();
+        for component in relative_dir.split('/').filter(|component| !component.is_empty()) {
+            current_path = filesystem::join_path(vec![current_path.as_str(), component]);
+            rules.enter_dir(current_path.as_str());
+        }
+        rules
+    }
+
+    fn get_relative_path(&self, path: &str) -> Option<String> {
+        //! Path relative to the working directory, with `/` separators
+        let relative_path = filesystem::diff_path(self.working_path.as_str(), path)?;
+        if relative_path.starts_with("..") {
+            return None;
+        }
+        Some(relative_path.replace('\\', "/"))
+    }
+
+    pub fn enter_dir(&mut self, dir_path: &str) -> usize {
+        //! Add the patterns of the ignore file of a directory, if it has one.
+        //! Returns the number of patterns before, to give to `leave_dir`
+        let count = self.patterns.len();
+        let ignore_file_path = filesystem::join_path(vec![dir_path, IGNORE_FILE]);
+        if let (Some(relative_dir), Ok(content)) =
+            (self.get_relative_path(dir_path), filesystem::read_file(ignore_file_path.as_str()))
+        {
+            let base = if relative_dir.is_empty() { relative_dir } else { format!("{}/", relative_dir) };
+            self.patterns.extend(content.lines().filter_map(|line| Pattern::parse(line, base.as_str())));
+        }
+        count
+    }
+
+    pub fn leave_dir(&mut self, count: usize) {
+        //! Drop the patterns added by the matching `enter_dir`
+        self.patterns.truncate(count);
+    }
+
+    pub fn list_unignored_files(&mut self, path: &str, exclude: &Vec<&str>) -> io::Result<(Vec<String>, Vec<String>)> {
+        //! List all files in the given directory recursively, except the ones ignored by the
+        //! `.goldfishignore` files. Also returns what was ignored: files, and directories (whose
+        //! content is not listed) with a trailing separator
+        let mut result = vec![];
+        let mut ignored = vec![];
+        self.walk_files(path, exclude, &mut result, &mut ignored)?;
+        Ok((result, ignored))
+    }
+
+    fn walk_files(&mut self, path: &str, exclude: &Vec<&str>, result: &mut Vec<String>, ignored: &mut Vec<String>) -> io::Result<()> {
+        'main_loop: for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let cur_path = filesystem::pathbuf_to_string(entry.path());
+
+            for ex in exclude.iter() {
+                if filesystem::canonicalize(cur_path.as_str())? == filesystem::canonicalize(ex)? {
+                    continue 'main_loop;
+                }
+            }
+
+            let cur_is_dir = filesystem::is_dir(cur_path.as_str());
+            if self.is_ignored(cur_path.as_str(), cur_is_dir) {
+                if cur_is_dir {
+                    ignored.push(filesystem::pathbuf_to_string(entry.path().join("")));
+                } else {
+                    ignored.push(cur_path);
+                }
+                continue;
+            }
+
+            if cur_is_dir {
+                let count = self.enter_dir(cur_path.as_str());
+                self.walk_files(cur_path.as_str(), exclude, result, ignored)?;
+                self.leave_dir(count);
+            } else {
+                result.push(cur_path);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        //! Check if a file or directory is ignored, according to the last pattern matching it
+        let relative_path = match self.get_relative_path(path) {
+            Some(relative_path) if !relative_path.is_empty() => relative_path,
+            _ => return false,
+        };
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(relative_path.as_str(), is_dir))
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ignore::{IgnoreRules, Pattern};
+    #[test]
+    fn test_1_ignore_patterns() {
+        //! Check globs, anchoring, directory-only patterns, negation and nested ignore files
+        let mut rules = IgnoreRules { working_path: "/tank".to_string(), patterns: vec![] };
+        let lines = ["# comment", "", "*.swp", "target/", "/notes.txt", "docs/**/*.pdf", "!docs/manual.pdf", "[ab]?.log"];
+        rules.patterns.extend(lines.iter().filter_map(|line| Pattern::parse(line, "")));
+        rules.patterns.extend(Pattern::parse("*.txt", "src/"));
+        assert_eq!(7, rules.patterns.len());
+        assert!(rules.is_ignored("/tank/.main.rs.swp", false));
+        assert!(rules.is_ignored("/tank/src/deep/.lib.rs.swp", false));
+        assert!(rules.is_ignored("/tank/target", true));
+        assert!(rules.is_ignored("/tank/src/target", true));
+        assert!(!rules.is_ignored("/tank/target", false));
+        assert!(rules.is_ignored("/tank/notes.txt", false));
+        assert!(!rules.is_ignored("/tank/fish/notes.txt", false));
+        assert!(rules.is_ignored("/tank/docs/guide.pdf", false));
+        assert!(rules.is_ignored("/tank/docs/a/b/guide.pdf", false));
+        assert!(!rules.is_ignored("/tank/docs/manual.pdf", false));
+        assert!(rules.is_ignored("/tank/b1.log", false));
+        assert!(!rules.is_ignored("/tank/c1.log", false));
+        assert!(rules.is_ignored("/tank/src/readme.txt", false));
+        assert!(!rules.is_ignored("/tank/readme.txt", false));
+        assert!(!rules.is_ignored("/tank", true));
+    }
+}
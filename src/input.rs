@@ -1,6 +1,7 @@
 
 use crate::controller;
 use crate::display;
+use crate::error::GoldfishError;
 use std::io::{self, Write};
 use regex::Regex;
 use std::process;
@@ -8,7 +9,8 @@ use std::env;
 
 /*
     Initialize input module
-    Call print_error() in display module if we have invalid command name
+    Print the error of a failed command to stderr; outside the interactive terminal,
+    also exit with the code of its kind of failure (see GoldfishError::exit_code)
 */
 pub fn initialize() {
     let env_args = env::args().collect::<Vec<String>>();
@@ -28,28 +30,34 @@ pub fn initialize() {
             // process input by removing new line character at the end and spliting to list of args
             let re_space = Regex::new(r"[ ]+").unwrap();
             let args = re_space.split(&input[0..input.len() - 1]).collect::<Vec<&str>>();
-            // match input with command
-            process_command(args);
+            // match input with command, and keep going after a failure
+            if let Err(e) = process_command(args) {
+                display::print_error_string(e.to_string());
+            }
         }
     } else {
         // read input directly from user initial command
         let mut args = env_args.iter().map(|s| s as &str).collect::<Vec<&str>>();
         // remove executable file arg
         args.remove(0);
-        process_command(args);
+        // the exit status tells scripts which kind of failure happened
+        if let Err(e) = process_command(args) {
+            display::print_error_string(e.to_string());
+            process::exit(e.exit_code());
+        }
     }
 }
 
 /*
     Verify and process user commands
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command, or the error of the failed command
 
     @param args: list of arguments from user input
 */
-fn process_command(args: Vec<&str>) {
+fn process_command(args: Vec<&str>) -> Result<(), GoldfishError> {
     if !args.is_empty() {
         match args[0] {
-            "" => return,
+            "" => Ok(()),
             "quit" => process_quit(args),
             "help" => process_help(args),
             "init" => process_init(args),
@@ -58,47 +66,58 @@ fn process_command(args: Vec<&str>) {
             "remove" => process_remove(args),
             "status" => process_status(args),
             "heads" => process_heads(args),
+            "branch" => process_branch(args),
             "diff" => process_diff(args),
             "cat" => process_cat(args),
             "checkout" => process_checkout(args),
             "commit" => process_commit(args),
             "log" => process_log(args),
             "merge" => process_merge(args),
+            "fetch" => process_fetch(args),
             "pull" => process_pull(args),
+            "remote" => process_remote(args),
             "push" => process_push(args),
-            _ => display::print_error("Invalid command. Please type help to see our supported commands")
+            "repack" => process_repack(args),
+            "fsck" => process_fsck(args),
+            "gc" => process_gc(args),
+            "config" => process_config(args),
+            "serve" => process_serve(args),
+            _ => Err(GoldfishError::Usage(String::from("Invalid command. Please type help to see our supported commands"))),
         }
+    } else {
+        Ok(())
     }
 }
 
 /*
     Verify and process quit command
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
-fn process_quit(args: Vec<&str>) {
+fn process_quit(args: Vec<&str>) -> Result<(), GoldfishError> {
     if args.len() == 1 {
         process::exit(0);
     } else {
-        display::print_error_string(format!("Invalid number of arguments for quit. Expect 0 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(format!("Invalid number of arguments for quit. Expect 0 but got {}", args.len() - 1)))
     }
 }
 
 
 /*
     Verify and process help command
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
-fn process_help(args: Vec<&str>) {
+fn process_help(args: Vec<&str>) -> Result<(), GoldfishError> {
     if args.len() == 1 {
         display::print_help();
+        Ok(())
     } else {
-        display::print_error_string(format!("Invalid number of arguments for help. Expect 0 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(format!("Invalid number of arguments for help. Expect 0 but got {}", args.len() - 1)))
     }
 }
 
@@ -106,212 +125,378 @@ fn process_help(args: Vec<&str>) {
 
 /*
     Verify init command and process by calling method in repository module
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
 
-fn process_init(args: Vec<&str>) {
+fn process_init(args: Vec<&str>) -> Result<(), GoldfishError> {
     if args.len() == 1 {
-        controller::init();
+        controller::init()
     } else {
-        display::print_error_string(format!("Invalid number of arguments for init. Expect 0 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(format!("Invalid number of arguments for init. Expect 0 but got {}", args.len() - 1)))
     }
 }
 
 
 /*
     Verify clone command and process by calling method in repository module
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
-fn process_clone(args: Vec<&str>) {
+fn process_clone(args: Vec<&str>) -> Result<(), GoldfishError> {
     if args.len() == 3 {
-        controller::clone(args[1], args[2]);
+        controller::clone(args[1], args[2])
     } else {
-        display::print_error_string(format!("Invalid number of arguments for clone. Expect 2 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(format!("Invalid number of arguments for clone. Expect 2 but got {}", args.len() - 1)))
     }
 }
 
 /*
     Verify add command and process by calling method in model module
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
-fn process_add(args: Vec<&str>) {
+fn process_add(args: Vec<&str>) -> Result<(), GoldfishError> {
     if args.len() == 2 {
-        controller::add_track_file(args[1]);
+        controller::add_track_file(args[1])
     } else {
-        display::print_error_string(format!("Invalid number of arguments for add. Expect 1 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(format!("Invalid number of arguments for add. Expect 1 but got {}", args.len() - 1)))
     }
 }
 
 /*
     Verify remove command and process by calling method in model module
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
-fn process_remove(args: Vec<&str>) {
+fn process_remove(args: Vec<&str>) -> Result<(), GoldfishError> {
     if args.len() == 2 {
-        controller::delete_track_file(args[1]);
+        controller::delete_track_file(args[1])
     } else {
-        display::print_error_string(format!("Invalid number of arguments for remove. Expect 1 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(format!("Invalid number of arguments for remove. Expect 1 but got {}", args.len() - 1)))
     }
 }
 
 /*
     Verify status command and process by calling method in repository module
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
-fn process_status(args: Vec<&str>) {
+fn process_status(args: Vec<&str>) -> Result<(), GoldfishError> {
     if args.len() == 1 {
-        controller::status();
+        controller::status(false)
+    } else if args.len() == 2 && args[1] == "--ignored" {
+        controller::status(true)
+    } else if args.len() == 2 {
+        Err(GoldfishError::Usage(format!("Invalid argument for status: {}. Expect --ignored", args[1])))
     } else {
-        display::print_error_string(format!("Invalid number of arguments for status. Expect 0 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(format!("Invalid number of arguments for status. Expect 0 or 1 but got {}", args.len() - 1)))
     }
 }
 
 /*
     Verify heads command and process by calling method in repository module
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
-fn process_heads(args: Vec<&str>) {
+fn process_heads(args: Vec<&str>) -> Result<(), GoldfishError> {
     if args.len() == 1 {
-        controller::heads();
+        controller::heads()
+    } else {
+        Err(GoldfishError::Usage(format!("Invalid number of arguments for heads. Expect 0 but got {}", args.len() - 1)))
+    }
+}
+
+/*
+    Verify branch command and process by calling method in repository module
+    Return a usage error if we have invalid command
+    (invalid number of arguments, invalid argument, etc...)
+
+    @param args: list of arguments from user input
+*/
+fn process_branch(args: Vec<&str>) -> Result<(), GoldfishError> {
+    if args.len() == 1 || (args.len() == 2 && args[1] == "--list") {
+        controller::list_branches()
+    } else if args.len() == 3 && args[1] == "-d" {
+        controller::delete_branch(args[2])
+    } else if args.len() == 2 && !args[1].starts_with('-') {
+        controller::branch(args[1])
     } else {
-        display::print_error_string(format!("Invalid number of arguments for heads. Expect 0 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(String::from("Invalid arguments for branch. Expect [name], --list or -d [name]")))
     }
 }
 
 /*
     Verify diff command and process by calling method in repository module
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
-fn process_diff(args: Vec<&str>) {
+fn process_diff(args: Vec<&str>) -> Result<(), GoldfishError> {
     if args.len() == 3 {
-        controller::diff(args[1], args[2]);
+        controller::diff(args[1], args[2], None)
+    } else if args.len() == 4 && args[1].starts_with("-U") {
+        match args[1][2..].parse::<usize>() {
+            Ok(context) => controller::diff(args[2], args[3], Some(context)),
+            Err(_) => Err(GoldfishError::Usage(format!("Invalid number of context lines: {}", &args[1][2..]))),
+        }
     } else {
-        display::print_error_string(format!("Invalid number of arguments for diff. Expect 2 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(String::from("Invalid arguments for diff. Expect [-U<n>] [commit1] [commit2]")))
     }
 }
 
 /*
     Verify cat command and process by calling method in repository module
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
-fn process_cat(args: Vec<&str>) {
+fn process_cat(args: Vec<&str>) -> Result<(), GoldfishError> {
     if args.len() == 3 {
-        controller::cat(args[1], args[2]);
+        controller::cat(args[1], args[2])
     } else {
-        display::print_error_string(format!("Invalid number of arguments for cat. Expect 2 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(format!("Invalid number of arguments for cat. Expect 2 but got {}", args.len() - 1)))
     }
 }
 
 /*
     Verify checkout command and process by calling method in repository module
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
-fn process_checkout(args: Vec<&str>) {
-    if args.len() == 2 {
-        controller::checkout(args[1]);
+fn process_checkout(args: Vec<&str>) -> Result<(), GoldfishError> {
+    let force = args.get(1) == Some(&"--force");
+    let revisions = &args[if force { 2 } else { 1 }..];
+    if revisions.len() == 1 {
+        controller::checkout(revisions[0], force)
     } else {
-        display::print_error_string(format!("Invalid number of arguments for checkout. Expect 1 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(String::from("Invalid arguments for checkout. Expect [--force] [rev]")))
     }
 }
 
 /*
     Verify commit command and process by calling method in repository module
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
-fn process_commit(args: Vec<&str>) {
+fn process_commit(args: Vec<&str>) -> Result<(), GoldfishError> {
     if args.len() == 1 {
-        controller::commit();
+        controller::commit("")
+    } else if args.len() >= 3 && args[1] == "-m" {
+        controller::commit(parse_message(&args[2..]).as_str())
     } else {
-        display::print_error_string(format!("Invalid number of arguments for commit. Expect 0 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(String::from("Invalid arguments for commit. Expect nothing or -m [message]")))
     }
 }
 
+/*
+    Join the words of a message back together, removing the surrounding quotes
+    that the interactive terminal does not strip
+
+    @param words: the message split by spaces
+*/
+fn parse_message(words: &[&str]) -> String {
+    let message = words.join(" ");
+    let unquoted = message
+        .strip_prefix('"')
+        .and_then(|m| m.strip_suffix('"'))
+        .or_else(|| message.strip_prefix('\'').and_then(|m| m.strip_suffix('\'')));
+    unquoted.unwrap_or(message.as_str()).to_string()
+}
+
 /*
     Verify log command and process by calling method in repository module
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
-fn process_log(args: Vec<&str>) {
+fn process_log(args: Vec<&str>) -> Result<(), GoldfishError> {
     if args.len() == 1 {
-        controller::log();
+        controller::log()
     } else {
-        display::print_error_string(format!("Invalid number of arguments for log. Expect 0 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(format!("Invalid number of arguments for log. Expect 0 but got {}", args.len() - 1)))
     }
 }
 
 /*
     Verify merge command and process by calling method in repository module
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
-fn process_merge(args: Vec<&str>) {
-    if args.len() == 2 {
-        controller::merge(args[1]);
+fn process_merge(args: Vec<&str>) -> Result<(), GoldfishError> {
+    if args.len() == 2 && args[1] == "--abort" {
+        controller::merge_abort()
+    } else if args.len() == 2 && args[1] == "--continue" {
+        controller::merge_continue()
+    } else if args.len() == 2 && !args[1].starts_with('-') {
+        controller::merge(args[1])
     } else {
-        display::print_error_string(format!("Invalid number of arguments for merge. Expect 1 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(String::from("Invalid arguments for merge. Expect [rev], --abort or --continue")))
+    }
+}
+
+/*
+    Verify fetch command and process by calling method in repository module
+    Return a usage error if we have invalid command
+    (invalid number of arguments, invalid argument, etc...)
+
+    @param args: list of arguments from user input
+*/
+fn process_fetch(args: Vec<&str>) -> Result<(), GoldfishError> {
+    if args.len() <= 2 {
+        controller::fetch(args.get(1).copied())
+    } else {
+        Err(GoldfishError::Usage(format!("Invalid number of arguments for fetch. Expect 0 or 1 but got {}", args.len() - 1)))
     }
 }
 
 /*
     Verify pull command and process by calling method in repository module
-    Call print_error() in display module if we have invalid command
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
-fn process_pull(args: Vec<&str>) {
-    if args.len() == 2 {
-        controller::pull(args[1]);
+fn process_pull(args: Vec<&str>) -> Result<(), GoldfishError> {
+    if args.len() <= 2 {
+        controller::pull(args.get(1).copied())
     } else {
-        display::print_error_string(format!("Invalid number of arguments for pull. Expect 1 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(format!("Invalid number of arguments for pull. Expect 0 or 1 but got {}", args.len() - 1)))
+    }
+}
+
+/*
+    Verify remote command and process by calling method in repository module
+    Return a usage error if we have invalid command
+    (invalid number of arguments, invalid argument, etc...)
+
+    @param args: list of arguments from user input
+*/
+fn process_remote(args: Vec<&str>) -> Result<(), GoldfishError> {
+    match args.as_slice() {
+        ["remote"] | ["remote", "list"] => controller::remote_list(),
+        ["remote", "add", name, url] => controller::remote_add(name, url),
+        ["remote", "remove", name] => controller::remote_remove(name),
+        ["remote", "set-url", name, url] => controller::remote_set_url(name, url),
+        _ => Err(GoldfishError::Usage(String::from("Invalid arguments for remote. Expect list, add [name] [url], remove [name] or set-url [name] [url]"))),
     }
 }
 
 /*
     Verify push command and process by calling method in repository module
-    Call PrintError() in display module if we have invalid command
+    Return a usage error if we have invalid command
+    (invalid number of arguments, invalid argument, etc...)
+
+    @param args: list of arguments from user input
+*/
+fn process_push(args: Vec<&str>) -> Result<(), GoldfishError> {
+    let force = args.get(1) == Some(&"--force");
+    let remotes = &args[if force { 2 } else { 1 }..];
+    if remotes.len() <= 1 && !remotes.iter().any(|remote| remote.starts_with('-')) {
+        controller::push(remotes.first().copied(), force)
+    } else {
+        Err(GoldfishError::Usage(String::from("Invalid arguments for push. Expect [--force] [remote]")))
+    }
+}
+
+/*
+    Verify gc command and process by calling method in repository module
+    Return a usage error if we have invalid command
+    (invalid number of arguments, invalid argument, etc...)
+
+    @param args: list of arguments from user input
+*/
+fn process_gc(args: Vec<&str>) -> Result<(), GoldfishError> {
+    if args.len() == 1 {
+        controller::gc(false)
+    } else if args.len() == 2 && args[1] == "--dry-run" {
+        controller::gc(true)
+    } else {
+        Err(GoldfishError::Usage(String::from("Invalid arguments for gc. Expect [--dry-run]")))
+    }
+}
+
+/*
+    Verify fsck command and process by calling method in repository module
+    Return a usage error if we have invalid command
     (invalid number of arguments, invalid argument, etc...)
 
     @param args: list of arguments from user input
 */
-fn process_push(args: Vec<&str>) {
+fn process_fsck(args: Vec<&str>) -> Result<(), GoldfishError> {
+    if args.len() == 1 {
+        controller::fsck()
+    } else {
+        Err(GoldfishError::Usage(format!("Invalid number of arguments for fsck. Expect 0 but got {}", args.len() - 1)))
+    }
+}
+
+/*
+    Verify repack command and process by calling method in repository module
+    Return a usage error if we have invalid command
+    (invalid number of arguments, invalid argument, etc...)
+
+    @param args: list of arguments from user input
+*/
+fn process_repack(args: Vec<&str>) -> Result<(), GoldfishError> {
+    if args.len() == 1 {
+        controller::repack()
+    } else {
+        Err(GoldfishError::Usage(format!("Invalid number of arguments for repack. Expect 0 but got {}", args.len() - 1)))
+    }
+}
+
+/*
+    Verify serve command and process by calling method in repository module
+    Return a usage error if we have invalid command
+    (invalid number of arguments, invalid argument, etc...)
+
+    @param args: list of arguments from user input
+*/
+fn process_serve(args: Vec<&str>) -> Result<(), GoldfishError> {
     if args.len() == 2 {
-        controller::push(args[1]);
+        controller::serve(args[1])
     } else {
-        display::print_error_string(format!("Invalid number of arguments for push. Expect 1 but got {}", args.len() - 1));
+        Err(GoldfishError::Usage(format!("Invalid number of arguments for serve. Expect 1 but got {}", args.len() - 1)))
+    }
+}
+
+/*
+    Verify config command and process by calling method in repository module
+    Return a usage error if we have invalid command
+    (invalid number of arguments, invalid argument, etc...)
+
+    @param args: list of arguments from user input
+*/
+fn process_config(args: Vec<&str>) -> Result<(), GoldfishError> {
+    let scope = args.get(1).copied().filter(|arg| ["--system", "--global", "--local"].contains(arg));
+    let args = &args[if scope.is_some() { 2 } else { 1 }..];
+    match args {
+        ["get", key] => controller::config_get(scope, key),
+        ["set", key, value @ ..] if !value.is_empty() => controller::config_set(scope, key, parse_message(value).as_str()),
+        ["unset", key] => controller::config_unset(scope, key),
+        ["list"] => controller::config_list(scope),
+        _ => Err(GoldfishError::Usage(String::from("Invalid arguments for config. Expect [--system|--global|--local] get [key], set [key] [value], unset [key] or list"))),
     }
-}
\ No newline at end of file
+}
@@ -1,10 +1,15 @@
 mod model;
+mod config;
 mod controller;
 mod utilities;
 mod filesystem;
+mod fsck;
+mod ignore;
 mod networking;
+mod pack;
 mod input;
 mod display;
+mod error;
 
 
 fn main() {
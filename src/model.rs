@@ -1,26 +1,76 @@
+use crate::config;
+use crate::config::{Config, ConfigValue, Settings};
+use crate::error::GoldfishError;
 use crate::filesystem;
+use crate::pack::Pack;
 use crate::utilities;
-use std::collections::{HashMap, HashSet};
+use std::cell::{Cell, RefCell};
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
 use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 // root
 pub const GOLDFISH_ROOT_DIR: &str = ".goldfish";
 
 // top-level directories
 pub const BLOBS_DIR: &str = "blobs";
+pub const TREES_DIR: &str = "trees";
 pub const STAGING_DIR: &str = "staging";
 pub const COMMITS_DIR: &str = "commits";
 pub const BRANCHES_DIR: &str = "branches";
+pub const PACKS_DIR: &str = "packs";
+pub const REMOTES_DIR: &str = "remotes";
 
 // top-level files
 pub const HEAD: &str = "HEAD";
+pub const MERGE_HEAD: &str = "MERGE_HEAD";
 pub const TRACKEDFILES: &str = "tracked_files";
+pub const UNMERGED_PATHS: &str = "unmerged_paths";
+pub const CONFIG: &str = "config";
 
+// HEAD content prefix when HEAD follows a branch, e.g. `ref: branches/main`
+pub const HEAD_REF_PREFIX: &str = "ref: ";
+// branch that a freshly initialized repository is on
+pub const DEFAULT_BRANCH: &str = "main";
 
-fn resolve_reference(reference: &str) -> Option<String> {
+// remote that clone downloads from and fetch, pull and push use for an url
+pub const DEFAULT_REMOTE: &str = "origin";
+
+
+pub fn resolve_reference(repo: &Repository, reference: &str) -> Option<String> {
     //! If given a branch name, resolve that branch name to the associated commit id
     //! else if given a commit id, return that commit id
-    Some(reference.to_string())
+    //! Returns None if the reference matches neither a branch nor an existing commit
+    if reference == HEAD {
+        let commit_id = repo.get_current_commit_id().ok()?;
+        return if commit_id.is_empty() { None } else { Some(commit_id) }
+    }
+    if is_valid_branch_name(reference) && repo.branch_exists(reference) {
+        return repo.read_branch(reference).ok()
+    }
+    // `{remote}/{branch}` or `remotes/{remote}/{branch}` for a remote-tracking branch
+    let remote_reference = reference.strip_prefix("remotes/").unwrap_or(reference);
+    if let Some((remote, branch)) = remote_reference.split_once('/') {
+        if is_valid_remote_name(remote) && is_valid_branch_name(branch) && repo.remote_branch_exists(remote, branch) {
+            return repo.read_remote_branch(remote, branch).ok()
+        }
+    }
+    if repo.has_object(COMMITS_DIR, reference) {
+        return Some(reference.to_string())
+    }
+    None
+}
+
+pub fn is_valid_branch_name(name: &str) -> bool {
+    //! A branch name may contain letters, digits, `-`, `_`, `.` and `/` (for grouping
+    //! branches into folders), but cannot start with `-`, be `HEAD`, contain `..`,
+    //! or have empty path components
+    !name.is_empty()
+        && name != HEAD
+        && !name.starts_with('-')
+        && !name.contains("..")
+        && name.split('/').all(|part| !part.is_empty())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_./".contains(c))
 }
 
 #[derive(Debug)]
@@ -29,6 +79,10 @@ pub struct Repository {
     working_path: String,
     // .Goldfish path
     repo_path: String,
+    // index of the pack files, loaded the first time a packed object is needed
+    packs: RefCell<Option<Vec<Pack>>>,
+    // the `core.verifyObjects` setting, read the first time an object is read
+    verify_objects: Cell<Option<bool>>,
 }
 
 impl Repository {
@@ -41,17 +95,36 @@ impl Repository {
 
         let current_repo_path = filesystem::join_path(vec![path, GOLDFISH_ROOT_DIR]);
         if filesystem::is_dir(current_repo_path.as_str()) {
-            return Some(Repository { working_path: path.to_owned(), repo_path: current_repo_path })
+            return Some(Repository {
+                working_path: path.to_owned(),
+                repo_path: current_repo_path,
+                packs: RefCell::new(None),
+                verify_objects: Cell::new(None),
+            })
         }
 
         let parent = filesystem::parent(path)?;  // return None if there is no parent path
         Repository::find(parent.as_str())  // recursively find in parent path
     }
 
-    pub fn get_current_commit_id(&self) -> io::Result<String> {
+    pub fn find_current() -> Result<Repository, GoldfishError> {
+        //! Find the repository that the current directory is in
+        let current_dir = std::env::current_dir().map_err(|e| GoldfishError::io("Fail to read the current directory", e))?;
+        Repository::find(filesystem::pathbuf_to_string(current_dir).as_str()).ok_or(GoldfishError::NotARepository)
+    }
+
+    pub fn get_current_commit_id(&self) -> Result<String, GoldfishError> {
         //! Return the current commit id or an empty string if this is a fresh repository
-        let head_content = filesystem::read_file(filesystem::join_path(vec![&self.repo_path, HEAD]).as_str())?;
-        Ok(resolve_reference(head_content.trim()).unwrap())
+        //! (or HEAD follows a branch that has no commits yet)
+        match parse_symbolic_head(self.read_head()?.as_str()) {
+            Some(branch_name) => {
+                if !self.branch_exists(branch_name.as_str()) {
+                    return Ok(String::new())
+                }
+                self.read_branch(branch_name.as_str())
+            }
+            None => Ok(self.read_head()?.trim().to_string()),
+        }
     }
 
     pub fn get_repo_path<'a>(&'a self) -> &'a str {
@@ -62,16 +135,298 @@ impl Repository {
         &self.working_path
     }
 
-    pub fn get_commits_path(&self) -> String {
-        filesystem::join_path(vec![&self.repo_path, COMMITS_DIR])
-    }
-
     pub fn get_staging_path(&self) -> String {
         filesystem::join_path(vec![&self.repo_path, STAGING_DIR])
     }
+}
+
+// Reading and writing objects (blobs, trees and commits)
+impl Repository {
+    pub fn get_object_path(&self, object_dir: &str, id: &str) -> String {
+        //! Path of the loose (unpacked) object file `{object_dir}/{id}`
+        filesystem::join_path(vec![&self.repo_path, object_dir, id])
+    }
+
+    pub fn get_packs_path(&self) -> String {
+        filesystem::join_path(vec![&self.repo_path, PACKS_DIR])
+    }
+
+    fn with_packs<T>(&self, action: impl FnOnce(&Vec<Pack>) -> T) -> T {
+        //! Run the action with the pack indexes, loading them if they were not loaded yet.
+        //! A repository whose packs cannot be read behaves as if it had no packs
+        let mut packs = self.packs.borrow_mut();
+        let packs = packs.get_or_insert_with(|| Pack::load_all(self.get_packs_path().as_str()).unwrap_or_default());
+        action(packs)
+    }
+
+    pub fn get_packs(&self) -> Vec<String> {
+        //! Return the paths of all pack files
+        self.with_packs(|packs| packs.iter().map(|pack| pack.get_pack_path().to_string()).collect())
+    }
+
+    pub fn reload_packs(&self) {
+        //! Forget the loaded pack indexes, so packs that were added or removed are seen
+        self.packs.replace(None);
+    }
+
+    pub fn has_object(&self, object_dir: &str, id: &str) -> bool {
+        !id.is_empty()
+            && (filesystem::is_file(&self.get_object_path(object_dir, id))
+                || self.with_packs(|packs| packs.iter().any(|pack| pack.contains(object_dir, id))))
+    }
+
+    pub fn list_loose_objects(&self, object_dir: &str) -> io::Result<Vec<String>> {
+        //! Return the ids of all loose objects of the given type
+        let dir_path = filesystem::join_path(vec![&self.repo_path, object_dir]);
+        if !filesystem::is_dir(dir_path.as_str()) {
+            return Ok(vec![])
+        }
+        Ok(filesystem::list_files(dir_path.as_str(), false, &vec![])?
+            .iter()
+            .filter_map(|path| filesystem::diff_path(dir_path.as_str(), path))
+            .collect())
+    }
+
+    pub fn list_objects(&self, object_dir: &str) -> io::Result<Vec<String>> {
+        //! Return the ids of all objects of the given type, loose or packed
+        let mut ids: HashSet<String> = self.list_loose_objects(object_dir)?.into_iter().collect();
+        self.with_packs(|packs| {
+            for pack in packs {
+                ids.extend(pack.list_objects(object_dir));
+            }
+        });
+        let mut ids: Vec<String> = ids.into_iter().collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    pub fn write_object(&self, object_dir: &str, id: &str, content: &[u8]) -> io::Result<()> {
+        //! Write the content of an object, compressed with zlib, to `{object_dir}/{id}`
+        filesystem::write_file(utilities::compress(content)?, &self.get_object_path(object_dir, id))
+    }
+
+    pub fn read_object(&self, object_dir: &str, id: &str) -> Result<Vec<u8>, GoldfishError> {
+        //! Read the uncompressed content of the object `{object_dir}/{id}`,
+        //! from its loose object file or else from the pack that contains it.
+        //! With the `core.verifyObjects` setting, an object that does not hash to its id is an error
+        let kind = get_object_kind(object_dir);
+        let content = self.read_unverified_object(object_dir, id).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => GoldfishError::CorruptObject(format!("Missing {} {}", kind, id)),
+            io::ErrorKind::InvalidData => GoldfishError::CorruptObject(format!("Cannot read {} {}: {}", kind, id, e)),
+            _ => GoldfishError::io(format!("Fail to read {} {}", kind, id), e),
+        })?;
+        if self.should_verify_objects() {
+            check_object_id(object_dir, id, &content)?;
+        }
+        Ok(content)
+    }
+
+    pub fn read_unverified_object(&self, object_dir: &str, id: &str) -> io::Result<Vec<u8>> {
+        //! Read an object without checking its hash
+        let object_path = self.get_object_path(object_dir, id);
+        if id.is_empty() || !filesystem::is_file(object_path.as_str()) {
+            return self.with_packs(|packs| match packs.iter().find(|pack| pack.contains(object_dir, id)) {
+                Some(pack) => pack.read_object(object_dir, id),
+                None => Err(io::Error::new(io::ErrorKind::NotFound, format!("Object {} not found", id))),
+            })
+        }
+        let raw = filesystem::read_bytes(object_path.as_str())?;
+        // objects written before compression was introduced start with their plain text
+        // header, which can never be the start of a zlib stream
+        if [&b"blob\n"[..], b"tree\n", b"commit\n"].iter().any(|header| raw.starts_with(header)) {
+            return Ok(raw)
+        }
+        utilities::decompress(&raw)
+    }
+
+    pub fn get_object_stats(&self, object_dir: &str, id: &str) -> io::Result<(u64, SystemTime)> {
+        //! Return the space an object takes on disk (as a loose file and in packs), and when
+        //! it was last written (the time of its loose file, or of the newest pack holding it)
+        let mut size = 0;
+        let mut modified = UNIX_EPOCH;
+        let object_path = self.get_object_path(object_dir, id);
+        if filesystem::is_file(object_path.as_str()) {
+            size += filesystem::file_size(object_path.as_str())?;
+            modified = modified.max(filesystem::modified_time(object_path.as_str())?);
+        }
+        self.with_packs(|packs| {
+            for pack in packs.iter().filter(|pack| pack.contains(object_dir, id)) {
+                size += pack.get_entry_size(object_dir, id)?;
+                modified = modified.max(filesystem::modified_time(pack.get_pack_path())?);
+            }
+            Ok((size, modified))
+        })
+    }
 
-    pub fn get_blobs_path(&self) -> String {
-        filesystem::join_path(vec![&self.repo_path, BLOBS_DIR])
+    fn should_verify_objects(&self) -> bool {
+        if let Some(verify_objects) = self.verify_objects.get() {
+            return verify_objects
+        }
+        let verify_objects = self.get_setting::<bool>(config::VERIFY_OBJECTS).ok().flatten().unwrap_or(false);
+        self.verify_objects.set(Some(verify_objects));
+        verify_objects
+    }
+}
+
+// Interacting with branches
+impl Repository {
+    pub fn get_branches_path(&self) -> String {
+        filesystem::join_path(vec![&self.repo_path, BRANCHES_DIR])
+    }
+
+    fn get_branch_path(&self, branch_name: &str) -> String {
+        filesystem::join_path(vec![self.get_branches_path().as_str(), branch_name])
+    }
+
+    pub fn branch_exists(&self, branch_name: &str) -> bool {
+        filesystem::is_file(&self.get_branch_path(branch_name))
+    }
+
+    pub fn read_branch(&self, branch_name: &str) -> Result<String, GoldfishError> {
+        //! Return the commit id that the given branch points to
+        match filesystem::read_file(&self.get_branch_path(branch_name)) {
+            Ok(commit_id) => Ok(commit_id.trim().to_string()),
+            Err(e) => Err(GoldfishError::io(format!("Fail to load branch {}", branch_name), e)),
+        }
+    }
+
+    pub fn write_branch(&self, branch_name: &str, commit_id: &str) -> Result<(), GoldfishError> {
+        //! Point the given branch to the given commit id, creating the branch if needed
+        filesystem::write_file(format!("{}\n", commit_id).as_str(), &self.get_branch_path(branch_name))
+            .map_err(|e| GoldfishError::io(format!("Fail to save branch {}", branch_name), e))
+    }
+
+    pub fn delete_branch(&self, branch_name: &str) -> Result<(), GoldfishError> {
+        filesystem::remove(&self.get_branch_path(branch_name))
+            .map_err(|e| GoldfishError::io(format!("Fail to delete branch {}", branch_name), e))
+    }
+
+    pub fn list_branches(&self) -> Result<Vec<String>, GoldfishError> {
+        //! Return the names of all branches, sorted alphabetically
+        let branches_path = self.get_branches_path();
+        if !filesystem::is_dir(&branches_path) {
+            return Ok(vec![])
+        }
+        match filesystem::list_files(&branches_path, true, &vec![]) {
+            Ok(files) => {
+                let mut branches: Vec<String> = files
+                    .iter()
+                    .filter_map(|file| filesystem::diff_path(&branches_path, file))
+                    .collect();
+                branches.sort();
+                Ok(branches)
+            }
+            Err(e) => Err(GoldfishError::io("Fail to list branches", e)),
+        }
+    }
+}
+
+pub fn is_valid_remote_name(name: &str) -> bool {
+    //! A remote name follows the branch name rules, without `/`
+    is_valid_branch_name(name) && !name.contains('/')
+}
+
+// Interacting with remote-tracking branches: the last known commit of each branch of a remote
+impl Repository {
+    fn get_remote_branches_path(&self, remote: &str) -> String {
+        filesystem::join_path(vec![&self.repo_path, REMOTES_DIR, remote])
+    }
+
+    fn get_remote_branch_path(&self, remote: &str, branch_name: &str) -> String {
+        filesystem::join_path(vec![self.get_remote_branches_path(remote).as_str(), branch_name])
+    }
+
+    pub fn remote_branch_exists(&self, remote: &str, branch_name: &str) -> bool {
+        filesystem::is_file(&self.get_remote_branch_path(remote, branch_name))
+    }
+
+    pub fn read_remote_branch(&self, remote: &str, branch_name: &str) -> Result<String, GoldfishError> {
+        match filesystem::read_file(&self.get_remote_branch_path(remote, branch_name)) {
+            Ok(commit_id) => Ok(commit_id.trim().to_string()),
+            Err(e) => Err(GoldfishError::io(format!("Fail to load remote-tracking branch {}/{}", remote, branch_name), e)),
+        }
+    }
+
+    pub fn write_remote_branch(&self, remote: &str, branch_name: &str, commit_id: &str) -> Result<(), GoldfishError> {
+        filesystem::write_file(format!("{}\n", commit_id).as_str(), &self.get_remote_branch_path(remote, branch_name))
+            .map_err(|e| GoldfishError::io(format!("Fail to save remote-tracking branch {}/{}", remote, branch_name), e))
+    }
+
+    pub fn delete_remote_branch(&self, remote: &str, branch_name: &str) -> Result<(), GoldfishError> {
+        filesystem::remove(&self.get_remote_branch_path(remote, branch_name))
+            .map_err(|e| GoldfishError::io(format!("Fail to delete remote-tracking branch {}/{}", remote, branch_name), e))
+    }
+
+    pub fn delete_remote_branches(&self, remote: &str) -> Result<(), GoldfishError> {
+        //! Delete every remote-tracking branch of a remote
+        let remote_path = self.get_remote_branches_path(remote);
+        if !filesystem::is_dir(&remote_path) {
+            return Ok(())
+        }
+        filesystem::remove(&remote_path)
+            .map_err(|e| GoldfishError::io(format!("Fail to delete the remote-tracking branches of {}", remote), e))
+    }
+
+    pub fn list_remotes_with_branches(&self) -> Result<Vec<String>, GoldfishError> {
+        //! Return the names of the remotes that have remote-tracking branches, sorted alphabetically
+        let remotes_path = filesystem::join_path(vec![&self.repo_path, REMOTES_DIR]);
+        if !filesystem::is_dir(&remotes_path) {
+            return Ok(vec![])
+        }
+        match filesystem::list_files(&remotes_path, true, &vec![]) {
+            Ok(files) => {
+                let mut remotes: Vec<String> = files
+                    .iter()
+                    .filter_map(|file| filesystem::diff_path(&remotes_path, file))
+                    .filter_map(|file| Some(file.split_once('/')?.0.to_string()))
+                    .collect();
+                remotes.sort();
+                remotes.dedup();
+                Ok(remotes)
+            }
+            Err(e) => Err(GoldfishError::io("Fail to list remotes", e)),
+        }
+    }
+
+    pub fn list_remote_branches(&self, remote: &str) -> Result<Vec<String>, GoldfishError> {
+        //! Return the names of the remote-tracking branches of a remote, sorted alphabetically
+        let remote_path = self.get_remote_branches_path(remote);
+        if !filesystem::is_dir(&remote_path) {
+            return Ok(vec![])
+        }
+        match filesystem::list_files(&remote_path, true, &vec![]) {
+            Ok(files) => {
+                let mut branches: Vec<String> = files
+                    .iter()
+                    .filter_map(|file| filesystem::diff_path(&remote_path, file))
+                    .collect();
+                branches.sort();
+                Ok(branches)
+            }
+            Err(e) => Err(GoldfishError::io(format!("Fail to list the branches of remote {}", remote), e)),
+        }
+    }
+}
+
+// Interacting with the config
+impl Repository {
+    pub fn get_config_path(&self) -> String {
+        filesystem::join_path(vec![&self.repo_path, CONFIG])
+    }
+
+    pub fn load_config(&self) -> Result<Config, GoldfishError> {
+        //! The repository config file only, for changing it
+        Config::load(self.get_config_path().as_str())
+    }
+
+    pub fn load_settings(&self) -> Result<Settings, GoldfishError> {
+        //! The settings of the system, user and repository config files
+        Settings::load(Some(self.get_config_path().as_str()))
+    }
+
+    pub fn get_setting<T: ConfigValue>(&self, key: &str) -> Result<Option<T>, GoldfishError> {
+        //! Read a setting from the config files as the given type
+        self.load_settings()?.get(key)
     }
 }
 
@@ -81,19 +436,111 @@ impl Repository {
         filesystem::join_path(vec![&self.repo_path, HEAD])
     }
 
-    pub fn read_head(&self) -> Result<String, String> {
-        match filesystem::read_file(&self.get_head_path()) {
-            Ok(goldfish_head) => Ok(goldfish_head),
-            Err(_e) => Err(String::from("Fail to load HEAD file"))
+    pub fn read_head(&self) -> Result<String, GoldfishError> {
+        filesystem::read_file(&self.get_head_path()).map_err(|e| GoldfishError::io("Fail to load HEAD file", e))
+    }
+
+    pub fn write_head(&self, goldfish_head: String) -> Result<(), GoldfishError> {
+        filesystem::write_file(goldfish_head.as_str(), &self.get_head_path()).map_err(|e| GoldfishError::io("Fail to save HEAD", e))
+    }
+
+    pub fn write_symbolic_head(&self, branch_name: &str) -> Result<(), GoldfishError> {
+        //! Make HEAD follow the given branch
+        self.write_head(format!("{}{}/{}\n", HEAD_REF_PREFIX, BRANCHES_DIR, branch_name))
+    }
+
+    pub fn get_current_branch(&self) -> Option<String> {
+        //! Return the branch HEAD follows, or None if HEAD is detached
+        parse_symbolic_head(self.read_head().ok()?.as_str())
+    }
+
+    pub fn update_head(&self, commit_id: &str) -> Result<(), GoldfishError> {
+        //! Move HEAD to the given commit. If HEAD follows a branch, the branch is moved instead
+        //! so HEAD keeps following it; otherwise HEAD stays detached at the new commit
+        match self.get_current_branch() {
+            Some(branch_name) => self.write_branch(branch_name.as_str(), commit_id),
+            None => self.write_head(format!("{}\n", commit_id)),
         }
     }
+}
+
+// Interacting with MERGE_HEAD, the commit being merged while a merge waits for its conflicts to be resolved
+impl Repository {
+    fn get_merge_head_path(&self) -> String {
+        filesystem::join_path(vec![&self.repo_path, MERGE_HEAD])
+    }
 
-    pub fn write_head(&self, goldfish_head: String) -> Option<String> {
-        match filesystem::write_file(goldfish_head.as_str(), &self.get_head_path()) {
-            Ok(_v) => return None,
-            Err(_e) => return Some(String::from("Fail to save HEAD")),
+    pub fn read_merge_head(&self) -> Option<String> {
+        //! Return the commit being merged, or None if no merge is in progress
+        let merge_head = filesystem::read_file(&self.get_merge_head_path()).ok()?;
+        Some(merge_head.trim().to_string()).filter(|commit_id| !commit_id.is_empty())
+    }
+
+    pub fn write_merge_head(&self, commit_id: &str) -> Result<(), GoldfishError> {
+        filesystem::write_file(format!("{}\n", commit_id), &self.get_merge_head_path())
+            .map_err(|e| GoldfishError::io("Fail to save MERGE_HEAD", e))
+    }
+
+    pub fn clear_merge_head(&self) -> Result<(), GoldfishError> {
+        if !filesystem::is_file(&self.get_merge_head_path()) {
+            return Ok(());
+        }
+        filesystem::remove(&self.get_merge_head_path()).map_err(|e| GoldfishError::io("Fail to remove MERGE_HEAD", e))
+    }
+}
+
+// Interacting with the list of files left with conflicts by a merge
+impl Repository {
+    fn get_unmerged_paths_path(&self) -> String {
+        filesystem::join_path(vec![&self.repo_path, UNMERGED_PATHS])
+    }
+
+    pub fn get_unmerged_paths(&self) -> Result<Vec<UnmergedPath>, GoldfishError> {
+        //! Return the conflicted files that have not been resolved yet
+        if !filesystem::is_file(&self.get_unmerged_paths_path()) {
+            return Ok(vec![]);
+        }
+        match filesystem::read_file(&self.get_unmerged_paths_path()) {
+            Ok(content) => content
+                .lines()
+                .map(|line| {
+                    UnmergedPath::parse(line)
+                        .ok_or_else(|| GoldfishError::CorruptObject(String::from("The list of unmerged paths is corrupted")))
+                })
+                .collect(),
+            Err(e) => Err(GoldfishError::io("Fail to load the list of unmerged paths", e)),
         }
     }
+
+    pub fn save_unmerged_paths(&self, unmerged_paths: &[UnmergedPath]) -> Result<(), GoldfishError> {
+        //! Replace the list of unmerged paths, removing the file once every conflict is resolved
+        let path = self.get_unmerged_paths_path();
+        let result = if unmerged_paths.is_empty() {
+            if filesystem::is_file(&path) { filesystem::remove(&path) } else { Ok(()) }
+        } else {
+            let content: String = unmerged_paths.iter().map(|unmerged_path| unmerged_path.serialize()).collect();
+            filesystem::write_file(content, &path)
+        };
+        result.map_err(|e| GoldfishError::io("Fail to save the list of unmerged paths", e))
+    }
+
+    pub fn resolve_unmerged_path(&self, rel_file_path_to_wd: &str) -> Result<(), GoldfishError> {
+        //! Mark a conflicted file as resolved
+        let mut unmerged_paths = self.get_unmerged_paths()?;
+        let count = unmerged_paths.len();
+        unmerged_paths.retain(|unmerged_path| unmerged_path.get_path() != rel_file_path_to_wd);
+        if unmerged_paths.len() == count {
+            return Ok(());
+        }
+        self.save_unmerged_paths(&unmerged_paths)
+    }
+}
+
+pub fn parse_symbolic_head(head_content: &str) -> Option<String> {
+    //! Return the branch name if the content of HEAD is `ref: branches/{branch_name}`
+    let reference = head_content.trim().strip_prefix(HEAD_REF_PREFIX)?;
+    let branch_name = reference.strip_prefix(BRANCHES_DIR)?.strip_prefix('/')?;
+    Some(branch_name.to_string())
 }
 
 // Interacting with list of tracked files
@@ -102,20 +549,18 @@ impl Repository {
         filesystem::join_path(vec![&self.repo_path, TRACKEDFILES])
     }
 
-    pub fn get_staging_tracked_files(&self) -> Result<HashMap<String, String>, String> {
-        match filesystem::read_file(&self.get_track_files_path()) {
-            Ok(_raw_tracked_files) => {
-                // parse tracked files
-                let raw_tracked_files = filesystem::read_file(self.get_track_files_path().as_str()).unwrap_or(String::from(""));
-                let mut tracked_file: HashMap<String, String> = HashMap::new();
-                for line in raw_tracked_files.split_terminator('\n') {
-                    let items: Vec<&str> = line.split(" ").collect();
-                    tracked_file.insert(String::from(items[0]), String::from(items[1]));
-                }
-                Ok(tracked_file)
-            },
-            Err(_e) => Err(String::from("Fail to get staging list of tracked files")),
+    pub fn get_staging_tracked_files(&self) -> Result<HashMap<String, String>, GoldfishError> {
+        let raw_tracked_files = filesystem::read_file(&self.get_track_files_path())
+            .map_err(|e| GoldfishError::io("Fail to get staging list of tracked files", e))?;
+        // parse tracked files
+        let mut tracked_file: HashMap<String, String> = HashMap::new();
+        for line in raw_tracked_files.split_terminator('\n') {
+            let (file_path, blob_id) = line
+                .rsplit_once(' ')
+                .ok_or_else(|| GoldfishError::CorruptObject(String::from("The staging list of tracked files is corrupted")))?;
+            tracked_file.insert(String::from(file_path), String::from(blob_id));
         }
+        Ok(tracked_file)
     }
 
     pub fn get_file_content_hash(&self, rel_file_path_to_wd: &str) -> Option<String> {
@@ -131,40 +576,30 @@ impl Repository {
         }
     }
 
-    pub fn save_staging_tracked_files(&self, tracked_file: HashMap<String, String>) -> Option<String> {
+    pub fn save_staging_tracked_files(&self, tracked_file: HashMap<String, String>) -> Result<(), GoldfishError> {
         let mut raw_new_tracked_files = String::new();
         for (k, v) in tracked_file {
             raw_new_tracked_files.push_str(format!("{} {}\n", k, v).as_str());
         }
-        match filesystem::write_file(raw_new_tracked_files.as_str(), &self.get_track_files_path()) {
-            Ok(_x) => None,
-            Err(_e) => Some(String::from("Fail to save staging list of tracked file")),
-        }
+        filesystem::write_file(raw_new_tracked_files.as_str(), &self.get_track_files_path())
+            .map_err(|e| GoldfishError::io("Fail to save staging list of tracked file", e))
     }
 
-    pub fn track_file(&self, abs_file_path: &str) -> Option<String> {
+    pub fn track_file(&self, abs_file_path: &str) -> Result<(), GoldfishError> {
         // parse tracked files
-        let mut tracked_file;
-        match self.get_staging_tracked_files() {
-            Ok(files) => tracked_file = files,
-            Err(e) => return Some(e),
-        }
+        let mut tracked_file = self.get_staging_tracked_files()?;
         // track files
-        let file_content = filesystem::read_file(abs_file_path).unwrap();
-        let file_content_hash = utilities::hash(file_content.as_str());
+        let file_content = filesystem::read_bytes(abs_file_path).map_err(|e| GoldfishError::io(format!("Fail to read {}", abs_file_path), e))?;
+        let file_content_hash = utilities::hash(&file_content);
         let rel_file_path_to_wd = filesystem::get_relative_path_from_base(self.get_working_path(), abs_file_path);
         tracked_file.insert(rel_file_path_to_wd, file_content_hash);
         // write back state
         self.save_staging_tracked_files(tracked_file)
     }
 
-    pub fn untrack_file(&self, abs_file_path: &str) -> Option<String> {
+    pub fn untrack_file(&self, abs_file_path: &str) -> Result<(), GoldfishError> {
         // parse tracked files
-        let mut tracked_file;
-        match self.get_staging_tracked_files() {
-            Ok(files) => tracked_file = files,
-            Err(e) => return Some(e),
-        }
+        let mut tracked_file = self.get_staging_tracked_files()?;
         // untrack files
         let rel_file_path_to_wd = filesystem::get_relative_path_from_base(self.get_working_path(), abs_file_path);
         tracked_file.remove(rel_file_path_to_wd.as_str());
@@ -173,22 +608,141 @@ impl Repository {
     }
 }
 
+/**
+ * A file that a merge left with a conflict, with its blob id in the merge base, in the
+ * current commit (ours) and in the merged commit (theirs). Stored in the unmerged_paths file as
+ * ```
+ * {base_blob_id} {ours_blob_id} {theirs_blob_id} {path}\n
+ * ```
+ * where a blob id is `-` if the file does not exist in that commit
+ */
+#[derive(Debug, Clone)]
+pub struct UnmergedPath {
+    path: String,
+    base_blob_id: Option<String>,
+    ours_blob_id: Option<String>,
+    theirs_blob_id: Option<String>,
+}
+
+impl UnmergedPath {
+    pub fn new(path: &str, base_blob_id: Option<&String>, ours_blob_id: Option<&String>, theirs_blob_id: Option<&String>) -> UnmergedPath {
+        UnmergedPath {
+            path: path.to_string(),
+            base_blob_id: base_blob_id.cloned(),
+            ours_blob_id: ours_blob_id.cloned(),
+            theirs_blob_id: theirs_blob_id.cloned(),
+        }
+    }
+
+    fn parse(line: &str) -> Option<UnmergedPath> {
+        let parse_id = |id: &str| if id == "-" { None } else { Some(id.to_string()) };
+        let mut items = line.splitn(4, ' ');
+        let base_blob_id = parse_id(items.next()?);
+        let ours_blob_id = parse_id(items.next()?);
+        let theirs_blob_id = parse_id(items.next()?);
+        Some(UnmergedPath { path: items.next()?.to_string(), base_blob_id, ours_blob_id, theirs_blob_id })
+    }
+
+    fn serialize(&self) -> String {
+        let id = |blob_id: &Option<String>| blob_id.clone().unwrap_or_else(|| String::from("-"));
+        format!("{} {} {} {}\n", id(&self.base_blob_id), id(&self.ours_blob_id), id(&self.theirs_blob_id), self.path)
+    }
+
+    pub fn get_path(&self) -> &str {
+        self.path.as_str()
+    }
+
+    pub fn get_blob_ids(&self) -> Vec<&str> {
+        //! The blobs of the file in the merge base and on both sides, where it exists
+        [&self.base_blob_id, &self.ours_blob_id, &self.theirs_blob_id].into_iter().flatten().map(|id| id.as_str()).collect()
+    }
+
+    pub fn get_kind(&self) -> &str {
+        //! Describe how the two sides of the merge conflict on this file
+        match (&self.base_blob_id, &self.ours_blob_id, &self.theirs_blob_id) {
+            (_, None, _) => "deleted by us",
+            (_, _, None) => "deleted by them",
+            (None, _, _) => "both added",
+            _ => "both modified",
+        }
+    }
+}
+
+/**
+ * The person who created a commit and when they created it. Stored in a commit file as
+ * ```
+ * author {name} <{email}> {seconds since the Unix epoch (UTC)}\n
+ * ```
+ */
+#[derive(Debug, Clone)]
+pub struct Signature {
+    name: String,
+    email: String,
+    timestamp: i64,
+}
+
+impl Signature {
+    pub fn now(name: &str, email: &str) -> Signature {
+        //! Create a signature for the given identity, timestamped with the current UTC time
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or(0);
+        Signature { name: name.to_string(), email: email.to_string(), timestamp }
+    }
+
+    fn parse(raw: &str) -> Option<Signature> {
+        //! Parse the `{name} <{email}> {timestamp}` part of an author line
+        let (identity, timestamp) = raw.trim().rsplit_once(' ')?;
+        let (name, email) = identity.strip_suffix('>')?.split_once('<')?;
+        Some(Signature {
+            name: name.trim().to_string(),
+            email: email.to_string(),
+            timestamp: timestamp.parse().ok()?,
+        })
+    }
+
+    fn serialize(&self) -> String {
+        format!("{} <{}> {}", self.name, self.email, self.timestamp)
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn get_timestamp(&self) -> i64 {
+        self.timestamp
+    }
+}
+
 /**
  * This is an interface for interacting with commit files. Commit files' filename is
  * the hash digest of their file content, and they have the following format:
  * ```
  * commit\n
+ * tree {root_tree_id}\n
  * parent {direct_parent_id}\n
  * {{ zero or more lines of `parent {parent_id}\n' for any other (merged) parents` }}
- * file {file_path} {blob_id}\n
- * {{ more file lines if necessary }}
+ * author {name} <{email}> {timestamp}\n
+ * \n
+ * {{ commit message }}
  * ```
+ * Commits made before author and message were recorded have neither the author line
+ * nor the message section. Commits made before trees were introduced have no tree line,
+ * and instead list every file as a `tracked_file {file_path} {blob_id}\n` line.
  */
 #[derive(Debug)]
 pub struct Commit<'a> {
     id: String,
     direct_parent_id: String,
     secondary_parent_ids: Vec<String>,
+    tree_id: Option<String>,
+    author: Option<Signature>,
+    message: String,
     repo: &'a Repository,
 }
 
@@ -196,139 +750,228 @@ pub struct Commit<'a> {
 
 
 impl<'a> Commit<'a> {
-    pub fn create(repo: &Repository, direct_parent_id: String, secondary_parent_ids: Vec<String>, tracked_files: HashMap<String, String>) -> io::Result<Commit> {
+    pub fn create(
+        repo: &'a Repository,
+        direct_parent_id: String,
+        secondary_parent_ids: Vec<String>,
+        tracked_files: HashMap<String, String>,
+        author: Signature,
+        message: &str,
+    ) -> Result<Commit<'a>, GoldfishError> {
         // TODO: assert non-empty file_list; a commit cannot have no files
 
-        let mut content = format!("commit\nparent {}\n", direct_parent_id);
+        let tree = Tree::create_from_tracked_files(repo, &tracked_files)
+            .map_err(|e| GoldfishError::io("Fail to write the trees of the commit", e))?;
+        let mut content = format!("commit\ntree {}\nparent {}\n", tree.get_id(), direct_parent_id);
 
         // add secondary parents
         for parent in secondary_parent_ids.iter() {
             content = format!("{}parent {}\n", content, parent);
         }
 
-        // add tracked file list
-        for (file_path, hash) in tracked_files.iter() {
-            content = format!("{}tracked_file {} {}\n", content, file_path, hash);
-        }
+        content = format!("{}author {}\n", content, author.serialize());
+
+        // the message goes last, after a blank line, so it may span several lines
+        let message = message.trim();
+        content = format!("{}\n{}\n", content, message);
 
         let commit_id = utilities::hash(content.as_str());
-        let commit_path = filesystem::join_path(vec![repo.get_commits_path().as_str(), commit_id.as_str()]);
 
         // write commit file
-        filesystem::write_file(content.as_str(), commit_path.as_str())?;
+        repo.write_object(COMMITS_DIR, commit_id.as_str(), content.as_bytes())
+            .map_err(|e| GoldfishError::io("Fail to write the commit file", e))?;
 
-        // update HEAD file
-        repo.write_head(String::from(&commit_id));
+        // move HEAD (or the branch HEAD follows) to the new commit
+        repo.update_head(commit_id.as_str())?;
 
         Ok(Commit {
             id: commit_id,
-            direct_parent_id: direct_parent_id,
-            secondary_parent_ids: secondary_parent_ids,
-            repo: repo
+            direct_parent_id,
+            secondary_parent_ids,
+            tree_id: Some(tree.get_id().to_string()),
+            author: Some(author),
+            message: message.to_string(),
+            repo,
         })
     }
 
-    pub fn get(repo: &'a Repository, id: &str) -> Option<Commit<'a>> {
+    pub fn get(repo: &'a Repository, id: &str) -> Result<Commit<'a>, GoldfishError> {
         //! Find the commit file with the given commit id
         //! and return the Commit object loaded from that commit file
-        let full_path = filesystem::join_path(vec![repo.get_commits_path().as_str(), id]);
-        let content = filesystem::read_file(full_path.as_str()).ok()?;
-        let mut lines = content.split('\n');
-        if lines.nth(0)?.trim() != "commit" {
-            return None
+        let malformed = || GoldfishError::CorruptObject(format!("Malformed commit {}", id));
+        let content = String::from_utf8(repo.read_object(COMMITS_DIR, id)?).map_err(|_| malformed())?;
+        let (header, message) = split_commit_content(content.as_str());
+        let mut lines = header.split('\n');
+        if lines.next().map(|line| line.trim()) != Some("commit") {
+            return Err(malformed())
         }
         let mut parent = String::from("");
         let mut secondary_parents = vec![];
+        let mut tree_id = None;
+        let mut author = None;
         for line in lines {
-            if line.starts_with("parent") {
-                let current_parent = line.split(" ").nth(1)?.to_string();
-                if parent == "" {
+            if let Some(raw_tree_id) = line.strip_prefix("tree ") {
+                tree_id = Some(raw_tree_id.trim().to_string());
+            } else if line.starts_with("parent") {
+                let current_parent = line.split(' ').nth(1).ok_or_else(malformed)?.to_string();
+                if parent.is_empty() {
                     parent = current_parent;
                 } else {
                     secondary_parents.push(current_parent);
                 }
-            } else {
-                break
+            } else if let Some(raw_author) = line.strip_prefix("author ") {
+                author = Signature::parse(raw_author);
             }
         }
-        Some(Commit { id: id.to_string(), direct_parent_id: parent, secondary_parent_ids: secondary_parents, repo: repo })
+        Ok(Commit {
+            id: id.to_string(),
+            direct_parent_id: parent,
+            secondary_parent_ids: secondary_parents,
+            tree_id,
+            author,
+            message: message.trim().to_string(),
+            repo,
+        })
     }
 
-    pub fn checkout(&self) -> Result<String, String> {
+    pub fn checkout(&self, force: bool) -> Result<(), GoldfishError> {
+        //! Update the working directory from the files of the current tracked file list to the files
+        //! of this commit: delete the files this commit does not have, and create or update the others.
+        //! Refuses if a file it changes has local modifications (staged, or in the working directory),
+        //! or is an untracked file in the way, unless `force` is given, which also discards the local
+        //! modifications of every other tracked file
         let repo = self.get_repo();
-        // load all the files of that commit
-        match self.load_tracked_files() {
-            Some(tracked_file_list) => {
-                // populate the staging area with the files of the commit
-                for (file_path, blob_id) in &tracked_file_list {
-                    match Blob::get(&repo, blob_id.as_str()) {
-                        Some(blob) => {
-                            match filesystem::write_file(
-                                blob.get_blob_content().unwrap().as_str(),
-                                filesystem::join_path(vec![
-                                    repo.get_staging_path().as_str(),
-                                    file_path.as_str(),
-                                ])
-                                .as_str(),
-                            ) {
-                                Ok(_) => {}
-                                Err(_) => return Err(
-                                    String::from("Something went wrong when writing files. Please try again")
-                                ),
-                            }
-                        }
-                        None => return Err(
-                            String::from("Something went wrong creating the committed files"),
-                        ),
+        let new_files = self.load_tracked_files()?;
+        let old_files = repo.get_staging_tracked_files()?;
+        let head_files = match repo.get_current_commit_id() {
+            Ok(head_id) if !head_id.is_empty() => Commit::get(repo, head_id.as_str())?.load_tracked_files()?,
+            _ => HashMap::new(),
+        };
+        let get_working_file_hash = |file_path: &str| {
+            filesystem::read_bytes(filesystem::join_path(vec![repo.get_working_path(), file_path]).as_str())
+                .ok()
+                .map(|content| utilities::hash(&content))
+        };
+        // whether a directory in the way of a file holds files that are not tracked
+        let has_untracked_files = |file_path: &str| {
+            let dir_path = filesystem::join_path(vec![repo.get_working_path(), file_path]);
+            filesystem::is_dir(dir_path.as_str())
+                && filesystem::list_files(dir_path.as_str(), true, &vec![]).map_or(true, |paths| {
+                    paths.iter().any(|path| {
+                        filesystem::diff_path(repo.get_working_path(), path.as_str())
+                            .is_none_or(|relative_path| !old_files.contains_key(&relative_path.replace('\\', "/")))
+                    })
+                })
+        };
+
+        let mut changed_paths: Vec<&String> = old_files.keys().chain(new_files.keys())
+            .filter(|file_path| old_files.get(*file_path) != new_files.get(*file_path))
+            .collect::<HashSet<&String>>()
+            .into_iter()
+            .collect();
+        changed_paths.sort();
+        if !force {
+            let overwritten_paths: Vec<&str> = changed_paths.iter()
+                .filter(|file_path| {
+                    let working_hash = get_working_file_hash(file_path);
+                    match old_files.get(**file_path) {
+                        // a staged change, or a change not added yet
+                        Some(old_blob_id) => head_files.get(**file_path) != Some(old_blob_id)
+                            || working_hash.is_some_and(|hash| &hash != old_blob_id),
+                        // an untracked file that is not already the file of the commit, or a
+                        // directory of untracked files where the commit has a file
+                        None => (working_hash.is_some() && working_hash.as_ref() != new_files.get(**file_path))
+                            || (new_files.contains_key(**file_path) && has_untracked_files(file_path)),
                     }
-                }
-                // populate staging tracked files
-                repo.save_staging_tracked_files(tracked_file_list);
-                // copy the staging area to the working path
-                for file_path in
-                    filesystem::list_files(repo.get_staging_path().as_str(), true, &vec![]).unwrap()
-                {
-                    let dest = filesystem::join_path(vec![
-                        repo.get_working_path(),
-                        filesystem::diff_path(repo.get_staging_path().as_str(), file_path.as_str())
-                            .unwrap()
-                            .as_str(),
-                    ]);
-                    match filesystem::write_file(
-                        filesystem::read_file(file_path.as_str()).unwrap().as_str(),
-                        dest.as_str(),
-                    ) {
-                        Ok(_) => {}
-                        Err(_) => return Err(String::from("Something failed while writing to working area"))
-                    }
-                }
-                // clean staging
-                filesystem::remove(repo.get_staging_path().as_str()).unwrap();
+                })
+                .map(|file_path| file_path.as_str())
+                .collect();
+            if !overwritten_paths.is_empty() {
+                return Err(GoldfishError::Conflict(format!(
+                    "Your local changes to these files would be overwritten by checkout:\n\t{}\nCommit them, or use `checkout --force` to discard them",
+                    overwritten_paths.join("\n\t")
+                )));
             }
-            None => return Err(String::from("Corrupt commit file")),
         }
-        Ok(String::from(""))
+
+        // with force, files unchanged by the checkout are restored too
+        let restored_paths: Vec<&String> = if force {
+            new_files.iter()
+                .filter(|(file_path, blob_id)| {
+                    old_files.get(*file_path) == Some(*blob_id) && get_working_file_hash(file_path).as_ref() != Some(*blob_id)
+                })
+                .map(|(file_path, _)| file_path)
+                .collect()
+        } else {
+            vec![]
+        };
+        // delete first, so that a file replacing a directory (or the other way around) has its place
+        let (written_paths, deleted_paths): (Vec<&String>, Vec<&String>) = changed_paths.into_iter()
+            .chain(restored_paths)
+            .partition(|file_path| new_files.contains_key(*file_path));
+        for file_path in deleted_paths {
+            let working_file_path = filesystem::join_path(vec![repo.get_working_path(), file_path.as_str()]);
+            if filesystem::is_file(working_file_path.as_str()) {
+                filesystem::remove(working_file_path.as_str())
+                    .map_err(|e| GoldfishError::io(format!("Something failed while removing {}", file_path), e))?;
+            }
+            filesystem::remove_empty_parents(working_file_path.as_str(), repo.get_working_path());
+        }
+        for file_path in written_paths {
+            let working_file_path = filesystem::join_path(vec![repo.get_working_path(), file_path.as_str()]);
+            let blob_content = Blob::get(repo, new_files[file_path].as_str())?.get_blob_content()?;
+            // what is left of a directory in the way is untracked, and was checked above unless forced
+            if filesystem::is_dir(working_file_path.as_str()) {
+                filesystem::remove(working_file_path.as_str())
+                    .map_err(|e| GoldfishError::io(format!("Something failed while removing the directory {}", file_path), e))?;
+            }
+            filesystem::write_file(blob_content, working_file_path.as_str())
+                .map_err(|e| GoldfishError::io(format!("Something failed while writing {}", file_path), e))?;
+        }
+
+        // the tracked file list is now the commit's, with nothing left in staging
+        repo.save_staging_tracked_files(new_files)?;
+        let staging_path = repo.get_staging_path();
+        if filesystem::is_dir(staging_path.as_str()) {
+            filesystem::remove(staging_path.as_str())
+                .map_err(|e| GoldfishError::io("Something went wrong cleaning the staging area", e))?;
+        }
+        Ok(())
     }
 
-    pub fn load_tracked_files(&self) -> Option<HashMap<String, String>> {
+    pub fn load_tracked_files(&self) -> Result<HashMap<String, String>, GoldfishError> {
+        //! Load the {file_path: blob_id} list of the commit, refusing paths that would lead
+        //! outside the working directory or into .goldfish
         let mut result = HashMap::new();
-        let commit_file_path = filesystem::join_path(vec![self.get_repo().get_commits_path().as_str(), self.get_id().as_str()]);
-        let content = filesystem::read_file(commit_file_path.as_str()).ok()?;
-        let lines = content.split('\n');
+        if let Some(tree_id) = &self.tree_id {
+            Tree::get(self.get_repo(), tree_id.as_str())?.load_tracked_files(self.get_repo(), "", &mut result)?;
+            if result.is_empty() {
+                return Err(GoldfishError::CorruptObject(format!("Commit {} has no files", self.id)))
+            }
+            return Ok(result)
+        }
+
+        // commits made before trees were introduced list every file directly
+        let corrupt_commit = || GoldfishError::CorruptObject(format!("Corrupt commit file {}", self.id));
+        let content = self.get_repo().read_object(COMMITS_DIR, self.id.as_str())?;
+        let content = String::from_utf8(content).map_err(|_| corrupt_commit())?;
+        let (header, _message) = split_commit_content(content.as_str());
+        let lines = header.split('\n');
         for line in lines {
             if line.starts_with("tracked_file") {
-                let file_path = line.split(' ').nth(1)?;
-                let hash = line.split(' ').nth(2)?;
+                let file_path = line.split(' ').nth(1).ok_or_else(corrupt_commit)?;
+                let hash = line.split(' ').nth(2).ok_or_else(corrupt_commit)?;
+                check_tracked_path(file_path).map_err(|e| GoldfishError::CorruptObject(format!("{} in commit {}", e, self.id)))?;
                 result.insert(file_path.to_string(), hash.to_string());
             }
         }
 
         // There should be at least one file present for a commit, otherwise this is a defective commit file
         if result.is_empty() {
-            return None
+            return Err(corrupt_commit())
         }
 
-        Some(result)
+        Ok(result)
     }
 
     pub fn get_lowest_common_parent_with<'b>(&'b self, other: &'b Commit) -> Option<Commit<'b>> {
@@ -337,47 +980,36 @@ impl<'a> Commit<'a> {
 
         // populate the parents set with all the ancestors of the self Commit
         fn populate_ancestors(ancestors: &mut HashSet<String>, commit: &Commit) {
-            let parent_results = commit.get_parents();
-            for parent_result in parent_results {
-                match parent_result {
-                    Some(parent) => {
-                        ancestors.insert(parent.get_id());
-                        populate_ancestors(ancestors, &parent);
-                    }
-                    None => {}
+            for parent in commit.get_parents().into_iter().flatten() {
+                // a commit reached through another merge path has its ancestors listed already
+                if ancestors.insert(parent.get_id()) {
+                    populate_ancestors(ancestors, &parent);
                 }
             }
         }
         populate_ancestors(&mut self_ancestors, self);
 
         if self_ancestors.contains(&other.get_id()) {
-            return Commit::get(other.get_repo(), other.get_id().as_str())
-        }
-
-        // walk up the ancestor tree of the other Commit,
-        // returning when an ancestor that exists in self's ancestor set is found
-
-        fn find_match(ancestors: &mut HashSet<String>, commit: &Commit) -> Option<String> {
-            let parent_results = commit.get_parents();
-            for parent_result in parent_results {
-                match parent_result {
-                    Some(parent) => {
-                        if ancestors.contains(&parent.get_id()) {
-                            return Some(parent.get_id())
-                        } else {
-                            match find_match(ancestors, &parent) {
-                                Some(id) => return Some(id),
-                                None => {}
-                            }
-                        }
-                    }
-                    None => {}
+            return Commit::get(other.get_repo(), other.get_id().as_str()).ok()
+        }
+
+        // walk up the ancestor tree of the other Commit generation by generation,
+        // returning the closest ancestor that exists in self's ancestor set
+        let mut visited: HashSet<String> = HashSet::new();
+        let mut queue: VecDeque<String> = VecDeque::new();
+        queue.push_back(other.get_id());
+        while let Some(commit_id) = queue.pop_front() {
+            if self_ancestors.contains(&commit_id) {
+                return Commit::get(self.repo, commit_id.as_str()).ok();
+            }
+            if visited.insert(commit_id.to_owned()) {
+                let commit = Commit::get(self.repo, commit_id.as_str()).ok()?;
+                for parent in commit.get_parents().into_iter().flatten() {
+                    queue.push_back(parent.get_id());
                 }
             }
-            None
         }
-
-        Commit::get(self.repo, find_match(&mut self_ancestors, &other)?.as_str())
+        None
     }
 
 
@@ -385,7 +1017,21 @@ impl<'a> Commit<'a> {
 
 
     pub fn pretty_print(&self) -> String {
-        format!("Commit: {}", &self.id)
+        let mut result = format!("Commit: {}", &self.id);
+        if !self.secondary_parent_ids.is_empty() {
+            result.push_str(format!("\nMerge:  {} {}", self.direct_parent_id, self.secondary_parent_ids.join(" ")).as_str());
+        }
+        if let Some(author) = self.get_author() {
+            result.push_str(format!("\nAuthor: {} <{}>", author.get_name(), author.get_email()).as_str());
+            result.push_str(format!("\nDate:   {}", utilities::format_timestamp(author.get_timestamp())).as_str());
+        }
+        if !self.get_message().is_empty() {
+            result.push('\n');
+            for line in self.get_message().lines() {
+                result.push_str(format!("\n    {}", line).as_str());
+            }
+        }
+        result
     }
 
     pub fn get_repo(&self) -> &Repository {
@@ -396,50 +1042,471 @@ impl<'a> Commit<'a> {
         (&self).id.to_owned()
     }
 
+    pub fn get_author(&self) -> Option<&Signature> {
+        //! The author of the commit, or None for commits made before authors were recorded
+        self.author.as_ref()
+    }
+
+    pub fn get_message(&self) -> &str {
+        &self.message
+    }
+
     pub fn get_direct_parent_id(&self) -> &str {
         &self.direct_parent_id
     }
 
-    pub fn get_direct_parent(&self) -> Option<Commit> {
-        Commit::get(&self.repo, &self.direct_parent_id)
+    pub fn get_direct_parent(&self) -> Option<Commit<'_>> {
+        Commit::get(self.repo, &self.direct_parent_id).ok()
     }
 
-    pub fn get_secondary_parents(&self) -> Vec<Option<Commit>> {
+    pub fn get_secondary_parents(&self) -> Vec<Option<Commit<'_>>> {
         let mut result = vec![];
         for parent_id in &self.secondary_parent_ids {
-            result.push(Commit::get(&self.repo, parent_id))
+            result.push(Commit::get(self.repo, parent_id).ok())
         }
         result
     }
 
-    pub fn get_parents(&self) -> Vec<Option<Commit>> {
+    pub fn get_parents(&self) -> Vec<Option<Commit<'_>>> {
         let mut parents = self.get_secondary_parents();
         parents.insert(0, self.get_direct_parent());
         parents
     }
+
+    pub fn get_parent_ids(&self) -> Vec<&str> {
+        //! Ids of all parents, whether or not they exist in this repository
+        let mut parent_ids = vec![self.direct_parent_id.as_str()];
+        parent_ids.extend(self.secondary_parent_ids.iter().map(|id| id.as_str()));
+        parent_ids.retain(|id| !id.is_empty());
+        parent_ids
+    }
+
+    pub fn get_tree_id(&self) -> Option<&str> {
+        //! The root tree, or None for commits made before trees were introduced
+        self.tree_id.as_deref()
+    }
+
+    pub fn collect_objects(&self, seen: &mut HashSet<(String, String)>, result: &mut Vec<(String, String)>) -> Result<(), GoldfishError> {
+        //! Add the (object_dir, id) of every tree and blob of this commit that is not in `seen`
+        //! to the result, without the commit itself. Trees already seen are not walked again
+        fn collect_tree(
+            repo: &Repository,
+            tree_id: &str,
+            seen: &mut HashSet<(String, String)>,
+            result: &mut Vec<(String, String)>,
+        ) -> Result<(), GoldfishError> {
+            if !seen.insert((TREES_DIR.to_string(), tree_id.to_string())) {
+                return Ok(())
+            }
+            result.push((TREES_DIR.to_string(), tree_id.to_string()));
+            for entry in Tree::get(repo, tree_id)?.get_entries() {
+                match entry.get_kind() {
+                    TreeEntryKind::Tree => collect_tree(repo, entry.get_id(), seen, result)?,
+                    TreeEntryKind::Blob => {
+                        if seen.insert((BLOBS_DIR.to_string(), entry.get_id().to_string())) {
+                            result.push((BLOBS_DIR.to_string(), entry.get_id().to_string()));
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        match self.get_tree_id() {
+            Some(tree_id) => collect_tree(self.repo, tree_id, seen, result),
+            None => {
+                for blob_id in self.load_tracked_files()?.into_values() {
+                    if seen.insert((BLOBS_DIR.to_string(), blob_id.to_owned())) {
+                        result.push((BLOBS_DIR.to_string(), blob_id));
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// Walking the commit graph
+impl Repository {
+    pub fn collect_ancestors(&self, commit_ids: &[String]) -> HashSet<String> {
+        //! Return the given commits and all their ancestors, leaving out commits
+        //! that do not exist in this repository
+        let mut ancestors: HashSet<String> = HashSet::new();
+        let mut queue: VecDeque<String> = commit_ids.iter().cloned().collect();
+        while let Some(commit_id) = queue.pop_front() {
+            if ancestors.contains(&commit_id) {
+                continue;
+            }
+            if let Ok(commit) = Commit::get(self, commit_id.as_str()) {
+                queue.extend(commit.get_parent_ids().into_iter().map(|id| id.to_string()));
+                ancestors.insert(commit_id);
+            }
+        }
+        ancestors
+    }
+
+    pub fn is_ancestor(&self, ancestor_id: &str, commit_id: &str) -> bool {
+        //! Whether the first commit is the second commit or one of its ancestors,
+        //! i.e. whether moving a branch from the first to the second is a fast-forward
+        self.collect_ancestors(&[commit_id.to_string()]).contains(ancestor_id)
+    }
+
+    pub fn collect_missing_objects(&self, wanted: &[String], common: &HashSet<String>) -> Result<Vec<(String, String)>, GoldfishError> {
+        //! Return the (object_dir, id) of every object another repository needs to have the
+        //! wanted commits, given `common` commits it already has with all their ancestors:
+        //! the commits between them, and their trees and blobs that the common commits do not have.
+        //! Common commits that do not exist here are ignored
+        fn get_timestamp(commit: &Commit) -> i64 {
+            commit.get_author().map_or(0, |author| author.get_timestamp())
+        }
+
+        // walk from the wanted and the common commits together, newest first (and common first
+        // among commits of the same second), passing on to the parents whether a commit is common,
+        // until only common commits are left to walk
+        let mut walked: HashMap<String, (Commit, bool)> = HashMap::new();
+        let mut order: Vec<String> = vec![];
+        let mut queue: BinaryHeap<(i64, bool, String)> = BinaryHeap::new();
+        let mut wanted_left = 0;
+        for commit_id in common {
+            if let Ok(commit) = Commit::get(self, commit_id.as_str()) {
+                queue.push((get_timestamp(&commit), true, commit_id.to_owned()));
+                walked.insert(commit_id.to_owned(), (commit, true));
+            }
+        }
+        for commit_id in wanted {
+            if !walked.contains_key(commit_id) {
+                let commit = Commit::get(self, commit_id.as_str())?;
+                queue.push((get_timestamp(&commit), false, commit_id.to_owned()));
+                walked.insert(commit_id.to_owned(), (commit, false));
+                order.push(commit_id.to_owned());
+                wanted_left += 1;
+            }
+        }
+        while wanted_left > 0 {
+            let Some((_, is_common, commit_id)) = queue.pop() else { break };
+            if !is_common {
+                wanted_left -= 1;
+            }
+            // a commit found to be common after it was queued is walked again as such
+            if walked[&commit_id].1 != is_common {
+                continue;
+            }
+            let parent_ids: Vec<String> = walked[&commit_id].0.get_parent_ids().into_iter().map(|id| id.to_string()).collect();
+            for parent_id in parent_ids {
+                let timestamp = match walked.get_mut(&parent_id) {
+                    Some((_, parent_is_common)) if *parent_is_common || !is_common => continue,
+                    Some((parent, parent_is_common)) => {
+                        *parent_is_common = true;
+                        get_timestamp(parent)
+                    }
+                    None => {
+                        let parent = match Commit::get(self, parent_id.as_str()) {
+                            Ok(parent) => parent,
+                            // the other repository has the ancestors of common commits anyway
+                            Err(_) if is_common => continue,
+                            Err(e) => return Err(e),
+                        };
+                        let timestamp = get_timestamp(&parent);
+                        walked.insert(parent_id.to_owned(), (parent, is_common));
+                        if !is_common {
+                            order.push(parent_id.to_owned());
+                        }
+                        timestamp
+                    }
+                };
+                queue.push((timestamp, is_common, parent_id));
+                if !is_common {
+                    wanted_left += 1;
+                }
+            }
+        }
+        let mut commits: Vec<&Commit> = vec![];
+        let mut boundary: HashSet<&str> = HashSet::new();
+        for commit_id in &order {
+            let (commit, is_common) = &walked[commit_id];
+            if *is_common {
+                continue;
+            }
+            for parent_id in commit.get_parent_ids() {
+                if walked.get(parent_id).is_some_and(|(_, parent_is_common)| *parent_is_common) {
+                    boundary.insert(parent_id);
+                }
+            }
+            commits.push(commit);
+        }
+
+        // the trees and blobs of the common commits next to the missing ones are not needed
+        let mut seen: HashSet<(String, String)> = HashSet::new();
+        for commit_id in boundary {
+            let _ = walked[commit_id].0.collect_objects(&mut seen, &mut vec![]);
+        }
+        let mut result: Vec<(String, String)> = vec![];
+        for commit in commits {
+            result.push((COMMITS_DIR.to_string(), commit.get_id()));
+            commit.collect_objects(&mut seen, &mut result)?;
+        }
+        Ok(result)
+    }
 }
 
+fn split_commit_content(content: &str) -> (&str, &str) {
+    //! Split the content of a commit file into its header lines and its message
+    content.split_once("\n\n").unwrap_or((content, ""))
+}
+
+/**
+ * The change made to one file between two commits: the tag is `+` for an added file,
+ * `-` for a removed file and `=` for a file in both commits, and the line list holds
+ * the line by line differences from `utilities::diff`. Binary files have no line list.
+ */
 #[derive(Debug)]
 pub struct ChangeBin {
     tag: String,
     line_list: Vec<(String,String)>,
+    old_blob_id: Option<String>,
+    new_blob_id: Option<String>,
+    binary: bool,
 }
 
 impl ChangeBin {
-    pub fn create(t: String, l: Vec<(String,String)>) -> ChangeBin {
+    pub fn create(t: String, l: Vec<(String,String)>, old_blob_id: Option<String>, new_blob_id: Option<String>, binary: bool) -> ChangeBin {
         ChangeBin{
             tag: t,
             line_list: l,
+            old_blob_id,
+            new_blob_id,
+            binary,
         }
     }
 
     pub fn get_tag(&self) -> &str{
         self.tag.as_str()
     }
+
+    pub fn get_line_list(&self) -> &Vec<(String,String)> {
+        &self.line_list
+    }
+
+    pub fn get_old_blob_id(&self) -> Option<&str> {
+        self.old_blob_id.as_deref()
+    }
+
+    pub fn get_new_blob_id(&self) -> Option<&str> {
+        self.new_blob_id.as_deref()
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    pub fn has_changes(&self) -> bool {
+        self.old_blob_id != self.new_blob_id
+    }
+}
+
+pub fn get_object_kind(object_dir: &str) -> &str {
+    //! `commit`, `tree` or `blob`
+    object_dir.strip_suffix('s').unwrap_or(object_dir)
+}
+
+pub fn check_tracked_path(path: &str) -> Result<(), GoldfishError> {
+    //! Check that a file path read from a commit or tree stays inside the working directory and out of
+    //! .goldfish: a relative path without empty, `.`, `..` or `.goldfish` components
+    let components: Vec<&str> = path.split(['/', '\\']).collect();
+    let has_drive = components[0].len() >= 2 && components[0].as_bytes()[1] == b':';
+    let has_unsafe_component = components.iter().any(|component| {
+        component.is_empty() || *component == "." || *component == ".." || component.eq_ignore_ascii_case(GOLDFISH_ROOT_DIR)
+    });
+    if has_drive || has_unsafe_component || path.contains('\0') {
+        return Err(GoldfishError::CorruptObject(format!("Unsafe file path {:?}", path)));
+    }
+    Ok(())
+}
+
+pub fn check_object_id(object_dir: &str, id: &str, content: &[u8]) -> Result<(), GoldfishError> {
+    //! Check that the content of an object starts with the header of its type, and hashes to its id.
+    //! The id of a blob is the hash of the content after its `blob` line
+    let (header, hashed_content): (&[u8], &[u8]) = match object_dir {
+        BLOBS_DIR => (b"blob\n", content.strip_prefix(b"blob\n").unwrap_or(content)),
+        TREES_DIR => (b"tree\n", content),
+        _ => (b"commit\n", content),
+    };
+    if !content.starts_with(header) {
+        return Err(GoldfishError::CorruptObject(format!("Object {} is not a valid {}", id, get_object_kind(object_dir))));
+    }
+    let actual_id = utilities::hash(hashed_content);
+    if !actual_id.eq_ignore_ascii_case(id) {
+        return Err(GoldfishError::CorruptObject(format!("Object {} is corrupt, its content hashes to {}", id, actual_id)));
+    }
+    Ok(())
+}
+
+pub fn check_object_paths(object_dir: &str, content: &[u8]) -> Result<(), GoldfishError> {
+    //! Check every file path listed by a tree, or by a commit made before trees were introduced
+    let content = String::from_utf8_lossy(content);
+    if object_dir == TREES_DIR {
+        for line in content.split_terminator('\n').skip(1) {
+            if let Some(name) = line.splitn(3, ' ').nth(2) {
+                check_tracked_path(name)?;
+            }
+        }
+    } else if object_dir == COMMITS_DIR {
+        let (header, _message) = split_commit_content(content.as_ref());
+        for line in header.split('\n').filter(|line| line.starts_with("tracked_file")) {
+            if let Some(file_path) = line.split(' ').nth(1) {
+                check_tracked_path(file_path)?;
+            }
+        }
+    }
+    Ok(())
 }
 
 /**
- * A blob is a file whose name is the hash of its MAIN CONTENT
+ * A tree lists the content of one directory of a commit: the blob of each file and
+ * the tree of each subdirectory, sorted by name. Its filename is the hash digest of its
+ * file content, so a directory that did not change between commits is stored only once.
+ * Format:
+ * ```
+ * tree\n
+ * blob {blob_id} {file_name}\n
+ * tree {tree_id} {directory_name}\n
+ * {{ more entry lines if necessary }}
+ * ```
+ */
+#[derive(Debug)]
+pub struct Tree {
+    id: String,
+    entries: Vec<TreeEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TreeEntryKind {
+    Blob,
+    Tree,
+}
+
+#[derive(Debug, Clone)]
+pub struct TreeEntry {
+    kind: TreeEntryKind,
+    id: String,
+    name: String,
+}
+
+impl TreeEntry {
+    pub fn get_kind(&self) -> &TreeEntryKind {
+        &self.kind
+    }
+
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Tree {
+    pub fn create(repo: &Repository, mut entries: Vec<TreeEntry>) -> io::Result<Tree> {
+        //! Write a tree file listing the given entries, unless an identical tree already exists
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut content = String::from("tree\n");
+        for entry in entries.iter() {
+            let kind = match entry.kind {
+                TreeEntryKind::Blob => "blob",
+                TreeEntryKind::Tree => "tree",
+            };
+            content.push_str(format!("{} {} {}\n", kind, entry.id, entry.name).as_str());
+        }
+        let tree_id = utilities::hash(content.as_str());
+        if !repo.has_object(TREES_DIR, tree_id.as_str()) {
+            repo.write_object(TREES_DIR, tree_id.as_str(), content.as_bytes())?;
+        }
+        Ok(Tree { id: tree_id, entries })
+    }
+
+    pub fn create_from_tracked_files(repo: &Repository, tracked_files: &HashMap<String, String>) -> io::Result<Tree> {
+        //! Write one tree per directory of the given {file_path: blob_id} list
+        //! and return the root tree
+        let mut files = vec![];
+        let mut directories: HashMap<String, HashMap<String, String>> = HashMap::new();
+        for (file_path, blob_id) in tracked_files {
+            match file_path.split_once('/') {
+                Some((directory, rest)) => {
+                    directories
+                        .entry(directory.to_string())
+                        .or_default()
+                        .insert(rest.to_string(), blob_id.to_string());
+                }
+                None => files.push(TreeEntry {
+                    kind: TreeEntryKind::Blob,
+                    id: blob_id.to_string(),
+                    name: file_path.to_string(),
+                }),
+            }
+        }
+        let mut entries = files;
+        for (directory, directory_files) in directories {
+            let subtree = Tree::create_from_tracked_files(repo, &directory_files)?;
+            entries.push(TreeEntry { kind: TreeEntryKind::Tree, id: subtree.id, name: directory });
+        }
+        Tree::create(repo, entries)
+    }
+
+    pub fn get(repo: &Repository, id: &str) -> Result<Tree, GoldfishError> {
+        //! Find the tree file with the given id and load its entries
+        let malformed = || GoldfishError::CorruptObject(format!("Malformed tree {}", id));
+        let content = String::from_utf8(repo.read_object(TREES_DIR, id)?).map_err(|_| malformed())?;
+        let mut lines = content.split_terminator('\n');
+        if lines.next().map(|line| line.trim()) != Some("tree") {
+            return Err(malformed())
+        }
+        let mut entries = vec![];
+        for line in lines {
+            let mut items = line.splitn(3, ' ');
+            let kind = match items.next() {
+                Some("blob") => TreeEntryKind::Blob,
+                Some("tree") => TreeEntryKind::Tree,
+                _ => return Err(malformed()),
+            };
+            let entry_id = items.next().ok_or_else(malformed)?.to_string();
+            let name = items.next().ok_or_else(malformed)?.to_string();
+            entries.push(TreeEntry { kind, id: entry_id, name });
+        }
+        Ok(Tree { id: id.to_string(), entries })
+    }
+
+    pub fn load_tracked_files(&self, repo: &Repository, prefix: &str, result: &mut HashMap<String, String>) -> Result<(), GoldfishError> {
+        //! Walk this tree and its subtrees, adding a {file_path: blob_id} entry
+        //! for every file to the result. Paths are prefixed with the given directory path.
+        //! Fails on a missing subtree, or a path that would lead outside the working directory
+        for entry in self.entries.iter() {
+            let path = if prefix.is_empty() { entry.name.to_owned() } else { format!("{}/{}", prefix, entry.name) };
+            check_tracked_path(path.as_str()).map_err(|e| GoldfishError::CorruptObject(format!("{} in tree {}", e, self.id)))?;
+            match entry.kind {
+                TreeEntryKind::Blob => {
+                    result.insert(path, entry.id.to_owned());
+                }
+                TreeEntryKind::Tree => {
+                    Tree::get(repo, entry.id.as_str())?.load_tracked_files(repo, path.as_str(), result)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get_entries(&self) -> &Vec<TreeEntry> {
+        &self.entries
+    }
+}
+
+/**
+ * A blob is a file whose name is the hash of its MAIN CONTENT.
+ * The main content is stored byte for byte, so blobs can hold binary files.
  * Format:
  * ```
  * blob\n
@@ -449,39 +1516,304 @@ impl ChangeBin {
 #[derive(Debug)]
 pub struct Blob {
     id: String,
-    path: String,
+    content: Vec<u8>,
 }
 
 impl Blob {
-    pub fn create(repo: &Repository, blob_data: &str) -> io::Result<Blob> {
+    pub fn create(repo: &Repository, blob_data: &[u8]) -> io::Result<Blob> {
         //! Write a new blob file with the given content
-        let content = format!("blob\n{}", blob_data);
+        let mut content = b"blob\n".to_vec();
+        content.extend_from_slice(blob_data);
         let blob_id = utilities::hash(blob_data);
-        let blob_path = filesystem::join_path(vec![repo.get_blobs_path().as_str(), blob_id.as_str()]);
-        filesystem::write_file(content.as_str(), blob_path.as_str())?;
-        Ok(Blob { id: blob_id, path: blob_path })
+        repo.write_object(BLOBS_DIR, blob_id.as_str(), &content)?;
+        Ok(Blob { id: blob_id, content: blob_data.to_vec() })
     }
 
-    pub fn get(repo: &Repository, id: &str) -> Option<Blob> {
-        //! Find a blob at the given path
-        let full_path = filesystem::join_path(vec![repo.get_blobs_path().as_str(), id]);
-        let content = filesystem::read_file(full_path.as_str()).ok()?;
-        let mut lines = content.split("\n");
-        if lines.nth(0)?.trim() != "blob" {
-            return None
-        }
+    pub fn get(repo: &Repository, id: &str) -> Result<Blob, GoldfishError> {
+        //! Find the blob with the given id and load its main content
+        let content = repo.read_object(BLOBS_DIR, id)?;
+        let main_content = content
+            .strip_prefix(b"blob\n")
+            .ok_or_else(|| GoldfishError::CorruptObject(format!("Malformed blob {}", id)))?;
 
-        Some(Blob { id: id.to_string(), path: full_path })
+        Ok(Blob { id: id.to_string(), content: main_content.to_vec() })
     }
 
-    pub fn get_blob_content(&self) -> io::Result<String> {
-        //! Read the main content of the blob
-        let content = filesystem::read_file(&self.path.as_str())?;
-        let lines = content.split("\n");
-        Ok(lines.skip(1).map(|x| x.to_string()).collect::<Vec<String>>().join("\n"))
+    pub fn get_blob_content(&self) -> io::Result<Vec<u8>> {
+        //! Read the main content of the blob, exactly as it was given to `Blob::create`
+        Ok(self.content.to_owned())
     }
 
     pub fn get_id(&self) -> &str {
         &self.id
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use crate::controller;
+    use crate::error::GoldfishError;
+    use crate::filesystem;
+    use crate::model::{check_object_paths, check_tracked_path, parse_symbolic_head, resolve_reference, Blob, Commit, Repository, Signature, Tree, TreeEntryKind};
+    use crate::model::{BLOBS_DIR, COMMITS_DIR, DEFAULT_BRANCH, HEAD, TREES_DIR};
+    use crate::utilities;
+    use std::collections::{HashMap, HashSet};
+
+    pub fn create_test_repository(name: &str) -> Repository {
+        //! Create an empty repository in a fresh folder of the temporary directory
+        let working_path = std::env::temp_dir().join(format!("goldfish-test-{}-{}", std::process::id(), name));
+        let working_path = filesystem::pathbuf_to_string(working_path);
+        filesystem::remove(working_path.as_str()).ok();
+        filesystem::create_dir(working_path.as_str()).unwrap();
+        controller::create_repository(working_path.as_str()).unwrap()
+    }
+
+    pub fn commit_test_files(repo: &Repository, files: &[(&str, &[u8])]) -> String {
+        //! Write the files into the working directory and commit exactly these files on top of HEAD,
+        //! as `add` and `commit` would. Returns the id of the new commit
+        let mut tracked_files = HashMap::new();
+        for (file_path, content) in files {
+            filesystem::write_file(content, filesystem::join_path(vec![repo.get_working_path(), file_path]).as_str()).unwrap();
+            let blob = Blob::create(repo, content).unwrap();
+            tracked_files.insert(file_path.to_string(), blob.get_id().to_string());
+        }
+        repo.save_staging_tracked_files(tracked_files.clone()).unwrap();
+        let parent_id = repo.get_current_commit_id().unwrap();
+        let author = Signature::now("test", "test@localhost");
+        Commit::create(repo, parent_id, vec![], tracked_files, author, "test").unwrap().get_id()
+    }
+
+    #[test]
+    fn test_1_check_tracked_path() {
+        //! Check that paths leading outside the working directory or into .goldfish are refused
+        for path in ["src/main.rs", "a", ".goldfishignore", "docs/.gitignore", "x..y"] {
+            assert!(check_tracked_path(path).is_ok(), "{}", path);
+        }
+        for path in ["../x", "a/../../x", "/etc/passwd", "C:\\x", "a\\..\\x", ".goldfish/HEAD", "a/.GOLDFISH/x", "a//b", "./a", ""] {
+            assert!(check_tracked_path(path).is_err(), "{}", path);
+        }
+        assert!(check_object_paths(TREES_DIR, b"tree\nblob AB ok\n").is_ok());
+        assert!(check_object_paths(TREES_DIR, b"tree\nblob AB ok\nblob CD ../../.bashrc\n").is_err());
+    }
+
+    #[test]
+    fn test_2_collect_missing_objects() {
+        //! Check that the walk stops at the ancestors of common commits, even when the wanted
+        //! commit branched off below a common commit
+        let repo = create_test_repository("collect_missing_objects");
+        commit_test_files(&repo, &[("a", b"1")]);
+        let base_id = commit_test_files(&repo, &[("a", b"2")]);
+        let common_id = commit_test_files(&repo, &[("a", b"3")]);
+        repo.write_branch("main", base_id.as_str()).unwrap();
+        let wanted_id = commit_test_files(&repo, &[("a", b"2"), ("b", b"4")]);
+
+        let common: HashSet<String> = [common_id.to_owned()].into();
+        let missing = repo.collect_missing_objects(&[wanted_id.to_owned()], &common).unwrap();
+        let commit = Commit::get(&repo, wanted_id.as_str()).unwrap();
+        let expected: HashSet<(String, String)> = [
+            (COMMITS_DIR.to_string(), wanted_id.to_owned()),
+            (TREES_DIR.to_string(), commit.get_tree_id().unwrap().to_string()),
+            (BLOBS_DIR.to_string(), Blob::create(&repo, b"4").unwrap().get_id().to_string()),
+        ].into();
+        assert_eq!(expected, missing.into_iter().collect());
+        let common: HashSet<String> = [wanted_id.to_owned()].into();
+        assert!(repo.collect_missing_objects(&[wanted_id.to_owned(), base_id], &common).unwrap().is_empty());
+    }
+    fn read_working_file(repo: &Repository, file_path: &str) -> Option<Vec<u8>> {
+        //! The content of a file of the working directory, or None if there is no such file
+        filesystem::read_bytes(filesystem::join_path(vec![repo.get_working_path(), file_path]).as_str()).ok()
+    }
+
+    fn write_working_file(repo: &Repository, file_path: &str, content: &[u8]) {
+        //! Change a file of the working directory without staging it
+        filesystem::write_file(content, filesystem::join_path(vec![repo.get_working_path(), file_path]).as_str()).unwrap();
+    }
+
+    #[test]
+    fn test_3_checkout() {
+        //! Check that checkout creates, updates and deletes files, with the folders left empty,
+        //! and replaces a directory with a file and back
+        let repo = create_test_repository("checkout");
+        let first_id = commit_test_files(&repo, &[("a", b"1"), ("dir/b", b"2")]);
+        filesystem::remove(filesystem::join_path(vec![repo.get_working_path(), "dir"]).as_str()).unwrap();
+        let third_id = commit_test_files(&repo, &[("a", b"1"), ("dir", b"4")]);
+        filesystem::remove(filesystem::join_path(vec![repo.get_working_path(), "dir"]).as_str()).unwrap();
+        let second_id = commit_test_files(&repo, &[("a", b"3")]);
+
+        let first = Commit::get(&repo, first_id.as_str()).unwrap();
+        first.checkout(false).unwrap();
+        repo.update_head(first_id.as_str()).unwrap();
+        assert_eq!(Some(b"1".to_vec()), read_working_file(&repo, "a"));
+        assert_eq!(Some(b"2".to_vec()), read_working_file(&repo, "dir/b"));
+        assert_eq!(first.load_tracked_files().unwrap(), repo.get_staging_tracked_files().unwrap());
+
+        let second = Commit::get(&repo, second_id.as_str()).unwrap();
+        second.checkout(false).unwrap();
+        assert_eq!(Some(b"3".to_vec()), read_working_file(&repo, "a"));
+        assert_eq!(None, read_working_file(&repo, "dir/b"));
+        assert!(!filesystem::is_dir(filesystem::join_path(vec![repo.get_working_path(), "dir"]).as_str()));
+        assert_eq!(second.load_tracked_files().unwrap(), repo.get_staging_tracked_files().unwrap());
+
+        // the directory dir becomes a file, but not over an untracked file inside it
+        repo.update_head(second_id.as_str()).unwrap();
+        first.checkout(false).unwrap();
+        repo.update_head(first_id.as_str()).unwrap();
+        let third = Commit::get(&repo, third_id.as_str()).unwrap();
+        write_working_file(&repo, "dir/untracked", b"5");
+        assert!(matches!(third.checkout(false), Err(GoldfishError::Conflict(_))));
+        assert_eq!(Some(b"5".to_vec()), read_working_file(&repo, "dir/untracked"));
+        filesystem::remove(filesystem::join_path(vec![repo.get_working_path(), "dir/untracked"]).as_str()).unwrap();
+        third.checkout(false).unwrap();
+        repo.update_head(third_id.as_str()).unwrap();
+        assert_eq!(Some(b"4".to_vec()), read_working_file(&repo, "dir"));
+        first.checkout(false).unwrap();
+        assert_eq!(Some(b"2".to_vec()), read_working_file(&repo, "dir/b"));
+
+        repo.update_head(first_id.as_str()).unwrap();
+        write_working_file(&repo, "dir/untracked", b"5");
+        third.checkout(true).unwrap();
+        assert_eq!(Some(b"4".to_vec()), read_working_file(&repo, "dir"));
+    }
+
+    #[test]
+    fn test_4_checkout_local_changes() {
+        //! Check that modified, staged and untracked files in the way block checkout, unless forced
+        let repo = create_test_repository("checkout_local_changes");
+        let first_id = commit_test_files(&repo, &[("a", b"1")]);
+        let second_id = commit_test_files(&repo, &[("a", b"2"), ("b", b"3")]);
+        Commit::get(&repo, first_id.as_str()).unwrap().checkout(false).unwrap();
+        repo.update_head(first_id.as_str()).unwrap();
+        let second = Commit::get(&repo, second_id.as_str()).unwrap();
+
+        write_working_file(&repo, "a", b"modified");
+        assert!(matches!(second.checkout(false), Err(GoldfishError::Conflict(_))));
+        assert_eq!(Some(b"modified".to_vec()), read_working_file(&repo, "a"));
+
+        write_working_file(&repo, "a", b"staged");
+        repo.track_file(filesystem::join_path(vec![repo.get_working_path(), "a"]).as_str()).unwrap();
+        assert!(matches!(second.checkout(false), Err(GoldfishError::Conflict(_))));
+        let blob_id = Blob::create(&repo, b"1").unwrap().get_id().to_string();
+        repo.save_staging_tracked_files(HashMap::from([(String::from("a"), blob_id)])).unwrap();
+        write_working_file(&repo, "a", b"1");
+
+        write_working_file(&repo, "b", b"untracked");
+        assert!(matches!(second.checkout(false), Err(GoldfishError::Conflict(_))));
+        assert_eq!(Some(b"1".to_vec()), read_working_file(&repo, "a"));
+        assert_eq!(Some(b"untracked".to_vec()), read_working_file(&repo, "b"));
+
+        write_working_file(&repo, "a", b"modified");
+        second.checkout(true).unwrap();
+        assert_eq!(Some(b"2".to_vec()), read_working_file(&repo, "a"));
+        assert_eq!(Some(b"3".to_vec()), read_working_file(&repo, "b"));
+        assert_eq!(second.load_tracked_files().unwrap(), repo.get_staging_tracked_files().unwrap());
+    }
+
+    #[test]
+    fn test_5_tree() {
+        //! Check that nested directories round-trip through trees, and that a commit shares
+        //! the tree of a directory left unchanged since its parent
+        let repo = create_test_repository("tree");
+        let files: [(&str, &[u8]); 4] = [("README", b"readme"), ("lib/util.rs", b"util"), ("src/main.rs", b"main"), ("src/net/url.rs", b"url")];
+        let tracked_files: HashMap<String, String> = files
+            .iter()
+            .map(|(path, content)| (path.to_string(), Blob::create(&repo, content).unwrap().get_id().to_string()))
+            .collect();
+        let root = Tree::create_from_tracked_files(&repo, &tracked_files).unwrap();
+        assert_eq!(root.get_id(), Tree::create_from_tracked_files(&repo, &tracked_files).unwrap().get_id());
+
+        let loaded = Tree::get(&repo, root.get_id()).unwrap();
+        let entries: Vec<(TreeEntryKind, &str)> = loaded.get_entries().iter().map(|entry| (entry.get_kind().clone(), entry.get_name())).collect();
+        assert_eq!(vec![(TreeEntryKind::Blob, "README"), (TreeEntryKind::Tree, "lib"), (TreeEntryKind::Tree, "src")], entries);
+        let mut loaded_files = HashMap::new();
+        loaded.load_tracked_files(&repo, "", &mut loaded_files).unwrap();
+        assert_eq!(tracked_files, loaded_files);
+        assert_eq!(4, repo.list_objects(TREES_DIR).unwrap().len());
+
+        let first_id = commit_test_files(&repo, &files);
+        let mut changed_files = files;
+        changed_files[0] = ("README", b"changed readme");
+        let second_id = commit_test_files(&repo, &changed_files);
+        let subtrees = |commit_id: &str| {
+            let commit = Commit::get(&repo, commit_id).unwrap();
+            let root = Tree::get(&repo, commit.get_tree_id().unwrap()).unwrap();
+            root.get_entries()
+                .iter()
+                .filter(|entry| *entry.get_kind() == TreeEntryKind::Tree)
+                .map(|entry| entry.get_id().to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(subtrees(first_id.as_str()), subtrees(second_id.as_str()));
+        // only the root tree of the second commit is new
+        assert_eq!(5, repo.list_objects(TREES_DIR).unwrap().len());
+    }
+
+    #[test]
+    fn test_6_legacy_objects() {
+        //! Check that objects written uncompressed, before compression was introduced, can still be read
+        let repo = create_test_repository("legacy_objects");
+        let content = b"legacy content\r\n\xff";
+        let blob_id = utilities::hash(content);
+        let mut legacy = b"blob\n".to_vec();
+        legacy.extend_from_slice(content);
+        filesystem::write_file(&legacy, repo.get_object_path(BLOBS_DIR, blob_id.as_str()).as_str()).unwrap();
+        assert_eq!(content.to_vec(), Blob::get(&repo, blob_id.as_str()).unwrap().get_blob_content().unwrap());
+
+        // a blob written now is compressed, and reads back the same
+        let compressed_id = Blob::create(&repo, b"compressed content").unwrap().get_id().to_string();
+        let raw = filesystem::read_bytes(repo.get_object_path(BLOBS_DIR, compressed_id.as_str()).as_str()).unwrap();
+        assert!(!raw.starts_with(b"blob\n"));
+        assert_eq!(b"compressed content".to_vec(), Blob::get(&repo, compressed_id.as_str()).unwrap().get_blob_content().unwrap());
+
+        filesystem::write_file(b"neither plain nor zlib", repo.get_object_path(BLOBS_DIR, "BAD").as_str()).unwrap();
+        assert!(matches!(Blob::get(&repo, "BAD"), Err(GoldfishError::CorruptObject(_))));
+    }
+
+    #[test]
+    fn test_7_resolve_reference() {
+        //! Check that HEAD, branch names, remote-tracking branches and commit ids resolve to commits
+        let repo = create_test_repository("resolve_reference");
+        assert_eq!(None, resolve_reference(&repo, HEAD));
+        let first_id = commit_test_files(&repo, &[("a", b"1")]);
+        let second_id = commit_test_files(&repo, &[("a", b"2")]);
+        repo.write_branch("feature/x", first_id.as_str()).unwrap();
+        repo.write_remote_branch("origin", "main", first_id.as_str()).unwrap();
+
+        assert_eq!(Some(second_id.clone()), resolve_reference(&repo, HEAD));
+        assert_eq!(Some(second_id.clone()), resolve_reference(&repo, DEFAULT_BRANCH));
+        assert_eq!(Some(first_id.clone()), resolve_reference(&repo, "feature/x"));
+        assert_eq!(Some(first_id.clone()), resolve_reference(&repo, "origin/main"));
+        assert_eq!(Some(first_id.clone()), resolve_reference(&repo, "remotes/origin/main"));
+        assert_eq!(Some(first_id.clone()), resolve_reference(&repo, first_id.as_str()));
+        for reference in ["unknown", "origin/unknown", "other/main", "0123456789ABCDEF"] {
+            assert_eq!(None, resolve_reference(&repo, reference), "{}", reference);
+        }
+    }
+
+    #[test]
+    fn test_8_symbolic_head() {
+        //! Check that committing moves the branch HEAD follows, and that a detached HEAD stays
+        //! a commit id and moves on its own
+        let repo = create_test_repository("symbolic_head");
+        assert_eq!(Some(String::from(DEFAULT_BRANCH)), repo.get_current_branch());
+        assert_eq!("", repo.get_current_commit_id().unwrap());
+        let first_id = commit_test_files(&repo, &[("a", b"1")]);
+        let second_id = commit_test_files(&repo, &[("a", b"2")]);
+        assert_eq!(format!("ref: branches/{}\n", DEFAULT_BRANCH), repo.read_head().unwrap());
+        assert_eq!(second_id, repo.read_branch(DEFAULT_BRANCH).unwrap());
+
+        repo.write_head(format!("{}\n", first_id)).unwrap();
+        assert_eq!(None, repo.get_current_branch());
+        let third_id = commit_test_files(&repo, &[("a", b"3")]);
+        assert_eq!(format!("{}\n", third_id), repo.read_head().unwrap());
+        assert_eq!(third_id, repo.get_current_commit_id().unwrap());
+        assert_eq!(second_id, repo.read_branch(DEFAULT_BRANCH).unwrap());
+
+        repo.write_symbolic_head("feature/x").unwrap();
+        assert_eq!("", repo.get_current_commit_id().unwrap());
+        let fourth_id = commit_test_files(&repo, &[("a", b"4")]);
+        assert_eq!(fourth_id, repo.read_branch("feature/x").unwrap());
+        assert_eq!(Some(String::from("feature/x")), parse_symbolic_head(repo.read_head().unwrap().as_str()));
+        assert_eq!(None, parse_symbolic_head(third_id.as_str()));
+        assert_eq!(None, parse_symbolic_head("ref: remotes/origin/main"));
+    }
+}
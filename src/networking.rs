@@ -1,11 +1,861 @@
-use std::process::Command;
+//! # Networking
+//! Transfer objects and branches between two repositories. The client runs
+//! `goldfish serve {path}` next to the other repository: over ssh for `[user@]host:path`
+//! and `ssh://[user@]host[:port]/path` urls, or as a local process for a plain path.
+//! Both sides then talk over the stdin and stdout of that command:
+//!
+//! ```text
+//! server: goldfish 1                          (advertisement)
+//!         head {content of HEAD}
+//!         branch {name} {commit_id}           (one line per branch)
+//!         end
+//! client: fetch                               (download commits)
+//!         want {commit_id}                    (one line per commit to download)
+//!         have {commit_id}                    (one line per commit the client already has,
+//!         next                                 in rounds of at most 32)
+//! server: common {commit_id}                  (one line per commit of the round it also has)
+//!         next
+//! client: end                                 (after the last round)
+//! server: data {object_dir} {id} {size}\n{size bytes of zlib-compressed content}
+//!         end
+//! client: push [--force]                      (upload objects and move branches)
+//!         data {object_dir} {id} {size}\n{size bytes of zlib-compressed content}
+//!         update {branch} {commit_id}
+//!         end
+//! server: ok                                  (or: rejected {message})
+//! client: quit
+//! ```
+//!
+//! For a fetch, the client walks down from its branches and HEAD, and stops walking each line
+//! of history at the first commit the server also has. The side sending objects then walks the
+//! commit graph from the wanted commits down to the commits the other side has (the commits
+//! the server said it has for a fetch, the advertised branches for a push) and their ancestors,
+//! and only sends the commits in between with the trees and blobs that the commits the other
+//! side has do not already contain.
+//! Without `--force`, the server refuses to move a branch to a commit that does not descend
+//! from the commit the branch is on (a non-fast-forward update). Moving the branch the server
+//! has checked out also checks out the pushed commit there, which is refused if that would
+//! overwrite local changes or if a merge is in progress. A refused push leaves every branch
+//! where it was, and is answered with `rejected {message}`.
+//! Instead of a reply, the server may send `error {message}` and exit with status 1.
+use crate::error::GoldfishError;
+use crate::filesystem;
+use crate::model::{self, Repository};
+use crate::utilities;
+use ssh2::{Channel, CheckResult, KnownHostFileKind, Session};
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
 
+const PROTOCOL_VERSION: &str = "goldfish 1";
+const CONNECTION_CLOSED: &str = "The connection was closed unexpectedly";
+const DEFAULT_SSH_PORT: u16 = 22;
+// commits a fetching client lists before asking which of them the server has
+const HAVES_PER_ROUND: usize = 32;
+// keys tried, in order, when no ssh-agent identity is accepted
+const SSH_KEY_FILES: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+// object types that are transferred, everything else in .goldfish stays local
+const OBJECT_DIRS: [&str; 3] = [model::COMMITS_DIR, model::TREES_DIR, model::BLOBS_DIR];
 
-pub fn rsync(source: &str, destination: &str) -> bool {
-    Command::new("rsync")
-    .arg("-avz")
-    .arg(source)
-    .arg(destination)
-    .output()
-    .is_ok()
+/**
+ * Location of a repository: a path on a host reached over ssh, or a local path
+ */
+#[derive(Debug, PartialEq)]
+pub enum Url {
+    Ssh { user: Option<String>, host: String, port: u16, path: String },
+    Local(String),
+}
+
+pub fn parse_url(url: &str) -> Url {
+    //! Recognize `ssh://[user@]host[:port]/path` and `[user@]host:path` as ssh urls,
+    //! anything else is a local path
+    fn split_user(authority: &str) -> (Option<String>, &str) {
+        match authority.rsplit_once('@') {
+            Some((user, host)) => (Some(user.to_string()), host),
+            None => (None, authority),
+        }
+    }
+
+    if let Some(rest) = url.strip_prefix("ssh://") {
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "."),
+        };
+        let (user, host) = split_user(authority);
+        let (host, port) = match host.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().unwrap_or(DEFAULT_SSH_PORT)),
+            None => (host, DEFAULT_SSH_PORT),
+        };
+        return Url::Ssh { user, host: host.to_string(), port, path: path.to_string() };
+    }
+    match url.split_once(':') {
+        // a single letter before the colon is a Windows drive, not a host
+        Some((authority, path)) if authority.len() > 1 && !authority.contains('/') => {
+            let (user, host) = split_user(authority);
+            Url::Ssh { user, host: host.to_string(), port: DEFAULT_SSH_PORT, path: path.to_string() }
+        }
+        _ => Url::Local(url.to_string()),
+    }
+}
+
+fn is_valid_object_id(id: &str) -> bool {
+    //! Object ids are hex digests, which also keeps them from naming paths outside the object folders
+    !id.is_empty() && id.len() <= 128 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_connection_closed(error: &GoldfishError) -> bool {
+    matches!(error, GoldfishError::Transport(message) if message == CONNECTION_CLOSED)
+}
+
+fn read_error(e: io::Error) -> GoldfishError {
+    GoldfishError::Transport(format!("Fail to read from the connection: {}", e))
+}
+
+fn write_error(e: io::Error) -> GoldfishError {
+    GoldfishError::Transport(format!("Fail to write to the connection: {}", e))
+}
+
+fn unexpected_message(line: &str) -> GoldfishError {
+    GoldfishError::Transport(format!("Unexpected message: {}", line))
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<String, GoldfishError> {
+    //! Read one line of the protocol, turning an `error` line from the other side into an error
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => return Err(GoldfishError::Transport(String::from(CONNECTION_CLOSED))),
+        Ok(_) => {}
+        Err(e) => return Err(read_error(e)),
+    }
+    let line = line.trim_end_matches('\n').to_string();
+    match line.strip_prefix("error ") {
+        Some(message) => Err(GoldfishError::Transport(message.to_string())),
+        None => Ok(line),
+    }
+}
+
+fn write_line(writer: &mut impl Write, line: &str) -> Result<(), GoldfishError> {
+    writer.write_all(format!("{}\n", line).as_bytes()).map_err(write_error)
+}
+
+fn write_object(writer: &mut impl Write, repo: &Repository, object_dir: &str, id: &str) -> Result<(), GoldfishError> {
+    //! Send the `data` line and the compressed content of an object
+    let payload = utilities::compress(&repo.read_object(object_dir, id)?)
+        .map_err(|e| GoldfishError::io(format!("Fail to compress object {}", id), e))?;
+    write_line(writer, format!("data {} {} {}", object_dir, id, payload.len()).as_str())?;
+    writer.write_all(&payload).map_err(write_error)
+}
+
+fn read_object(reader: &mut impl BufRead, repo: &Repository, header: &str) -> Result<(String, String), GoldfishError> {
+    //! Receive the object announced by a `data` line and store it, returning its type and id
+    let items: Vec<&str> = header.split(' ').collect();
+    let size: u64 = match items.as_slice() {
+        ["data", object_dir, id, size] if OBJECT_DIRS.contains(object_dir) && is_valid_object_id(id) => {
+            size.parse().map_err(|_| GoldfishError::Transport(format!("Invalid object size in `{}`", header)))?
+        }
+        _ => return Err(unexpected_message(header)),
+    };
+    let mut payload: Vec<u8> = vec![];
+    reader.take(size).read_to_end(&mut payload).map_err(read_error)?;
+    if payload.len() as u64 != size {
+        return Err(GoldfishError::Transport(String::from(CONNECTION_CLOSED)));
+    }
+    let content = utilities::decompress(&payload)
+        .map_err(|_| GoldfishError::CorruptObject(format!("Object {} is corrupted", items[2])))?;
+    // an object stored under another id would only be noticed by fsck
+    model::check_object_id(items[1], items[2], &content)?;
+    model::check_object_paths(items[1], &content)
+        .map_err(|e| GoldfishError::CorruptObject(format!("Refusing object {}: {}", items[2], e)))?;
+    repo.write_object(items[1], items[2], &content).map_err(|e| GoldfishError::io(format!("Fail to write object {}", items[2]), e))?;
+    Ok((items[1].to_string(), items[2].to_string()))
+}
+
+fn list_local_commit_ids(repo: &Repository) -> Vec<String> {
+    //! Commits of every branch, remote-tracking branch and of HEAD
+    let mut commit_ids: Vec<String> = repo.list_branches().unwrap_or_default()
+        .iter()
+        .filter_map(|branch| repo.read_branch(branch).ok())
+        .collect();
+    for remote in repo.list_remotes_with_branches().unwrap_or_default() {
+        for branch in repo.list_remote_branches(remote.as_str()).unwrap_or_default() {
+            commit_ids.extend(repo.read_remote_branch(remote.as_str(), branch.as_str()).ok());
+        }
+    }
+    commit_ids.extend(repo.get_current_commit_id().ok().filter(|commit_id| !commit_id.is_empty()));
+    commit_ids
+}
+
+// The process running `goldfish serve` for a connection
+enum Process {
+    Ssh { _session: Session, channel: Channel },
+    Local(Child),
+}
+
+// Output and input of a started `goldfish serve`
+type Transport = (Box<dyn Read>, Box<dyn Write>, Process);
+
+/**
+ * Client side of the protocol: a running `goldfish serve` and what it advertised
+ */
+pub struct Connection {
+    reader: BufReader<Box<dyn Read>>,
+    writer: BufWriter<Box<dyn Write>>,
+    process: Process,
+    head: String,
+    branches: Vec<(String, String)>,
+}
+
+impl Connection {
+    pub fn open(url: &str) -> Result<Connection, GoldfishError> {
+        //! Start `goldfish serve` for the repository at the url and read its advertisement
+        let (reader, writer, process) = match parse_url(url) {
+            Url::Ssh { user, host, port, path } => connect_ssh(user, host.as_str(), port, path.as_str())?,
+            Url::Local(path) => spawn_local(path.as_str())?,
+        };
+        let mut connection = Connection {
+            reader: BufReader::new(reader),
+            writer: BufWriter::new(writer),
+            process,
+            head: String::new(),
+            branches: vec![],
+        };
+        match connection.read_advertisement() {
+            Ok(_) => Ok(connection),
+            // the remote command did not start (e.g. goldfish is not installed there)
+            Err(e) if is_connection_closed(&e) => Err(connection.finish().err().unwrap_or(e)),
+            Err(e) => {
+                connection.finish().ok();
+                Err(e)
+            }
+        }
+    }
+
+    fn read_advertisement(&mut self) -> Result<(), GoldfishError> {
+        let version = read_line(&mut self.reader)?;
+        if version != PROTOCOL_VERSION {
+            return Err(GoldfishError::Transport(format!("Unsupported protocol version: {}", version)));
+        }
+        loop {
+            let line = read_line(&mut self.reader)?;
+            match line.split_once(' ') {
+                Some(("head", head)) => self.head = head.to_string(),
+                Some(("branch", branch)) => match branch.split_once(' ') {
+                    Some((name, id)) if model::is_valid_branch_name(name) && is_valid_object_id(id) => {
+                        self.branches.push((name.to_string(), id.to_string()))
+                    }
+                    _ => return Err(GoldfishError::Transport(format!("Invalid branch: {}", branch))),
+                },
+                None if line == "end" => return Ok(()),
+                _ => return Err(unexpected_message(line.as_str())),
+            }
+        }
+    }
+
+    pub fn get_head(&self) -> &str {
+        //! Content of the remote HEAD: `ref: branches/{name}` or a commit id
+        self.head.as_str()
+    }
+
+    pub fn get_branches(&self) -> &Vec<(String, String)> {
+        &self.branches
+    }
+
+    fn get_remote_commit_ids(&self) -> Vec<String> {
+        //! Commits of the remote branches, and of the remote HEAD when it is detached
+        let mut commit_ids: Vec<String> = self.branches.iter().map(|(_, commit_id)| commit_id.to_owned()).collect();
+        if model::parse_symbolic_head(self.head.as_str()).is_none() && is_valid_object_id(self.head.as_str()) {
+            commit_ids.push(self.head.to_owned());
+        }
+        commit_ids
+    }
+
+    pub fn fetch(&mut self, repo: &Repository) -> Result<usize, GoldfishError> {
+        //! Download the remote commits the local repository does not have, with their trees
+        //! and blobs, and return how many objects were received
+        let mut wanted: Vec<String> = self.get_remote_commit_ids();
+        wanted.retain(|commit_id| !repo.has_object(model::COMMITS_DIR, commit_id));
+        wanted.sort();
+        wanted.dedup();
+        if wanted.is_empty() {
+            return Ok(0);
+        }
+        write_line(&mut self.writer, "fetch")?;
+        for commit_id in &wanted {
+            write_line(&mut self.writer, format!("want {}", commit_id).as_str())?;
+        }
+        // walk down from the local branches a round of commits at a time, and stop walking each
+        // line of history at the first commit the remote repository says it also has
+        let mut queue: VecDeque<String> = list_local_commit_ids(repo).into();
+        let mut seen: HashSet<String> = HashSet::new();
+        loop {
+            let mut round: Vec<model::Commit> = vec![];
+            while round.len() < HAVES_PER_ROUND {
+                let Some(commit_id) = queue.pop_front() else { break };
+                if seen.insert(commit_id.to_owned()) {
+                    round.extend(model::Commit::get(repo, commit_id.as_str()).ok());
+                }
+            }
+            if round.is_empty() {
+                break;
+            }
+            for commit in &round {
+                write_line(&mut self.writer, format!("have {}", commit.get_id()).as_str())?;
+            }
+            write_line(&mut self.writer, "next")?;
+            self.writer.flush().map_err(write_error)?;
+            let mut common: HashSet<String> = HashSet::new();
+            loop {
+                let line = read_line(&mut self.reader)?;
+                if line == "next" {
+                    break;
+                }
+                match line.strip_prefix("common ") {
+                    Some(commit_id) => common.insert(commit_id.to_string()),
+                    None => return Err(unexpected_message(line.as_str())),
+                };
+            }
+            for commit in round.iter().filter(|commit| !common.contains(&commit.get_id())) {
+                queue.extend(commit.get_parent_ids().into_iter().map(|id| id.to_string()));
+            }
+        }
+        write_line(&mut self.writer, "end")?;
+        self.writer.flush().map_err(write_error)?;
+        let mut count = 0;
+        loop {
+            let line = read_line(&mut self.reader)?;
+            if line == "end" {
+                break;
+            }
+            read_object(&mut self.reader, repo, line.as_str())?;
+            count += 1;
+        }
+        repo.reload_packs();
+        match wanted.iter().find(|commit_id| !repo.has_object(model::COMMITS_DIR, commit_id)) {
+            Some(commit_id) => Err(GoldfishError::Transport(format!("The remote repository did not send commit {}", commit_id))),
+            None => Ok(count),
+        }
+    }
+
+    pub fn push(&mut self, repo: &Repository, updates: &[(String, String)], force: bool) -> Result<usize, GoldfishError> {
+        //! Upload the commits of `updates` (branch name, commit id) that the remote repository
+        //! does not have, with their trees and blobs, then point each remote branch to its commit,
+        //! even if that is not a fast-forward when `force` is set. Return how many objects were sent
+        let common: HashSet<String> = self.get_remote_commit_ids().into_iter().collect();
+        let commit_ids: Vec<String> = updates.iter().map(|(_, commit_id)| commit_id.to_owned()).collect();
+        let missing = repo.collect_missing_objects(&commit_ids, &common)?;
+        write_line(&mut self.writer, if force { "push --force" } else { "push" })?;
+        for (object_dir, id) in &missing {
+            write_object(&mut self.writer, repo, object_dir, id)?;
+        }
+        for (branch, commit_id) in updates {
+            write_line(&mut self.writer, format!("update {} {}", branch, commit_id).as_str())?;
+        }
+        write_line(&mut self.writer, "end")?;
+        self.writer.flush().map_err(write_error)?;
+        let line = read_line(&mut self.reader)?;
+        match line.strip_prefix("rejected ") {
+            Some(message) => Err(GoldfishError::Conflict(message.to_string())),
+            None if line == "ok" => Ok(missing.len()),
+            None => Err(unexpected_message(line.as_str())),
+        }
+    }
+
+    pub fn close(mut self) -> Result<(), GoldfishError> {
+        //! End the session and check that the remote command succeeded
+        write_line(&mut self.writer, "quit")?;
+        self.writer.flush().map_err(write_error)?;
+        self.finish()
+    }
+
+    fn finish(self) -> Result<(), GoldfishError> {
+        //! Close our side of the connection, wait for the remote command to exit and
+        //! turn a failure into an error with what it printed to stderr
+        let Connection { writer, process, .. } = self;
+        drop(writer);
+        let (status, stderr) = match process {
+            Process::Ssh { _session, mut channel } => {
+                channel.send_eof().ok();
+                let mut stderr = String::new();
+                channel.stderr().read_to_string(&mut stderr).ok();
+                channel.wait_close().map_err(|e| GoldfishError::Transport(format!("Fail to close the ssh channel: {}", e)))?;
+                let status = channel
+                    .exit_status()
+                    .map_err(|e| GoldfishError::Transport(format!("Fail to read the remote exit status: {}", e)))?;
+                (status, stderr)
+            }
+            Process::Local(child) => {
+                let output = child
+                    .wait_with_output()
+                    .map_err(|e| GoldfishError::Transport(format!("Fail to wait for the goldfish server: {}", e)))?;
+                (output.status.code().unwrap_or(-1), String::from_utf8_lossy(&output.stderr).to_string())
+            }
+        };
+        match (status, stderr.trim()) {
+            (0, _) => Ok(()),
+            (status, "") => Err(GoldfishError::Transport(format!("The remote command failed with exit status {}", status))),
+            (status, stderr) => Err(GoldfishError::Transport(format!("The remote command failed with exit status {}: {}", status, stderr))),
+        }
+    }
+}
+
+fn connect_ssh(user: Option<String>, host: &str, port: u16, path: &str) -> Result<Transport, GoldfishError> {
+    //! Open an ssh session to the host and run `goldfish serve` there. The command can be
+    //! changed with the GOLDFISH_SERVE_COMMAND environment variable if goldfish is not on the
+    //! remote PATH
+    let user = user
+        .or_else(|| std::env::var("USER").ok())
+        .or_else(|| std::env::var("USERNAME").ok())
+        .ok_or_else(|| GoldfishError::Transport(String::from("Cannot tell which user to log in as, put it in the url as user@host")))?;
+    let tcp = TcpStream::connect((host, port))
+        .map_err(|e| GoldfishError::Transport(format!("Cannot connect to {}:{}: {}", host, port, e)))?;
+    let mut session = Session::new().map_err(|e| GoldfishError::Transport(format!("Fail to start an ssh session: {}", e)))?;
+    session.set_tcp_stream(tcp);
+    session.handshake().map_err(|e| GoldfishError::Transport(format!("SSH handshake with {} failed: {}", host, e)))?;
+    check_host_key(&session, host, port)?;
+    authenticate(&session, user.as_str(), host)?;
+
+    let serve_command = std::env::var("GOLDFISH_SERVE_COMMAND").unwrap_or_else(|_| String::from("goldfish"));
+    let mut channel = session.channel_session().map_err(|e| GoldfishError::Transport(format!("Fail to open an ssh channel: {}", e)))?;
+    channel.exec(format!("{} serve {}", serve_command, shell_quote(path)).as_str())
+        .map_err(|e| GoldfishError::Transport(format!("Fail to run goldfish on {}: {}", host, e)))?;
+    let reader: Box<dyn Read> = Box::new(channel.stream(0));
+    let writer: Box<dyn Write> = Box::new(channel.stream(0));
+    Ok((reader, writer, Process::Ssh { _session: session, channel }))
+}
+
+fn check_host_key(session: &Session, host: &str, port: u16) -> Result<(), GoldfishError> {
+    //! Only talk to hosts whose key is already in ~/.ssh/known_hosts
+    let mut known_hosts = session.known_hosts().map_err(|e| GoldfishError::Transport(format!("Fail to check the host key: {}", e)))?;
+    let known_hosts_path = filesystem::join_path(vec![home_dir().as_str(), ".ssh", "known_hosts"]);
+    if filesystem::is_file(known_hosts_path.as_str()) {
+        known_hosts.read_file(Path::new(known_hosts_path.as_str()), KnownHostFileKind::OpenSSH)
+            .map_err(|e| GoldfishError::Transport(format!("Fail to read {}: {}", known_hosts_path, e)))?;
+    }
+    let (key, _) = session.host_key().ok_or_else(|| GoldfishError::Transport(format!("{} did not send a host key", host)))?;
+    match known_hosts.check_port(host, port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::NotFound => Err(GoldfishError::Transport(format!(
+            "The host key of {} is not in {}. Connect once with ssh to verify it and add it",
+            host, known_hosts_path
+        ))),
+        CheckResult::Mismatch => Err(GoldfishError::Transport(format!(
+            "The host key of {} does not match the one in {}. Someone could be impersonating the host",
+            host, known_hosts_path
+        ))),
+        CheckResult::Failure => Err(GoldfishError::Transport(format!("Fail to check the host key of {}", host))),
+    }
+}
+
+fn authenticate(session: &Session, user: &str, host: &str) -> Result<(), GoldfishError> {
+    //! Log in with an ssh-agent identity, or else with one of the usual keys in ~/.ssh
+    if session.userauth_agent(user).is_ok() && session.authenticated() {
+        return Ok(());
+    }
+    for key_file in SSH_KEY_FILES {
+        let key_path = filesystem::join_path(vec![home_dir().as_str(), ".ssh", key_file]);
+        if filesystem::is_file(key_path.as_str())
+            && session.userauth_pubkey_file(user, None, Path::new(key_path.as_str()), None).is_ok()
+            && session.authenticated()
+        {
+            return Ok(());
+        }
+    }
+    Err(GoldfishError::Transport(format!(
+        "Authentication failed for {}@{}: no ssh-agent identity or key in ~/.ssh was accepted",
+        user, host
+    )))
+}
+
+fn home_dir() -> String {
+    std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE")).unwrap_or_default()
+}
+
+fn shell_quote(argument: &str) -> String {
+    //! Quote an argument for the remote shell
+    format!("'{}'", argument.replace('\'', "'\\''"))
+}
+
+fn spawn_local(path: &str) -> Result<Transport, GoldfishError> {
+    //! Run `goldfish serve` for a repository on this machine
+    let executable = std::env::current_exe().map_err(|e| GoldfishError::io("Cannot find the goldfish executable", e))?;
+    let mut child = Command::new(executable)
+        .arg("serve")
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| GoldfishError::Transport(format!("Cannot start the goldfish server: {}", e)))?;
+    let reader: Box<dyn Read> = Box::new(child.stdout.take().unwrap());
+    let writer: Box<dyn Write> = Box::new(child.stdin.take().unwrap());
+    Ok((reader, writer, Process::Local(child)))
+}
+
+pub fn serve(path: &str) -> Result<(), GoldfishError> {
+    //! Server side of the protocol, answering requests on stdin and stdout until the
+    //! client quits. Errors are also reported to the client
+    let stdin = io::stdin();
+    let mut reader = stdin.lock();
+    let mut writer = BufWriter::new(io::stdout());
+    let result = serve_repository(path, &mut reader, &mut writer);
+    if let Err(e) = &result {
+        write_line(&mut writer, format!("error {}", e).as_str()).ok();
+    }
+    writer.flush().ok();
+    result
+}
+
+fn open_served_repository(path: &str) -> Result<Repository, GoldfishError> {
+    //! The repository at exactly the given working folder (or .goldfish folder)
+    let working_path = match Path::new(path).file_name() {
+        Some(name) if name == model::GOLDFISH_ROOT_DIR => filesystem::parent(path).unwrap_or(String::from(".")),
+        _ => path.to_string(),
+    };
+    if !filesystem::is_dir(filesystem::join_path(vec![working_path.as_str(), model::GOLDFISH_ROOT_DIR]).as_str()) {
+        return Err(GoldfishError::Invalid(format!("{} is not a goldfish repository", path)));
+    }
+    Repository::find(working_path.as_str()).ok_or_else(|| GoldfishError::Invalid(format!("{} is not a goldfish repository", path)))
+}
+
+fn serve_repository(path: &str, reader: &mut impl BufRead, writer: &mut impl Write) -> Result<(), GoldfishError> {
+    let repo = open_served_repository(path)?;
+
+    // advertisement
+    write_line(writer, PROTOCOL_VERSION)?;
+    write_line(writer, format!("head {}", repo.read_head()?.trim()).as_str())?;
+    for branch in repo.list_branches()? {
+        let commit_id = repo.read_branch(branch.as_str())?;
+        write_line(writer, format!("branch {} {}", branch, commit_id).as_str())?;
+    }
+    write_line(writer, "end")?;
+    writer.flush().map_err(write_error)?;
+
+    loop {
+        let request = match read_line(reader) {
+            Ok(request) => request,
+            Err(e) if is_connection_closed(&e) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        match request.as_str() {
+            "quit" => return Ok(()),
+            "fetch" => {
+                let mut wanted: Vec<String> = vec![];
+                let mut common: HashSet<String> = HashSet::new();
+                // the commits of the current round that both repositories have
+                let mut round: Vec<String> = vec![];
+                loop {
+                    let line = read_line(reader)?;
+                    if line == "end" {
+                        break;
+                    }
+                    if line == "next" {
+                        for commit_id in round.drain(..) {
+                            write_line(writer, format!("common {}", commit_id).as_str())?;
+                        }
+                        write_line(writer, "next")?;
+                        writer.flush().map_err(write_error)?;
+                        continue;
+                    }
+                    match line.split_once(' ') {
+                        Some((_, commit_id)) if !is_valid_object_id(commit_id) => return Err(unexpected_message(line.as_str())),
+                        Some(("want", commit_id)) if repo.has_object(model::COMMITS_DIR, commit_id) => {
+                            wanted.push(commit_id.to_string())
+                        }
+                        Some(("want", commit_id)) => return Err(GoldfishError::UnknownRevision(commit_id.to_string())),
+                        // commits the client has that this repository does not know are of no use
+                        Some(("have", commit_id)) => {
+                            if repo.has_object(model::COMMITS_DIR, commit_id) {
+                                common.insert(commit_id.to_string());
+                                round.push(commit_id.to_string());
+                            }
+                        }
+                        _ => return Err(unexpected_message(line.as_str())),
+                    }
+                }
+                for (object_dir, id) in repo.collect_missing_objects(&wanted, &common)? {
+                    write_object(writer, &repo, object_dir.as_str(), id.as_str())?;
+                }
+                write_line(writer, "end")?;
+            }
+            "push" | "push --force" => {
+                let mut updates: Vec<(String, String)> = vec![];
+                loop {
+                    let line = read_line(reader)?;
+                    if line == "end" {
+                        break;
+                    }
+                    if line.starts_with("data ") {
+                        read_object(reader, &repo, line.as_str())?;
+                        continue;
+                    }
+                    match line.split(' ').collect::<Vec<&str>>().as_slice() {
+                        ["update", branch, commit_id] if model::is_valid_branch_name(branch) && is_valid_object_id(commit_id) => {
+                            updates.push((branch.to_string(), commit_id.to_string()))
+                        }
+                        _ => return Err(unexpected_message(line.as_str())),
+                    }
+                }
+                // a refused update is not an error of the session, which goes on
+                match update_branches(&repo, &updates, request == "push --force") {
+                    Ok(()) => write_line(writer, "ok")?,
+                    Err(GoldfishError::Conflict(message)) => write_line(writer, format!("rejected {}", message).as_str())?,
+                    Err(e) => return Err(e),
+                }
+            }
+            _ => return Err(GoldfishError::Transport(format!("Unknown request: {}", request))),
+        }
+        writer.flush().map_err(write_error)?;
+    }
+}
+
+fn update_branches(repo: &Repository, updates: &[(String, String)], force: bool) -> Result<(), GoldfishError> {
+    //! Move each branch of `updates` (branch name, commit id) to its commit once every object it
+    //! needs has arrived. Unless `force` is set, only fast-forward updates are accepted. The branch
+    //! checked out here also gets the files of its new commit, which is refused if that would
+    //! overwrite local changes. Refusals are conflicts, and then no branch moves
+    repo.reload_packs();
+    let known: HashSet<String> = list_local_commit_ids(repo).into_iter().collect();
+    for (branch, commit_id) in updates {
+        let needed = repo.collect_missing_objects(&[commit_id.to_owned()], &known)
+            .map_err(|e| GoldfishError::CorruptObject(format!("Cannot update {}: {}", branch, e)))?;
+        if let Some((_, id)) = needed.iter().find(|(object_dir, id)| !repo.has_object(object_dir, id)) {
+            return Err(GoldfishError::CorruptObject(format!("Cannot update {}, object {} is missing", branch, id)));
+        }
+        if !force {
+            if let Ok(current_id) = repo.read_branch(branch) {
+                if !repo.is_ancestor(current_id.as_str(), commit_id) {
+                    return Err(GoldfishError::Conflict(format!(
+                        "Rejected {} (non-fast-forward): the branch has commits that the pushed commit does not contain",
+                        branch
+                    )));
+                }
+            }
+        }
+    }
+    let current_branch = repo.get_current_branch();
+    if let Some((branch, commit_id)) = updates.iter().find(|(branch, _)| current_branch.as_ref() == Some(branch)) {
+        if repo.read_merge_head().is_some() {
+            return Err(GoldfishError::Conflict(format!(
+                "Rejected {}: it is checked out in the remote repository, which is in the middle of a merge",
+                branch
+            )));
+        }
+        model::Commit::get(repo, commit_id)?.checkout(false).map_err(|e| match e {
+            GoldfishError::Conflict(_) => GoldfishError::Conflict(format!(
+                "Rejected {}: it is checked out in the remote repository, which has local changes the pushed commit would overwrite",
+                branch
+            )),
+            e => e,
+        })?;
+    }
+    for (branch, commit_id) in updates {
+        repo.write_branch(branch, commit_id)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::GoldfishError;
+    use crate::filesystem;
+    use crate::model::tests::{commit_test_files, create_test_repository};
+    use crate::model::{self, Repository};
+    use crate::networking::{parse_url, read_line, read_object, serve_repository, write_line, write_object, Url};
+    use crate::utilities;
+    use std::collections::HashSet;
+    use std::io::Cursor;
+
+    fn serve(repo: &Repository, requests: &[u8]) -> (Result<(), GoldfishError>, Vec<u8>) {
+        //! Run the server on a repository with the given requests, and return how it ended and what it sent
+        let mut output: Vec<u8> = vec![];
+        let result = serve_repository(repo.get_working_path(), &mut Cursor::new(requests), &mut output);
+        (result, output)
+    }
+
+    fn push_request(client: &Repository, branch: &str, commit_id: &str, request: &str) -> Vec<u8> {
+        //! A push of every object of a commit, moving a branch of the server to it
+        let mut requests: Vec<u8> = vec![];
+        write_line(&mut requests, request).unwrap();
+        for (object_dir, id) in client.collect_missing_objects(&[commit_id.to_string()], &HashSet::new()).unwrap() {
+            write_object(&mut requests, client, object_dir.as_str(), id.as_str()).unwrap();
+        }
+        write_line(&mut requests, format!("update {} {}", branch, commit_id).as_str()).unwrap();
+        write_line(&mut requests, "end").unwrap();
+        write_line(&mut requests, "quit").unwrap();
+        requests
+    }
+
+    fn reply_lines(output: &[u8]) -> Vec<String> {
+        //! The lines the server sent after its advertisement
+        let text = String::from_utf8_lossy(output);
+        let lines: Vec<&str> = text.lines().collect();
+        let end = lines.iter().position(|line| *line == "end").unwrap();
+        lines[end + 1..].iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_1_parse_url() {
+        //! Check that ssh urls are told apart from local paths
+        assert_eq!(
+            Url::Ssh { user: Some("fish".to_string()), host: "tank.org".to_string(), port: 22, path: "repos/goldfish".to_string() },
+            parse_url("fish@tank.org:repos/goldfish")
+        );
+        assert_eq!(
+            Url::Ssh { user: None, host: "tank.org".to_string(), port: 2222, path: "/srv/goldfish".to_string() },
+            parse_url("ssh://tank.org:2222/srv/goldfish")
+        );
+        assert_eq!(Url::Local("../goldfish".to_string()), parse_url("../goldfish"));
+        assert_eq!(Url::Local("./a:b".to_string()), parse_url("./a:b"));
+        assert_eq!(Url::Local("C:\\goldfish".to_string()), parse_url("C:\\goldfish"));
+    }
+    #[test]
+    fn test_2_push_checked_out_branch() {
+        //! Check that a push to the branch the server has checked out updates its files, and is
+        //! rejected when that would overwrite local changes there
+        let server = create_test_repository("serve_checked_out_server");
+        let client = create_test_repository("serve_checked_out_client");
+        let first_id = commit_test_files(&client, &[("a", b"1"), ("dir/b", b"2")]);
+
+        let (result, output) = serve(&server, &push_request(&client, "main", first_id.as_str(), "push"));
+        assert!(result.is_ok());
+        assert_eq!(vec!["ok"], reply_lines(&output));
+        let file_path = filesystem::join_path(vec![server.get_working_path(), "dir/b"]);
+        assert_eq!(b"2".to_vec(), filesystem::read_bytes(file_path.as_str()).unwrap());
+        assert_eq!(client.get_staging_tracked_files().unwrap(), server.get_staging_tracked_files().unwrap());
+
+        let second_id = commit_test_files(&client, &[("a", b"1"), ("dir/b", b"3")]);
+        filesystem::write_file(b"local", file_path.as_str()).unwrap();
+        let (result, output) = serve(&server, &push_request(&client, "main", second_id.as_str(), "push"));
+        assert!(result.is_ok());
+        let reply = reply_lines(&output);
+        assert_eq!(1, reply.len());
+        assert!(reply[0].starts_with("rejected Rejected main: it is checked out"), "{}", reply[0]);
+        assert_eq!(first_id, server.read_branch("main").unwrap());
+        assert_eq!(b"local".to_vec(), filesystem::read_bytes(file_path.as_str()).unwrap());
+    }
+
+    #[test]
+    fn test_3_push_non_fast_forward() {
+        //! Check that a push that would drop commits of the branch is rejected unless forced
+        let server = create_test_repository("serve_non_fast_forward_server");
+        let server_id = commit_test_files(&server, &[("a", b"1")]);
+        let client = create_test_repository("serve_non_fast_forward_client");
+        let client_id = commit_test_files(&client, &[("a", b"2")]);
+
+        let (result, output) = serve(&server, &push_request(&client, "main", client_id.as_str(), "push"));
+        assert!(result.is_ok());
+        let reply = reply_lines(&output);
+        assert_eq!(1, reply.len());
+        assert!(reply[0].starts_with("rejected Rejected main (non-fast-forward)"), "{}", reply[0]);
+        assert_eq!(server_id, server.read_branch("main").unwrap());
+
+        let (result, output) = serve(&server, &push_request(&client, "main", client_id.as_str(), "push --force"));
+        assert!(result.is_ok());
+        assert_eq!(vec!["ok"], reply_lines(&output));
+        assert_eq!(client_id, server.read_branch("main").unwrap());
+    }
+    #[test]
+    fn test_4_fetch_negotiation() {
+        //! Check that the server tells which commits of a round it has, and then only sends the
+        //! objects the client does not have
+        let server = create_test_repository("serve_negotiation_server");
+        let base_id = commit_test_files(&server, &[("a", b"1")]);
+        let head_id = commit_test_files(&server, &[("a", b"1"), ("b", b"2")]);
+        let client = create_test_repository("serve_negotiation_client");
+        let unknown_id = commit_test_files(&client, &[("c", b"3")]);
+
+        let requests = format!("fetch\nwant {}\nhave {}\nhave {}\nnext\nend\nquit\n", head_id, unknown_id, base_id);
+        let (result, output) = serve(&server, requests.as_bytes());
+        assert!(result.is_ok());
+        let mut reader = Cursor::new(output);
+        while read_line(&mut reader).unwrap() != "end" {}
+        assert_eq!(format!("common {}", base_id), read_line(&mut reader).unwrap());
+        assert_eq!("next", read_line(&mut reader).unwrap());
+        let mut received: Vec<(String, String)> = vec![];
+        loop {
+            let line = read_line(&mut reader).unwrap();
+            if line == "end" {
+                break;
+            }
+            received.push(read_object(&mut reader, &client, line.as_str()).unwrap());
+        }
+        // the new commit, its tree and the blob of b
+        let expected: HashSet<(String, String)> = [
+            (model::COMMITS_DIR.to_string(), head_id.to_owned()),
+            (model::TREES_DIR.to_string(), model::Commit::get(&server, head_id.as_str()).unwrap().get_tree_id().unwrap().to_string()),
+            (model::BLOBS_DIR.to_string(), utilities::hash(b"2")),
+        ].into();
+        assert_eq!(expected, received.into_iter().collect());
+    }
+    #[test]
+    fn test_5_fetch() {
+        //! Check that the server advertises its HEAD and branches, and sends every object of a
+        //! wanted commit to a client that has none of them
+        let server = create_test_repository("serve_fetch_server");
+        commit_test_files(&server, &[("a", b"1")]);
+        let head_id = commit_test_files(&server, &[("a", b"1"), ("dir/b", b"2")]);
+        let client = create_test_repository("serve_fetch_client");
+
+        let (result, output) = serve(&server, format!("fetch\nwant {}\nend\nquit\n", head_id).as_bytes());
+        assert!(result.is_ok());
+        let mut reader = Cursor::new(output);
+        let advertisement: Vec<String> = (0..4).map(|_| read_line(&mut reader).unwrap()).collect();
+        assert_eq!(vec!["goldfish 1", "head ref: branches/main", format!("branch main {}", head_id).as_str(), "end"], advertisement);
+        loop {
+            let line = read_line(&mut reader).unwrap();
+            if line == "end" {
+                break;
+            }
+            read_object(&mut reader, &client, line.as_str()).unwrap();
+        }
+        let objects = client.collect_missing_objects(&[head_id.to_owned()], &HashSet::new()).unwrap();
+        // two commits, the root tree of each, the tree of dir and the blobs of a and b
+        assert_eq!(7, objects.len());
+        assert!(objects.iter().all(|(object_dir, id)| client.has_object(object_dir, id)));
+
+        let (result, _) = serve(&server, format!("fetch\nwant {}\nend\n", utilities::hash(b"unknown")).as_bytes());
+        assert!(matches!(result, Err(GoldfishError::UnknownRevision(_))));
+    }
+
+    #[test]
+    fn test_6_push() {
+        //! Check that a push stores the objects and moves a branch that is not checked out
+        let server = create_test_repository("serve_push_server");
+        let client = create_test_repository("serve_push_client");
+        let commit_id = commit_test_files(&client, &[("a", b"1"), ("dir/b", b"2")]);
+
+        let (result, output) = serve(&server, &push_request(&client, "topic", commit_id.as_str(), "push"));
+        assert!(result.is_ok());
+        assert_eq!(vec!["ok"], reply_lines(&output));
+        assert_eq!(commit_id, server.read_branch("topic").unwrap());
+        assert!(server.collect_missing_objects(&[commit_id.to_owned()], &HashSet::new()).unwrap()
+            .iter()
+            .all(|(object_dir, id)| server.has_object(object_dir, id)));
+        assert!(!filesystem::is_file(filesystem::join_path(vec![server.get_working_path(), "a"]).as_str()));
+    }
+
+    #[test]
+    fn test_7_malformed_lines() {
+        //! Check that malformed `data`, `update` and `want` lines end the session with an error
+        let server = create_test_repository("serve_malformed_server");
+        let commit_id = commit_test_files(&server, &[("a", b"1")]);
+        let requests = [
+            String::from("push\ndata blobs ../../HEAD 5\nhello\nend\n"),
+            String::from("push\ndata config ABCD 5\nhello\nend\n"),
+            String::from("push\ndata blobs ABCD five\nhello\nend\n"),
+            String::from("push\ndata blobs ABCD 100\nhello"),
+            format!("push\nupdate main {}\nend\n", "../HEAD"),
+            format!("push\nupdate ../main {}\nend\n", commit_id),
+            format!("push\nupdate main {} extra\nend\n", commit_id),
+            format!("fetch\nwant ../{}\nend\n", commit_id),
+        ];
+        for request in requests {
+            let (result, output) = serve(&server, request.as_bytes());
+            assert!(matches!(result, Err(GoldfishError::Transport(_))), "{}", request);
+            assert!(reply_lines(&output).is_empty(), "{}", request);
+        }
+        assert_eq!(commit_id, server.read_branch("main").unwrap());
+    }
 }
@@ -0,0 +1,444 @@
+//! # Pack files
+//! A pack file stores many objects in one file, next to an index file listing where
+//! each object starts. Blobs that are similar to another blob of the same pack are
+//! stored as a delta (a list of byte ranges to copy from the other blob, plus the bytes
+//! to insert between them) instead of their full content.
+use crate::error::GoldfishError;
+use crate::filesystem;
+use crate::model::{Repository, Tree, TreeEntryKind, BLOBS_DIR, COMMITS_DIR, TREES_DIR};
+use crate::utilities;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/**
+ * Pack file format:
+ * ```
+ * GFPACK1\n
+ * {{ one entry per object, at the offset listed in the index }}
+ * ```
+ * Each entry is a type byte (0 for a full object, 1 for a delta), followed for deltas
+ * by the base object id (a length byte, then the id), then the length of the payload
+ * as a 4-byte big-endian integer, then the zlib-compressed payload (the object content
+ * or the delta).
+ *
+ * Index file format:
+ * ```
+ * packindex\n
+ * {object_dir} {object_id} {offset}\n
+ * {{ more object lines if necessary }}
+ * ```
+ */
+const PACK_SIGNATURE: &[u8] = b"GFPACK1\n";
+const INDEX_HEADER: &str = "packindex";
+pub const PACK_EXTENSION: &str = "pack";
+pub const INDEX_EXTENSION: &str = "idx";
+
+const ENTRY_FULL: u8 = 0;
+const ENTRY_DELTA: u8 = 1;
+
+// delta instructions
+const DELTA_COPY: u8 = 1;
+const DELTA_INSERT: u8 = 2;
+// size of the blocks of the base object that the delta search looks up
+const DELTA_BLOCK_SIZE: usize = 16;
+// number of previously packed blobs a blob is compared against
+const DELTA_WINDOW: usize = 10;
+// longest chain of deltas (a delta whose base is a delta whose base is ...)
+const MAX_DELTA_DEPTH: usize = 10;
+
+#[derive(Debug)]
+pub struct Pack {
+    pack_path: String,
+    // {(object_dir, object_id): offset of the entry in the pack file}
+    offsets: HashMap<(String, String), u64>,
+}
+
+impl Pack {
+    pub fn load_all(packs_path: &str) -> io::Result<Vec<Pack>> {
+        //! Load the index of every pack file in the given folder
+        let mut packs = vec![];
+        if !filesystem::is_dir(packs_path) {
+            return Ok(packs)
+        }
+        for index_path in filesystem::list_files(packs_path, false, &vec![])? {
+            if let Some(pack_path) = index_path.strip_suffix(format!(".{}", INDEX_EXTENSION).as_str()) {
+                packs.push(Pack::load(format!("{}.{}", pack_path, PACK_EXTENSION).as_str(), index_path.as_str())?);
+            }
+        }
+        Ok(packs)
+    }
+
+    fn load(pack_path: &str, index_path: &str) -> io::Result<Pack> {
+        let content = filesystem::read_file(index_path)?;
+        let mut lines = content.split_terminator('\n');
+        if lines.next() != Some(INDEX_HEADER) {
+            return Err(corrupt(index_path))
+        }
+        let mut offsets = HashMap::new();
+        for line in lines {
+            let items: Vec<&str> = line.split(' ').collect();
+            if items.len() != 3 {
+                return Err(corrupt(index_path))
+            }
+            let offset = items[2].parse().map_err(|_| corrupt(index_path))?;
+            offsets.insert((items[0].to_string(), items[1].to_string()), offset);
+        }
+        Ok(Pack { pack_path: pack_path.to_string(), offsets })
+    }
+
+    pub fn get_pack_path(&self) -> &str {
+        &self.pack_path
+    }
+
+    pub fn contains(&self, object_dir: &str, id: &str) -> bool {
+        self.offsets.contains_key(&(object_dir.to_string(), id.to_string()))
+    }
+
+    pub fn list_objects(&self, object_dir: &str) -> Vec<String> {
+        //! Return the ids of all objects of the given type stored in this pack
+        self.offsets.keys().filter(|(dir, _)| dir == object_dir).map(|(_, id)| id.to_owned()).collect()
+    }
+
+    pub fn get_entry_size(&self, object_dir: &str, id: &str) -> io::Result<u64> {
+        //! Return the number of bytes the entry of an object takes in the pack file
+        let offset = match self.offsets.get(&(object_dir.to_string(), id.to_string())) {
+            Some(offset) => *offset,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} is not in the pack", id))),
+        };
+        // the entry ends where the next one starts, or at the end of the file
+        let end = match self.offsets.values().filter(|other| **other > offset).min() {
+            Some(next_offset) => *next_offset,
+            None => filesystem::file_size(self.pack_path.as_str())?,
+        };
+        Ok(end - offset)
+    }
+
+    pub fn read_object(&self, object_dir: &str, id: &str) -> io::Result<Vec<u8>> {
+        //! Read the uncompressed content of an object stored in this pack
+        let mut file = File::open(&self.pack_path)?;
+        self.read_entry(&mut file, object_dir, id, 0)
+    }
+
+    fn read_entry(&self, file: &mut File, object_dir: &str, id: &str, depth: usize) -> io::Result<Vec<u8>> {
+        let offset = match self.offsets.get(&(object_dir.to_string(), id.to_string())) {
+            Some(offset) => *offset,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} is not in the pack", id))),
+        };
+        if depth > MAX_DELTA_DEPTH {
+            return Err(corrupt(&self.pack_path))
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        let mut entry_type = [0u8; 1];
+        file.read_exact(&mut entry_type)?;
+        let base_id = match entry_type[0] {
+            ENTRY_FULL => None,
+            ENTRY_DELTA => {
+                let mut base_id_length = [0u8; 1];
+                file.read_exact(&mut base_id_length)?;
+                let mut base_id = vec![0u8; base_id_length[0] as usize];
+                file.read_exact(&mut base_id)?;
+                Some(String::from_utf8(base_id).map_err(|_| corrupt(&self.pack_path))?)
+            }
+            _ => return Err(corrupt(&self.pack_path)),
+        };
+        let mut payload_length = [0u8; 4];
+        file.read_exact(&mut payload_length)?;
+        let mut payload = vec![0u8; u32::from_be_bytes(payload_length) as usize];
+        file.read_exact(&mut payload)?;
+        let payload = utilities::decompress(&payload)?;
+        match base_id {
+            Some(base_id) => {
+                let base = self.read_entry(file, object_dir, base_id.as_str(), depth + 1)?;
+                apply_delta(&base, &payload)
+            }
+            None => Ok(payload),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PackSummary {
+    pub pack_name: String,
+    pub object_count: usize,
+    pub delta_count: usize,
+    pub pack_size: u64,
+    // size of the loose objects and packs that the new pack replaced
+    pub replaced_size: u64,
+}
+
+pub fn write_pack(packs_path: &str, objects: &[(String, String, Vec<u8>)], name_hints: &HashMap<String, String>) -> io::Result<PackSummary> {
+    //! Write the given (object_dir, object_id, content) objects into a new pack file and its
+    //! index. Blobs are sorted by file name and size so that versions of the same file are
+    //! next to each other, then each blob is stored as a delta against one of the blobs
+    //! before it if that takes less than half of its size
+    let mut order: Vec<usize> = (0..objects.len()).collect();
+    order.sort_by(|a, b| {
+        let (dir_a, id_a, content_a) = &objects[*a];
+        let (dir_b, id_b, content_b) = &objects[*b];
+        dir_a
+            .cmp(dir_b)
+            .then(name_hints.get(id_a).cmp(&name_hints.get(id_b)))
+            .then(content_b.len().cmp(&content_a.len()))
+    });
+
+    let mut pack = PACK_SIGNATURE.to_vec();
+    let mut index = format!("{}\n", INDEX_HEADER);
+    let mut depths: HashMap<&str, usize> = HashMap::new();
+    let mut window: Vec<usize> = vec![];
+    let mut delta_count = 0;
+    for i in order {
+        let (object_dir, id, content) = &objects[i];
+        index.push_str(format!("{} {} {}\n", object_dir, id, pack.len()).as_str());
+
+        // find the blob in the window that gives the smallest delta
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        if object_dir == BLOBS_DIR {
+            for candidate in window.iter() {
+                let (_, candidate_id, candidate_content) = &objects[*candidate];
+                // a delta can only point into blobs whose offsets fit in 4 bytes
+                if depths[candidate_id.as_str()] >= MAX_DELTA_DEPTH || u32::try_from(candidate_content.len().max(content.len())).is_err() {
+                    continue;
+                }
+                let delta = create_delta(candidate_content, content)?;
+                let best_size = best.as_ref().map_or(content.len() / 2, |(_, best_delta)| best_delta.len());
+                if delta.len() < best_size {
+                    best = Some((*candidate, delta));
+                }
+            }
+            window.push(i);
+            if window.len() > DELTA_WINDOW {
+                window.remove(0);
+            }
+        }
+
+        let payload = match best {
+            Some((base, delta)) => {
+                let base_id = &objects[base].1;
+                depths.insert(id.as_str(), depths[base_id.as_str()] + 1);
+                delta_count += 1;
+                pack.push(ENTRY_DELTA);
+                pack.push(base_id.len() as u8);
+                pack.extend_from_slice(base_id.as_bytes());
+                utilities::compress(&delta)?
+            }
+            None => {
+                depths.insert(id.as_str(), 0);
+                pack.push(ENTRY_FULL);
+                utilities::compress(content)?
+            }
+        };
+        pack.extend_from_slice(&encode_length(payload.len())?);
+        pack.extend_from_slice(&payload);
+    }
+
+    let pack_name = format!("pack-{}", utilities::hash(&pack));
+    let pack_path = filesystem::join_path(vec![packs_path, format!("{}.{}", pack_name, PACK_EXTENSION).as_str()]);
+    let index_path = filesystem::join_path(vec![packs_path, format!("{}.{}", pack_name, INDEX_EXTENSION).as_str()]);
+    filesystem::write_file(&pack, pack_path.as_str())?;
+    // the index is written last: a pack without an index is ignored
+    filesystem::write_file(index, index_path.as_str())?;
+    Ok(PackSummary { pack_name, object_count: objects.len(), delta_count, pack_size: pack.len() as u64, replaced_size: 0 })
+}
+
+pub fn repack(repo: &Repository, keep: Option<&HashSet<(String, String)>>) -> Result<Option<PackSummary>, GoldfishError> {
+    //! Move every object of the repository (loose or already packed) into a single new pack,
+    //! then delete the loose objects and the old packs. If `keep` is given, only the objects
+    //! whose (object_dir, id) is in it are packed and every other object is dropped.
+    //! Returns None if there was no object to pack
+    let mut objects = vec![];
+    let mut name_hints = HashMap::new();
+    for object_dir in [COMMITS_DIR, TREES_DIR, BLOBS_DIR] {
+        for id in repo.list_objects(object_dir)? {
+            if keep.is_some_and(|keep| !keep.contains(&(object_dir.to_string(), id.to_owned()))) {
+                continue;
+            }
+            if object_dir == TREES_DIR {
+                // remember a file name for each blob so that versions of a file get packed together
+                if let Ok(tree) = Tree::get(repo, id.as_str()) {
+                    for entry in tree.get_entries() {
+                        if *entry.get_kind() == TreeEntryKind::Blob {
+                            name_hints.insert(entry.get_id().to_string(), entry.get_name().to_string());
+                        }
+                    }
+                }
+            }
+            let content = repo.read_object(object_dir, id.as_str())?;
+            objects.push((object_dir.to_string(), id, content));
+        }
+    }
+    let old_packs = repo.get_packs();
+    let mut summary = if objects.is_empty() {
+        None
+    } else {
+        Some(write_pack(repo.get_packs_path().as_str(), &objects, &name_hints).map_err(|e| GoldfishError::io("Fail to write the pack", e))?)
+    };
+
+    // everything is safely in the new pack: remove the loose objects and the old packs
+    let mut replaced_size = 0;
+    for object_dir in [COMMITS_DIR, TREES_DIR, BLOBS_DIR] {
+        for id in repo.list_loose_objects(object_dir)? {
+            let object_path = repo.get_object_path(object_dir, id.as_str());
+            replaced_size += filesystem::file_size(object_path.as_str())?;
+            filesystem::remove(object_path.as_str()).map_err(|e| GoldfishError::io(format!("Fail to remove {}", object_path), e))?;
+        }
+    }
+    for pack_path in old_packs {
+        replaced_size += filesystem::file_size(pack_path.as_str())?;
+        if summary.as_ref().is_some_and(|summary| pack_path.contains(summary.pack_name.as_str())) {
+            continue;
+        }
+        let base_path = pack_path.trim_end_matches(PACK_EXTENSION);
+        filesystem::remove(format!("{}{}", base_path, INDEX_EXTENSION).as_str())
+            .and_then(|_| filesystem::remove(pack_path.as_str()))
+            .map_err(|e| GoldfishError::io(format!("Fail to remove {}", pack_path), e))?;
+    }
+    repo.reload_packs();
+    if let Some(summary) = summary.as_mut() {
+        summary.replaced_size = replaced_size;
+    }
+    Ok(summary)
+}
+
+pub fn create_delta(base: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+    //! Describe the target as byte ranges copied from the base and bytes inserted between them.
+    //! Fails if an offset or a length does not fit in 4 bytes
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    let mut start = 0;
+    while start + DELTA_BLOCK_SIZE <= base.len() {
+        blocks.entry(&base[start..start + DELTA_BLOCK_SIZE]).or_insert(start);
+        start += DELTA_BLOCK_SIZE;
+    }
+
+    let mut delta = vec![];
+    let mut insert_start = 0;
+    let mut i = 0;
+    while i + DELTA_BLOCK_SIZE <= target.len() {
+        match blocks.get(&target[i..i + DELTA_BLOCK_SIZE]) {
+            Some(base_start) => {
+                let mut length = DELTA_BLOCK_SIZE;
+                while base_start + length < base.len() && i + length < target.len() && base[base_start + length] == target[i + length] {
+                    length += 1;
+                }
+                push_insert(&mut delta, &target[insert_start..i])?;
+                delta.push(DELTA_COPY);
+                delta.extend_from_slice(&encode_length(*base_start)?);
+                delta.extend_from_slice(&encode_length(length)?);
+                i += length;
+                insert_start = i;
+            }
+            None => i += 1,
+        }
+    }
+    push_insert(&mut delta, &target[insert_start..])?;
+    Ok(delta)
+}
+
+fn push_insert(delta: &mut Vec<u8>, data: &[u8]) -> io::Result<()> {
+    if !data.is_empty() {
+        delta.push(DELTA_INSERT);
+        delta.extend_from_slice(&encode_length(data.len())?);
+        delta.extend_from_slice(data);
+    }
+    Ok(())
+}
+
+fn encode_length(length: usize) -> io::Result<[u8; 4]> {
+    //! Encode an offset or a length as a 4-byte big-endian integer, as pack entries and deltas store them
+    u32::try_from(length)
+        .map(|length| length.to_be_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("{} bytes do not fit in a pack entry (4 GiB at most)", length)))
+}
+
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    //! Rebuild the target that `create_delta` was given from the base and the delta
+    fn read_u32(delta: &[u8], at: usize) -> io::Result<usize> {
+        match delta.get(at..at + 4) {
+            Some(bytes) => Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, "Truncated delta")),
+        }
+    }
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid delta");
+
+    let mut result = vec![];
+    let mut i = 0;
+    while i < delta.len() {
+        match delta[i] {
+            DELTA_COPY => {
+                let start = read_u32(delta, i + 1)?;
+                let length = read_u32(delta, i + 5)?;
+                result.extend_from_slice(base.get(start..start + length).ok_or_else(invalid)?);
+                i += 9;
+            }
+            DELTA_INSERT => {
+                let length = read_u32(delta, i + 1)?;
+                result.extend_from_slice(delta.get(i + 5..i + 5 + length).ok_or_else(invalid)?);
+                i += 5 + length;
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(result)
+}
+
+fn corrupt(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Corrupt pack file {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::tests::{commit_test_files, create_test_repository};
+    use crate::model::{Blob, Commit, BLOBS_DIR};
+    use crate::pack::{apply_delta, create_delta, repack, Pack};
+    use crate::utilities;
+
+    #[test]
+    fn test_1_delta() {
+        //! Check that a delta rebuilds the target and is much smaller than it
+        let base: Vec<u8> = (0..4000).map(|i| (i % 251) as u8).collect();
+        let mut target = base.clone();
+        target.splice(1000..1010, b"inserted bytes".iter().cloned());
+        target.truncate(3500);
+        target.extend_from_slice(b"new ending without newline");
+        let delta = create_delta(&base, &target).unwrap();
+        assert!(delta.len() < target.len() / 10);
+        assert_eq!(target, apply_delta(&base, &delta).unwrap());
+    }
+
+    #[test]
+    fn test_2_delta_unrelated() {
+        //! Check that a delta against unrelated or empty data still rebuilds the target
+        let target = b"short target".to_vec();
+        assert_eq!(target, apply_delta(b"", &create_delta(b"", &target).unwrap()).unwrap());
+        assert_eq!(Vec::<u8>::new(), apply_delta(b"base", &create_delta(b"base", b"").unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_3_repack() {
+        //! Check that objects read back from a pack after a repack, including a blob stored as a delta
+        let repo = create_test_repository("pack-repack");
+        // bytes that zlib cannot shrink, so that only a delta makes the entry small
+        let base: Vec<u8> = (0..4000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+        let mut target = base.clone();
+        target.splice(2000..2000, b"a few more bytes".iter().cloned());
+        target.truncate(3000);
+        let first_id = commit_test_files(&repo, &[("notes.txt", &base)]);
+        let second_id = commit_test_files(&repo, &[("notes.txt", &target)]);
+
+        let summary = repack(&repo, None).unwrap().unwrap();
+        assert_eq!(6, summary.object_count);
+        assert_eq!(1, summary.delta_count);
+        assert!(repo.list_loose_objects(BLOBS_DIR).unwrap().is_empty());
+
+        // the smaller version comes after the bigger one, so it is the delta
+        let target_id = utilities::hash(&target);
+        let packs = Pack::load_all(repo.get_packs_path().as_str()).unwrap();
+        assert_eq!(1, packs.len());
+        let base_size = packs[0].get_entry_size(BLOBS_DIR, utilities::hash(&base).as_str()).unwrap();
+        assert!(packs[0].get_entry_size(BLOBS_DIR, target_id.as_str()).unwrap() < base_size / 10);
+        assert_eq!(target, Blob::get(&repo, target_id.as_str()).unwrap().get_blob_content().unwrap());
+        assert_eq!(base, Blob::get(&repo, utilities::hash(&base).as_str()).unwrap().get_blob_content().unwrap());
+        let second = Commit::get(&repo, second_id.as_str()).unwrap();
+        assert_eq!(first_id, second.get_direct_parent().unwrap().get_id());
+    }
+}
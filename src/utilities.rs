@@ -1,60 +1,344 @@
 // # Common Utilities
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
 use sha2::{Sha256, Digest};
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::io::{self, Read, Write};
 
 // Returns a list of differences (line by line) in the two strings
 // (e.g. [('+', 'this_line_only_in_2\n'), ('=', 'common_line\n'), ('-', 'this_line_not_in_2\n)])
+// Within a run of changed lines, the added lines come before the removed ones
 pub fn diff(a: Vec<String>, b: Vec<String>) -> Vec<(String, String)> {
-    let lcs_result: Vec<String> = lcs(&a, &b);
+    // compare small integer ids instead of whole lines
+    let mut ids: HashMap<&str, usize> = HashMap::new();
+    let mut a_ids: Vec<usize> = vec![];
+    let mut b_ids: Vec<usize> = vec![];
+    for (lines, line_ids) in [(&a, &mut a_ids), (&b, &mut b_ids)] {
+        for line in lines.iter() {
+            let next_id = ids.len();
+            line_ids.push(*ids.entry(line.as_str()).or_insert(next_id));
+        }
+    }
+
+    let mut edits: Vec<Edit> = vec![];
+    myers(&a_ids, &b_ids, 0, a.len(), 0, b.len(), &mut edits);
+
     let mut diff: Vec<(String, String)> = vec![];
-    let mut index_a: usize = 0;
-    let mut index_b: usize = 0;
-    let mut index: usize = 0;
-    while index < lcs_result.len() && index_a < a.len() && index_b < b.len() {
-        let equal_a = lcs_result[index] == a[index_a];
-        let equal_b = lcs_result[index] == b[index_b];
-        if equal_a && equal_b {
-            diff.push(("=".to_owned(), lcs_result[index].to_string()));
-            index_a += 1;
-            index_b += 1;
-            index += 1;
-        } else if equal_a {
-            diff.push(("+".to_owned(), b[index_b].to_owned()));
-            index_b += 1;
-        } else if equal_b {
-            diff.push(("-".to_owned(), a[index_a].to_owned()));
-            index_a += 1;
-        } else {
-            diff.push(("+".to_owned(), b[index_b].to_owned()));
-            diff.push(("-".to_owned(), a[index_a].to_owned()));
-            index_a += 1;
-            index_b += 1;
+    let mut removed: Vec<(String, String)> = vec![];
+    for edit in edits {
+        match edit {
+            Edit::Equal(index_a) => {
+                diff.append(&mut removed);
+                diff.push(("=".to_owned(), a[index_a].to_owned()));
+            }
+            Edit::Insert(index_b) => diff.push(("+".to_owned(), b[index_b].to_owned())),
+            Edit::Delete(index_a) => removed.push(("-".to_owned(), a[index_a].to_owned())),
         }
     }
-    while index_a < a.len() && index_b < b.len() {
-        diff.push(("+".to_owned(), b[index_b].to_owned()));
-        diff.push(("-".to_owned(), a[index_a].to_owned()));
-        index_a += 1;
-        index_b += 1;
+    diff.append(&mut removed);
+    diff
+}
+
+// A single step of an edit script, holding the index of the line in its sequence
+enum Edit {
+    Equal(usize),
+    Insert(usize),
+    Delete(usize),
+}
+
+// Myers' linear space diff of a[a_lo..a_hi] and b[b_lo..b_hi]: strip the common
+// prefix and suffix, then split both ranges on the middle snake of the shortest
+// edit path and diff each half
+fn myers(a: &[usize], b: &[usize], mut a_lo: usize, mut a_hi: usize, mut b_lo: usize, mut b_hi: usize, edits: &mut Vec<Edit>) {
+    while a_lo < a_hi && b_lo < b_hi && a[a_lo] == b[b_lo] {
+        edits.push(Edit::Equal(a_lo));
+        a_lo += 1;
+        b_lo += 1;
     }
-    while index_b < b.len() {
-        diff.push(("+".to_owned(), b[index_b].to_owned()));
-        index_b += 1;
+    let mut suffix: usize = 0;
+    while a_lo < a_hi && b_lo < b_hi && a[a_hi - 1] == b[b_hi - 1] {
+        a_hi -= 1;
+        b_hi -= 1;
+        suffix += 1;
     }
-    while index_a < a.len() {
-        diff.push(("-".to_owned(), a[index_a].to_owned()));
-        index_a += 1;
+
+    if a_lo == a_hi {
+        edits.extend((b_lo..b_hi).map(Edit::Insert));
+    } else if b_lo == b_hi {
+        edits.extend((a_lo..a_hi).map(Edit::Delete));
+    } else {
+        let (x, y) = middle_snake(&a[a_lo..a_hi], &b[b_lo..b_hi]);
+        myers(a, b, a_lo, a_lo + x, b_lo, b_lo + y, edits);
+        myers(a, b, a_lo + x, a_hi, b_lo + y, b_hi, edits);
+    }
+
+    edits.extend((a_hi..a_hi + suffix).map(Edit::Equal));
+}
+
+// Walk the edit graph from both corners at once until the forward and backward
+// paths overlap, and return the point where they meet
+fn middle_snake(a: &[usize], b: &[usize]) -> (usize, usize) {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    // furthest x reached on each diagonal k = x - y, forward and backward
+    let mut forward = vec![0isize; 2 * offset as usize + 1];
+    let mut backward = vec![0isize; 2 * offset as usize + 1];
+
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let index = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && forward[index - 1] < forward[index + 1]) {
+                forward[index + 1]
+            } else {
+                forward[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[index] = x;
+            let c = delta - k;
+            if odd && c.abs() < d && x + backward[(c + offset) as usize] >= n {
+                return (x as usize, y as usize);
+            }
+        }
+        for c in (-d..=d).step_by(2) {
+            let index = (c + offset) as usize;
+            let mut x = if c == -d || (c != d && backward[index - 1] < backward[index + 1]) {
+                backward[index + 1]
+            } else {
+                backward[index - 1] + 1
+            };
+            let mut y = x - c;
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[index] = x;
+            let k = delta - c;
+            if !odd && k.abs() <= d && x + forward[(k + offset) as usize] >= n {
+                return ((n - x) as usize, (m - y) as usize);
+            }
+        }
+    }
+    // the paths always meet by d = max, this only guards against a bad input
+    (n as usize, m as usize)
+}
+
+// Split a text into its lines, each keeping the `\n` that ends it (and any `\r` before it),
+// so that a last line without a newline differs from the same line with one
+pub fn split_lines(content: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(content).split_inclusive('\n').map(|line| line.to_string()).collect()
+}
+
+// Group the differences returned by `diff` between lists of `split_lines` into unified diff
+// hunks, each starting with a `@@ -old_start,old_count +new_start,new_count @@` header and
+// keeping up to `context` unchanged lines around the changes (e.g. ["@@ -1,2 +1,2 @@", " same",
+// "-old", "+new"]). Lines are shown without their newline, and a last line without one is
+// followed by `\ No newline at end of file`
+pub fn unified_hunks(changes: &[(String, String)], context: usize) -> Vec<String> {
+    fn push_line(result: &mut Vec<String>, prefix: &str, line: &str) {
+        match line.strip_suffix('\n') {
+            Some(line) => result.push(format!("{}{}", prefix, line)),
+            None => {
+                result.push(format!("{}{}", prefix, line));
+                result.push(String::from("\\ No newline at end of file"));
+            }
+        }
+    }
+
+    // line numbers in the old and new file reached before each entry
+    let mut old_before: Vec<usize> = vec![0];
+    let mut new_before: Vec<usize> = vec![0];
+    for (tag, _) in changes {
+        old_before.push(old_before[old_before.len() - 1] + if tag == "+" { 0 } else { 1 });
+        new_before.push(new_before[new_before.len() - 1] + if tag == "-" { 0 } else { 1 });
+    }
+
+    // ranges of entries shown in each hunk, merged when their context overlaps
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for (index, (tag, _)) in changes.iter().enumerate() {
+        if tag == "=" {
+            continue;
+        }
+        let start = index.saturating_sub(context);
+        let end = (index + context + 1).min(changes.len());
+        match ranges.last_mut() {
+            Some(range) if start <= range.1 => range.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    let mut result: Vec<String> = vec![];
+    for (start, end) in ranges {
+        let old_count = old_before[end] - old_before[start];
+        let new_count = new_before[end] - new_before[start];
+        // an empty side starts at the line before the hunk, as in `diff -u`
+        let old_start = old_before[start] + if old_count > 0 { 1 } else { 0 };
+        let new_start = new_before[start] + if new_count > 0 { 1 } else { 0 };
+        result.push(format!("@@ -{},{} +{},{} @@", old_start, old_count, new_start, new_count));
+
+        // removed lines are listed before the added lines of the same change
+        let mut added: Vec<String> = vec![];
+        for (tag, line) in &changes[start..end] {
+            match tag.as_str() {
+                "+" => push_line(&mut added, "+", line),
+                "-" => push_line(&mut result, "-", line),
+                _ => {
+                    result.append(&mut added);
+                    push_line(&mut result, " ", line);
+                }
+            }
+        }
+        result.append(&mut added);
+    }
+    result
+}
+
+// A region of a three-way merge: lines that merged cleanly, or the lines of
+// both sides (and of their common ancestor) where they changed the same region
+#[derive(Debug, PartialEq)]
+pub enum MergeChunk {
+    Clean(Vec<String>),
+    Conflict { ours: Vec<String>, base: Vec<String>, theirs: Vec<String> },
+}
+
+// diff3 merge of two versions of a file against their common ancestor: regions
+// changed on one side only take that side, and regions changed differently on
+// both sides become conflicts
+pub fn merge3(base: &[String], ours: &[String], theirs: &[String]) -> Vec<MergeChunk> {
+    // index of the line matching each base line in the other version, if it was kept
+    fn matches(base: &[String], other: &[String]) -> Vec<Option<usize>> {
+        let mut result: Vec<Option<usize>> = vec![None; base.len()];
+        let (mut index_base, mut index_other) = (0, 0);
+        for (tag, _) in diff(base.to_vec(), other.to_vec()) {
+            match tag.as_str() {
+                "=" => {
+                    result[index_base] = Some(index_other);
+                    index_base += 1;
+                    index_other += 1;
+                }
+                "-" => index_base += 1,
+                _ => index_other += 1,
+            }
+        }
+        result
+    }
+    // add merged lines, extending the previous chunk when it is clean too
+    fn push_clean(result: &mut Vec<MergeChunk>, lines: &[String]) {
+        if lines.is_empty() {
+            return;
+        }
+        match result.last_mut() {
+            Some(MergeChunk::Clean(clean)) => clean.extend_from_slice(lines),
+            _ => result.push(MergeChunk::Clean(lines.to_vec())),
+        }
+    }
+    let match_ours = matches(base, ours);
+    let match_theirs = matches(base, theirs);
+
+    let mut result: Vec<MergeChunk> = vec![];
+    let (mut index_base, mut index_ours, mut index_theirs) = (0, 0, 0);
+    loop {
+        // lines kept unchanged by both sides
+        let stable_start = index_base;
+        while index_base < base.len()
+            && match_ours[index_base] == Some(index_ours)
+            && match_theirs[index_base] == Some(index_theirs)
+        {
+            index_base += 1;
+            index_ours += 1;
+            index_theirs += 1;
+        }
+        push_clean(&mut result, &base[stable_start..index_base]);
+
+        // the changed region runs up to the next base line both sides kept
+        let next = (index_base..base.len())
+            .find(|&index| match_ours[index].is_some() && match_theirs[index].is_some());
+        let (end_base, end_ours, end_theirs) = match next {
+            Some(index) => (index, match_ours[index].unwrap(), match_theirs[index].unwrap()),
+            None => (base.len(), ours.len(), theirs.len()),
+        };
+        let base_part = &base[index_base..end_base];
+        let ours_part = &ours[index_ours..end_ours];
+        let theirs_part = &theirs[index_theirs..end_theirs];
+        if ours_part == base_part || ours_part == theirs_part {
+            push_clean(&mut result, theirs_part);
+        } else if theirs_part == base_part {
+            push_clean(&mut result, ours_part);
+        } else {
+            result.push(MergeChunk::Conflict {
+                ours: ours_part.to_vec(),
+                base: base_part.to_vec(),
+                theirs: theirs_part.to_vec(),
+            });
+        }
+        index_base = end_base;
+        index_ours = end_ours;
+        index_theirs = end_theirs;
+
+        if next.is_none() {
+            return result;
+        }
     }
-    diff
 }
 
-pub fn hash(data: &str) -> String {
+pub fn hash<T: AsRef<[u8]>>(data: T) -> String {
     let mut hasher = Sha256::new();
     hasher.update(data);
     format!("{:X}", hasher.finalize())
 }
 
+// Format seconds since the Unix epoch as a UTC date (e.g. `2021-12-10 18:30:00 UTC`)
+pub fn format_timestamp(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(86400);
+    let seconds_of_day = timestamp.rem_euclid(86400);
+    // convert days since the epoch to a (year, month, day) civil date
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year, month, day,
+        seconds_of_day / 3600, seconds_of_day % 3600 / 60, seconds_of_day % 60
+    )
+}
+
+// Compress data with zlib
+pub fn compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+// Decompress zlib-compressed data. Data that is not a valid zlib stream is an InvalidData error
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut result = vec![];
+    ZlibDecoder::new(data)
+        .read_to_end(&mut result)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(result)
+}
+
+// Heuristic used to avoid line-based processing of binary files: a NUL byte
+// near the start of the data means the content is not text
+pub fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|byte| *byte == 0)
+}
+
 // True if equal
 pub fn compare_map<K: Eq + Hash, V: Eq>(m1: &HashMap<K, V>, m2: &HashMap<K, V>) -> bool {
     for (k, v) in m1 {
@@ -65,44 +349,10 @@ pub fn compare_map<K: Eq + Hash, V: Eq>(m1: &HashMap<K, V>, m2: &HashMap<K, V>)
     return true;
 }
 
-// Longest common subsequence between two vectors of strings
-fn lcs(a: &Vec<String>, b: &Vec<String>) -> Vec<String> {
-    let mut result: HashMap<String, Vec<String>> = HashMap::new();
-    for (i, str_a) in a.iter().enumerate() {
-        for (j, str_b) in b.iter().enumerate() {
-            let mut added: Vec<String> = vec![];
-            if str_a == str_b {
-                if i > 0 && j > 0 {
-                    let prev_res = result.get(&format!("{}#{}", i - 1, j - 1)).unwrap();
-                    for s in prev_res.iter() {
-                        added.push(s.to_string());
-                    }
-                }
-                added.push(str_a.to_string());
-            } else {
-                let len_i = if i > 0 { result.get(&format!("{}#{}", i - 1, j)).unwrap().len() } else { 0 };
-                let len_j = if j > 0 { result.get(&format!("{}#{}", i, j - 1)).unwrap().len() } else { 0 };
-                if len_i > len_j && len_i > 0 {
-                    let prev_res = result.get(&format!("{}#{}", i - 1, j)).unwrap();
-                    for s in prev_res.iter() {
-                        added.push(s.to_string());
-                    }
-                } else if len_j > 0 {
-                    let prev_res = result.get(&format!("{}#{}", i, j - 1)).unwrap();
-                    for s in prev_res.iter() {
-                        added.push(s.to_string());
-                    }
-                }
-            }
-            result.insert(format!("{}#{}", i, j), added);
-        }
-    }
-    result.get(&format!("{}#{}", a.len() - 1, b.len() - 1)).unwrap().to_vec()
-}
-
 #[cfg(test)]
 mod tests {
-    use crate::utilities::diff;
+    use crate::utilities::{compress, decompress, diff, format_timestamp, merge3, split_lines, unified_hunks, MergeChunk};
+    use std::collections::HashMap;
     #[test]
     fn test_1_diff() {
         //! Check that the right differences are returned from
@@ -116,4 +366,127 @@ mod tests {
         expected_result.push(("-".to_string(), "hello".to_string()));
         assert_eq!(expected_result, diff(a,b));
     }
+
+    #[test]
+    fn test_2_format_timestamp() {
+        //! Check that timestamps are formatted as UTC calendar dates
+        assert_eq!("1970-01-01 00:00:00 UTC", format_timestamp(0));
+        assert_eq!("2000-02-29 12:34:56 UTC", format_timestamp(951827696));
+        assert_eq!("2021-12-31 23:59:59 UTC", format_timestamp(1640995199));
+    }
+
+    #[test]
+    fn test_3_compress() {
+        //! Check that compressed data round-trips byte for byte
+        let data = b"blob\nline one\n\x00\xff binary and no trailing newline".to_vec();
+        let compressed = compress(&data).unwrap();
+        assert_ne!(data, compressed);
+        assert_eq!(data, decompress(&compressed).unwrap());
+    }
+
+    #[test]
+    fn test_4_diff_empty() {
+        //! Check that diffing against an empty file adds or removes every line
+        let lines: Vec<String> = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(Vec::<(String, String)>::new(), diff(vec![], vec![]));
+        assert_eq!(
+            vec![("+".to_string(), "a".to_string()), ("+".to_string(), "b".to_string())],
+            diff(vec![], lines.clone())
+        );
+        assert_eq!(
+            vec![("-".to_string(), "a".to_string()), ("-".to_string(), "b".to_string())],
+            diff(lines, vec![])
+        );
+    }
+
+    #[test]
+    fn test_5_diff_minimal() {
+        //! Check that the differences rebuild both files and keep a longest common subsequence
+        let mut seed: u64 = 42;
+        let mut random_lines = |count: usize| -> Vec<String> {
+            (0..count).map(|_| {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                ((seed >> 33) % 4).to_string()
+            }).collect()
+        };
+        for round in 0..50 {
+            let a = random_lines(round % 13);
+            let b = random_lines(round % 17);
+            let result = diff(a.clone(), b.clone());
+            let old: Vec<String> = result.iter().filter(|(tag, _)| tag != "+").map(|(_, line)| line.clone()).collect();
+            let new: Vec<String> = result.iter().filter(|(tag, _)| tag != "-").map(|(_, line)| line.clone()).collect();
+            assert_eq!(a, old);
+            assert_eq!(b, new);
+
+            let mut lengths: HashMap<(usize, usize), usize> = HashMap::new();
+            for i in 1..=a.len() {
+                for j in 1..=b.len() {
+                    let length = if a[i - 1] == b[j - 1] {
+                        lengths.get(&(i - 1, j - 1)).unwrap_or(&0) + 1
+                    } else {
+                        *lengths.get(&(i - 1, j)).unwrap_or(&0).max(lengths.get(&(i, j - 1)).unwrap_or(&0))
+                    };
+                    lengths.insert((i, j), length);
+                }
+            }
+            let common = result.iter().filter(|(tag, _)| tag == "=").count();
+            assert_eq!(*lengths.get(&(a.len(), b.len())).unwrap_or(&0), common);
+        }
+    }
+
+    #[test]
+    fn test_6_unified_hunks() {
+        //! Check that far apart changes get separate hunks with the right line numbers
+        let a: Vec<String> = (1..=12).map(|i| format!("{}\n", i)).collect();
+        let mut b = a.clone();
+        b[1] = "two\n".to_string();
+        b.remove(10);
+        b.push("13\n".to_string());
+        let expected_result: Vec<String> = [
+            "@@ -1,3 +1,3 @@", " 1", "-2", "+two", " 3",
+            "@@ -10,3 +10,3 @@", " 10", "-11", " 12", "+13",
+        ].iter().map(|s| s.to_string()).collect();
+        assert_eq!(expected_result, unified_hunks(&diff(a.clone(), b), 1));
+        assert_eq!(Vec::<String>::new(), unified_hunks(&diff(a.clone(), a.clone()), 3));
+
+        let added: Vec<(String, String)> = vec![("+".to_string(), "new\n".to_string())];
+        assert_eq!(vec!["@@ -0,0 +1,1 @@".to_string(), "+new".to_string()], unified_hunks(&added, 3));
+    }
+
+    #[test]
+    fn test_7_merge3() {
+        //! Check that one-sided changes merge cleanly and overlapping changes conflict
+        let lines = |text: &str| -> Vec<String> { text.split(' ').map(|s| s.to_string()).collect() };
+        let base = lines("a b c d e f g");
+        let ours = lines("a B c d e f g");
+        let theirs = lines("a b c d e F g h");
+        assert_eq!(vec![MergeChunk::Clean(lines("a B c d e F g h"))], merge3(&base, &ours, &theirs));
+
+        let theirs = lines("a X c d e f g");
+        assert_eq!(
+            vec![
+                MergeChunk::Clean(lines("a")),
+                MergeChunk::Conflict { ours: lines("B"), base: lines("b"), theirs: lines("X") },
+                MergeChunk::Clean(lines("c d e f g")),
+            ],
+            merge3(&base, &ours, &theirs)
+        );
+        assert_eq!(vec![MergeChunk::Clean(lines("a B c d e f g"))], merge3(&base, &ours, &ours));
+    }
+    #[test]
+    fn test_8_unified_hunks_line_endings() {
+        //! Check that carriage returns are kept, and that a last line without a newline is noted
+        let a = split_lines(b"one\r\ntwo\nthree");
+        assert_eq!(vec!["one\r\n", "two\n", "three"], a);
+        let b = split_lines(b"one\r\ntwo\nthree\n");
+        let expected_result: Vec<String> = [
+            "@@ -2,2 +2,2 @@", " two", "-three", "\\ No newline at end of file", "+three",
+        ].iter().map(|s| s.to_string()).collect();
+        assert_eq!(expected_result, unified_hunks(&diff(a.clone(), b.clone()), 1));
+        let expected_result: Vec<String> = [
+            "@@ -1,3 +1,3 @@", "-one\r", "+one", " two", " three", "\\ No newline at end of file",
+        ].iter().map(|s| s.to_string()).collect();
+        assert_eq!(expected_result, unified_hunks(&diff(a, split_lines(b"one\ntwo\nthree")), 3));
+        assert!(split_lines(b"").is_empty());
+    }
 }